@@ -0,0 +1,168 @@
+// src/command_table.rs
+// Metadata for every implemented command, as reported by COMMAND. Add an entry here along
+// with the dispatch arms in parsing.rs whenever a command is added.
+
+pub struct CommandSpec {
+    pub name: &'static str, // Lowercase, as COMMAND reports it
+    pub arity: i32, // Argument count including the name; negative means at least that many
+    pub flags: &'static [&'static str],
+    pub first_key: i32, // Position of the first key argument, 0 if there are none
+    pub last_key: i32, // Position of the last key argument; -1 means the last argument
+    pub step: i32, // Distance between key arguments
+    pub group: &'static str, // Command group as in the Redis docs
+}
+
+const fn spec(
+    name: &'static str,
+    arity: i32,
+    flags: &'static [&'static str],
+    (first_key, last_key, step): (i32, i32, i32),
+    group: &'static str,
+) -> CommandSpec {
+    CommandSpec { name, arity, flags, first_key, last_key, step, group }
+}
+
+// Key positions for commands without keys, with a single key first, and with every argument a key
+const NO_KEYS: (i32, i32, i32) = (0, 0, 0);
+const ONE_KEY: (i32, i32, i32) = (1, 1, 1);
+const ALL_KEYS: (i32, i32, i32) = (1, -1, 1);
+
+pub const COMMAND_TABLE: &[CommandSpec] = &[
+    // Strings
+    spec("append", 3, &["write", "denyoom", "fast"], ONE_KEY, "string"),
+    spec("decr", 2, &["write", "denyoom", "fast"], ONE_KEY, "string"),
+    spec("decrby", 3, &["write", "denyoom", "fast"], ONE_KEY, "string"),
+    spec("get", 2, &["readonly", "fast"], ONE_KEY, "string"),
+    spec("getdel", 2, &["write", "fast"], ONE_KEY, "string"),
+    spec("getrange", 4, &["readonly"], ONE_KEY, "string"),
+    spec("incr", 2, &["write", "denyoom", "fast"], ONE_KEY, "string"),
+    spec("incrby", 3, &["write", "denyoom", "fast"], ONE_KEY, "string"),
+    spec("incrbyfloat", 3, &["write", "denyoom", "fast"], ONE_KEY, "string"),
+    spec("mget", -2, &["readonly", "fast"], ALL_KEYS, "string"),
+    spec("mset", -3, &["write", "denyoom"], (1, -1, 2), "string"),
+    spec("psetex", 4, &["write", "denyoom"], ONE_KEY, "string"),
+    spec("set", -3, &["write", "denyoom"], ONE_KEY, "string"),
+    spec("setex", 4, &["write", "denyoom"], ONE_KEY, "string"),
+    spec("setnx", 3, &["write", "denyoom", "fast"], ONE_KEY, "string"),
+    spec("setrange", 4, &["write", "denyoom"], ONE_KEY, "string"),
+    spec("strlen", 2, &["readonly", "fast"], ONE_KEY, "string"),
+    // Keys
+    spec("copy", -3, &["write", "denyoom"], (1, 2, 1), "generic"),
+    spec("del", -2, &["write"], ALL_KEYS, "generic"),
+    spec("exists", -2, &["readonly", "fast"], ALL_KEYS, "generic"),
+    spec("expire", -3, &["write", "fast"], ONE_KEY, "generic"),
+    spec("keys", 2, &["readonly"], NO_KEYS, "generic"),
+    spec("move", 3, &["write", "fast"], ONE_KEY, "generic"),
+    spec("object", -2, &["readonly"], (2, 2, 1), "generic"),
+    spec("persist", 2, &["write", "fast"], ONE_KEY, "generic"),
+    spec("pexpire", -3, &["write", "fast"], ONE_KEY, "generic"),
+    spec("pttl", 2, &["readonly", "fast"], ONE_KEY, "generic"),
+    spec("randomkey", 1, &["readonly"], NO_KEYS, "generic"),
+    spec("rename", 3, &["write"], (1, 2, 1), "generic"),
+    spec("renamenx", 3, &["write", "fast"], (1, 2, 1), "generic"),
+    spec("scan", -2, &["readonly"], NO_KEYS, "generic"),
+    spec("ttl", 2, &["readonly", "fast"], ONE_KEY, "generic"),
+    spec("type", 2, &["readonly", "fast"], ONE_KEY, "generic"),
+    // Lists
+    spec("lindex", 3, &["readonly"], ONE_KEY, "list"),
+    spec("llen", 2, &["readonly", "fast"], ONE_KEY, "list"),
+    spec("lpop", -2, &["write", "fast"], ONE_KEY, "list"),
+    spec("lpush", -3, &["write", "denyoom", "fast"], ONE_KEY, "list"),
+    spec("lpushx", -3, &["write", "denyoom", "fast"], ONE_KEY, "list"),
+    spec("lrange", 4, &["readonly"], ONE_KEY, "list"),
+    spec("lset", 4, &["write", "denyoom"], ONE_KEY, "list"),
+    spec("rpop", -2, &["write", "fast"], ONE_KEY, "list"),
+    spec("rpush", -3, &["write", "denyoom", "fast"], ONE_KEY, "list"),
+    spec("rpushx", -3, &["write", "denyoom", "fast"], ONE_KEY, "list"),
+    // Hashes
+    spec("hdel", -3, &["write", "fast"], ONE_KEY, "hash"),
+    spec("hexists", 3, &["readonly", "fast"], ONE_KEY, "hash"),
+    spec("hget", 3, &["readonly", "fast"], ONE_KEY, "hash"),
+    spec("hgetall", 2, &["readonly"], ONE_KEY, "hash"),
+    spec("hincrby", 4, &["write", "denyoom", "fast"], ONE_KEY, "hash"),
+    spec("hkeys", 2, &["readonly"], ONE_KEY, "hash"),
+    spec("hlen", 2, &["readonly", "fast"], ONE_KEY, "hash"),
+    spec("hset", -4, &["write", "denyoom", "fast"], ONE_KEY, "hash"),
+    spec("hvals", 2, &["readonly"], ONE_KEY, "hash"),
+    // Sets
+    spec("sadd", -3, &["write", "denyoom", "fast"], ONE_KEY, "set"),
+    spec("scard", 2, &["readonly", "fast"], ONE_KEY, "set"),
+    spec("sdiff", -2, &["readonly"], ALL_KEYS, "set"),
+    spec("sinter", -2, &["readonly"], ALL_KEYS, "set"),
+    spec("sismember", 3, &["readonly", "fast"], ONE_KEY, "set"),
+    spec("smembers", 2, &["readonly"], ONE_KEY, "set"),
+    spec("srem", -3, &["write", "fast"], ONE_KEY, "set"),
+    spec("sunion", -2, &["readonly"], ALL_KEYS, "set"),
+    // Sorted sets
+    spec("zadd", -4, &["write", "denyoom", "fast"], ONE_KEY, "sorted-set"),
+    spec("zrange", -4, &["readonly"], ONE_KEY, "sorted-set"),
+    spec("zrangebyscore", -4, &["readonly"], ONE_KEY, "sorted-set"),
+    spec("zscore", 3, &["readonly", "fast"], ONE_KEY, "sorted-set"),
+    // Streams
+    spec("xadd", -5, &["write", "denyoom", "fast"], ONE_KEY, "stream"),
+    spec("xdel", -3, &["write", "fast"], ONE_KEY, "stream"),
+    spec("xinfo", -2, &["readonly"], (2, 2, 1), "stream"),
+    spec("xlen", 2, &["readonly", "fast"], ONE_KEY, "stream"),
+    spec("xrange", -4, &["readonly"], ONE_KEY, "stream"),
+    spec("xread", -4, &["readonly", "blocking", "movablekeys"], NO_KEYS, "stream"),
+    spec("xrevrange", -4, &["readonly"], ONE_KEY, "stream"),
+    spec("xtrim", -4, &["write"], ONE_KEY, "stream"),
+    // Pub/sub
+    spec("psubscribe", -2, &["pubsub", "noscript", "loading", "stale"], NO_KEYS, "pubsub"),
+    spec("publish", 3, &["pubsub", "loading", "stale", "fast"], NO_KEYS, "pubsub"),
+    spec("punsubscribe", -1, &["pubsub", "noscript", "loading", "stale"], NO_KEYS, "pubsub"),
+    spec("subscribe", -2, &["pubsub", "noscript", "loading", "stale"], NO_KEYS, "pubsub"),
+    spec("unsubscribe", -1, &["pubsub", "noscript", "loading", "stale"], NO_KEYS, "pubsub"),
+    // Transactions
+    spec("discard", 1, &["noscript", "loading", "stale", "fast"], NO_KEYS, "transactions"),
+    spec("exec", 1, &["noscript", "loading", "stale"], NO_KEYS, "transactions"),
+    spec("multi", 1, &["noscript", "loading", "stale", "fast"], NO_KEYS, "transactions"),
+    spec("unwatch", 1, &["noscript", "loading", "stale", "fast"], NO_KEYS, "transactions"),
+    spec("watch", -2, &["noscript", "loading", "stale", "fast"], ALL_KEYS, "transactions"),
+    // Connection
+    spec("auth", -2, &["noscript", "loading", "stale", "fast", "no-auth"], NO_KEYS, "connection"),
+    spec("client", -2, &["admin", "noscript", "loading", "stale"], NO_KEYS, "connection"),
+    spec("echo", 2, &["fast"], NO_KEYS, "connection"),
+    spec("hello", -1, &["noscript", "loading", "stale", "fast", "no-auth"], NO_KEYS, "connection"),
+    spec("ping", -1, &["fast", "no-auth"], NO_KEYS, "connection"),
+    spec("reset", 1, &["noscript", "loading", "stale", "fast"], NO_KEYS, "connection"),
+    spec("select", 2, &["loading", "stale", "fast"], NO_KEYS, "connection"),
+    // Server
+    spec("acl", -2, &["noscript", "loading", "stale"], NO_KEYS, "server"),
+    spec("bgsave", -1, &["admin", "noscript"], NO_KEYS, "server"),
+    spec("command", -1, &["loading", "stale"], NO_KEYS, "server"),
+    spec("config", -2, &["admin", "noscript", "loading", "stale"], NO_KEYS, "server"),
+    spec("dbsize", 1, &["readonly", "fast"], NO_KEYS, "server"),
+    spec("debug", -2, &["admin", "noscript", "loading", "stale"], NO_KEYS, "server"),
+    spec("flushall", -1, &["write"], NO_KEYS, "server"),
+    spec("flushdb", -1, &["write"], NO_KEYS, "server"),
+    spec("info", -1, &["loading", "stale"], NO_KEYS, "server"),
+    spec("psync", -3, &["admin", "noscript"], NO_KEYS, "server"),
+    spec("replconf", -1, &["admin", "noscript", "loading", "stale"], NO_KEYS, "server"),
+    spec("save", 1, &["admin", "noscript"], NO_KEYS, "server"),
+    spec("shutdown", -1, &["admin", "noscript", "loading", "stale"], NO_KEYS, "server"),
+    spec("swapdb", 3, &["write", "fast"], NO_KEYS, "server"),
+    spec("wait", 3, &["noscript"], NO_KEYS, "generic"),
+];
+
+// Look up a command by name, case-insensitively
+pub fn command_spec(name: &str) -> Option<&'static CommandSpec> {
+    COMMAND_TABLE.iter().find(|spec| spec.name.eq_ignore_ascii_case(name))
+}
+
+// Whether the command has the given flag, e.g. "write"
+pub fn has_flag(name: &str, flag: &str) -> bool {
+    command_spec(name).is_some_and(|spec| spec.flags.contains(&flag))
+}
+
+// The key arguments of a command, found from its key positions; `args` excludes the name
+pub fn command_keys<'a>(name: &str, args: &'a [String]) -> Vec<&'a str> {
+    let Some(spec) = command_spec(name).filter(|spec| spec.first_key > 0) else {
+        return Vec::new();
+    };
+    let last_key = if spec.last_key < 0 { args.len() as i32 + 1 + spec.last_key } else { spec.last_key };
+    (spec.first_key..=last_key)
+        .step_by(spec.step as usize)
+        .filter_map(|position| args.get(position as usize - 1).map(String::as_str))
+        .collect()
+}
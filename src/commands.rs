@@ -1,122 +1,1475 @@
-use crate::database::{RedisDatabase, RedisValue, RedisValueType, ReplicationInfoValue, StreamID};
-use crate::network::ClientState;
+use crate::command_table::{command_spec, CommandSpec, COMMAND_TABLE};
+use crate::database::{PubSubMessage, RedisDatabase, RedisValue, RedisValueType, ReplicationInfoValue, SortedSet, Stream, StreamID, DATABASE_COUNT, selected_db};
+use crate::network::{ClientState, SharedConfig};
 use crate::parsing::parse_redis_message;
-use std::collections::{BTreeMap, HashMap};
+use crate::rdb_writer::{rdb_checksum_enabled, rdb_file_path, snapshot, write_rdb_file};
+use crate::utils::{glob_match, random_u64, sha256_hex};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use tokio::net::tcp::OwnedWriteHalf;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, Notify, RwLock, RwLockReadGuard};
+use std::future::{poll_fn, Future};
+use std::ops::Bound;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::task::Poll;
 use tokio::io::{self, AsyncWriteExt};
-use tokio::net::TcpStream;
 use tokio::time::{timeout, Duration};
 
+// Largest string SETRANGE may produce, matching Redis' default proto-max-bulk-len
+const MAX_STRING_LENGTH: usize = 512 * 1024 * 1024;
+
+const WRONGTYPE_ERROR: &str = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+
+// Take the shared lock for a read-only command. Its keys that have expired are removed first,
+// which takes the write lock only when one of them actually has.
+async fn read_live_keys<'a>(db: &'a Arc<RwLock<RedisDatabase>>, keys: &[impl AsRef<str>]) -> RwLockReadGuard<'a, RedisDatabase> {
+    {
+        let db_lock = db.read().await;
+        if !keys.iter().any(|key| db_lock.data().get(key.as_ref()).is_some_and(RedisValue::is_expired)) {
+            return db_lock;
+        }
+    }
+    let mut db_lock = db.write().await;
+    for key in keys {
+        db_lock.remove_if_expired(key.as_ref());
+    }
+    db_lock.downgrade()
+}
+
+// The value looked up at a key, or the WRONGTYPE error if it holds something other than the
+// `expected` type (as named by TYPE). A missing key is fine and gives Ok(None).
+fn expect_type<'a>(redis_value: Option<&'a RedisValue>, expected: &str) -> Result<Option<&'a RedisValueType>, String> {
+    match redis_value.map(RedisValue::get_value) {
+        Some(value) if value.type_name() != expected => Err(WRONGTYPE_ERROR.to_string()),
+        value => Ok(value),
+    }
+}
+
+// Server version reported by HELLO
+const SERVER_VERSION: &str = "7.2.0";
+
+tokio::task_local! {
+    // The RESP version negotiated with HELLO by the connection running the command
+    pub static RESP_VERSION: u8;
+    // Set while EXEC runs a transaction, which holds the write turn so mustn't wait on other
+    // clients; blocking commands answer at once as if their timeout had expired
+    pub static DENY_BLOCKING: bool;
+}
+
+fn current_resp_version() -> u8 {
+    RESP_VERSION.try_with(|version| *version).unwrap_or(2)
+}
+
+fn blocking_allowed() -> bool {
+    !DENY_BLOCKING.try_with(|deny| *deny).unwrap_or(false)
+}
+
+// Header for a reply of `pairs` key/value pairs: a RESP3 map, or a flat array under RESP2
+fn map_header(pairs: usize) -> String {
+    resp_map_header(current_resp_version(), pairs)
+}
+
+fn resp_map_header(version: u8, pairs: usize) -> String {
+    if version >= 3 {
+        format!("%{}\r\n", pairs)
+    } else {
+        format!("*{}\r\n", pairs * 2)
+    }
+}
+
+// Header for an out-of-band pub/sub frame: a RESP3 push, or an array under RESP2
+fn resp_push_header(version: u8, len: usize) -> String {
+    if version >= 3 {
+        format!(">{}\r\n", len)
+    } else {
+        format!("*{}\r\n", len)
+    }
+}
+
+// Encode a RESP bulk string
+fn bulk_string(value: &str) -> String {
+    format!("${}\r\n{}\r\n", value.len(), value)
+}
+
+// Encode a RESP array of bulk strings
+fn bulk_string_array<'a>(elements: impl ExactSizeIterator<Item = &'a String>) -> String {
+    let mut response = format!("*{}\r\n", elements.len());
+    for element in elements {
+        response.push_str(&bulk_string(element));
+    }
+    response
+}
+
+// Encode a RESP bulk string from raw bytes without assuming they are valid UTF-8
+fn bulk_string_bytes(value: &[u8]) -> Vec<u8> {
+    let mut reply = format!("${}\r\n", value.len()).into_bytes();
+    reply.extend_from_slice(value);
+    reply.extend_from_slice(b"\r\n");
+    reply
+}
+
+// Resolve Redis-style inclusive start/end indexes (negative counts from the end)
+// against a sequence of `len` items. Returns None when the range is empty.
+fn normalize_range(start: i64, end: i64, len: usize) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 { (start + len).max(0) } else { start };
+    let end = if end < 0 { end + len } else { end.min(len - 1) };
+    if len == 0 || start > end || start >= len {
+        None
+    } else {
+        Some((start as usize, end as usize))
+    }
+}
+
+// Decode a raw argument used as a key or option name
+fn arg_to_string(arg: &[u8]) -> String {
+    String::from_utf8_lossy(arg).into_owned()
+}
+
+// Options accepted by SET after the key and value
+#[derive(Default)]
+struct SetOptions {
+    ttl_millis: Option<u64>,
+    keep_ttl: bool,        // KEEPTTL
+    only_if_absent: bool,  // NX
+    only_if_present: bool, // XX
+    get: bool,             // GET
+}
+
+impl SetOptions {
+    // Parse the trailing SET options in any order, returning a RESP error on bad input
+    fn parse(options: &[String]) -> Result<Self, String> {
+        let mut parsed = SetOptions::default();
+        let mut i = 0;
+        while i < options.len() {
+            match options[i].to_uppercase().as_str() {
+                "NX" => parsed.only_if_absent = true,
+                "XX" => parsed.only_if_present = true,
+                "KEEPTTL" => parsed.keep_ttl = true,
+                "GET" => parsed.get = true,
+                option @ ("EX" | "PX") => {
+                    // EX and PX are mutually exclusive
+                    if parsed.ttl_millis.is_some() {
+                        return Err("-ERR syntax error\r\n".to_string());
+                    }
+                    let ttl = options.get(i + 1).ok_or_else(|| "-ERR syntax error\r\n".to_string())?;
+                    let ttl = ttl
+                        .parse::<i64>()
+                        .map_err(|_| "-ERR value is not an integer or out of range\r\n".to_string())?;
+                    let unit_millis = if option == "EX" { 1000 } else { 1 };
+                    match ttl.checked_mul(unit_millis) {
+                        Some(ttl_millis) if ttl_millis > 0 => parsed.ttl_millis = Some(ttl_millis as u64),
+                        _ => return Err("-ERR invalid expire time in 'set' command\r\n".to_string()),
+                    }
+                    i += 1;
+                }
+                _ => return Err("-ERR syntax error\r\n".to_string()),
+            }
+            i += 1;
+        }
+
+        if (parsed.only_if_absent && parsed.only_if_present) || (parsed.keep_ttl && parsed.ttl_millis.is_some()) {
+            return Err("-ERR syntax error\r\n".to_string());
+        }
+        Ok(parsed)
+    }
+}
+
 // Handle the SET command
-pub async fn handle_set(db: &Arc<Mutex<RedisDatabase>>, args: &[String]) -> String {
-    let mut db = db.lock().await;
-    if args.len() == 2 {
-        db.insert(args[0].clone(), RedisValue::new(args[1].clone(), None));
-        "+OK\r\n".to_string()
-    } else if args.len() == 4 && args[2].to_uppercase() == "PX" {
-        let ttl = args[3].parse::<u64>().unwrap();
-        db.insert(args[0].clone(), RedisValue::new(args[1].clone(), Some(ttl)));
-        "+OK\r\n".to_string()
+pub async fn handle_set(db: &Arc<RwLock<RedisDatabase>>, args: &[Vec<u8>]) -> Vec<u8> {
+    if args.len() < 2 {
+        return b"-ERR wrong number of arguments for 'set' command\r\n".to_vec();
+    }
+    let key = arg_to_string(&args[0]);
+    let options: Vec<String> = args[2..].iter().map(|option| arg_to_string(option)).collect();
+    let options = match SetOptions::parse(&options) {
+        Ok(options) => options,
+        Err(error) => return error.into_bytes(),
+    };
+
+    let mut db = db.write().await;
+    db.remove_if_expired(&key);
+    let existing = db.get(&key);
+    let exists = existing.is_some();
+
+    // With GET the reply is the previous value, which only makes sense for strings
+    let reply = if options.get {
+        match existing.map(|redis_value| redis_value.get_value().string_bytes()) {
+            Some(Some(bytes)) => bulk_string_bytes(&bytes),
+            Some(None) => return WRONGTYPE_ERROR.into(),
+            None => b"$-1\r\n".to_vec(),
+        }
     } else {
-        "-ERR wrong number of arguments for 'set' command\r\n".to_string()
+        b"+OK\r\n".to_vec()
+    };
+
+    if (options.only_if_absent && exists) || (options.only_if_present && !exists) {
+        return if options.get { reply } else { b"$-1\r\n".to_vec() };
+    }
+
+    // KEEPTTL carries the remaining lifetime of the value being overwritten
+    let ttl_millis = if options.keep_ttl {
+        existing.and_then(|redis_value| redis_value.remaining_ttl()).map(|ttl| ttl.as_millis() as u64)
+    } else {
+        options.ttl_millis
+    };
+    db.insert(key.clone(), RedisValue::new(args[1].clone(), ttl_millis));
+    db.notify_keyspace_event('$', "set", &key);
+    reply
+}
+
+// Handle the SETNX command
+pub async fn handle_setnx(db: &Arc<RwLock<RedisDatabase>>, args: &[Vec<u8>]) -> String {
+    if args.len() != 2 {
+        return "-ERR wrong number of arguments for 'setnx' command\r\n".to_string();
+    }
+
+    let key = arg_to_string(&args[0]);
+    let mut db = db.write().await;
+    db.remove_if_expired(&key);
+    if db.get(&key).is_some() {
+        return ":0\r\n".to_string();
+    }
+    db.insert(key.clone(), RedisValue::new(args[1].clone(), None));
+    db.notify_keyspace_event('$', "set", &key);
+    ":1\r\n".to_string()
+}
+
+// Handle the SETEX command
+pub async fn handle_setex(db: &Arc<RwLock<RedisDatabase>>, args: &[Vec<u8>]) -> String {
+    set_with_ttl(db, args, 1000, "setex").await
+}
+
+// Handle the PSETEX command
+pub async fn handle_psetex(db: &Arc<RwLock<RedisDatabase>>, args: &[Vec<u8>]) -> String {
+    set_with_ttl(db, args, 1, "psetex").await
+}
+
+// Shared by SETEX and PSETEX: `key ttl value` with the TTL in units of `unit_millis`
+async fn set_with_ttl(db: &Arc<RwLock<RedisDatabase>>, args: &[Vec<u8>], unit_millis: i64, command: &str) -> String {
+    if args.len() != 3 {
+        return format!("-ERR wrong number of arguments for '{}' command\r\n", command);
+    }
+    let ttl_millis = match arg_to_string(&args[1]).parse::<i64>() {
+        Ok(ttl) => match ttl.checked_mul(unit_millis) {
+            Some(ttl_millis) if ttl_millis > 0 => ttl_millis as u64,
+            _ => return format!("-ERR invalid expire time in '{}' command\r\n", command),
+        },
+        Err(_) => return "-ERR value is not an integer or out of range\r\n".to_string(),
+    };
+
+    let key = arg_to_string(&args[0]);
+    let mut db = db.write().await;
+    db.insert(key.clone(), RedisValue::new(args[2].clone(), Some(ttl_millis)));
+    db.notify_keyspace_event('$', "set", &key);
+    "+OK\r\n".to_string()
+}
+
+// Handle the GET command
+pub async fn handle_get(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> Vec<u8> {
+    let db = read_live_keys(db, &args[..1]).await;
+    match db.get(&args[0]).map(|redis_value| redis_value.get_value().string_bytes()) {
+        Some(Some(bytes)) => bulk_string_bytes(&bytes),
+        Some(None) => WRONGTYPE_ERROR.into(),
+        None => b"$-1\r\n".to_vec(),
+    }
+}
+
+// Handle the GETDEL command
+pub async fn handle_getdel(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> Vec<u8> {
+    if args.len() != 1 {
+        return b"-ERR wrong number of arguments for 'getdel' command\r\n".to_vec();
+    }
+
+    let mut db = db.write().await;
+    db.remove_if_expired(&args[0]);
+    match db.get(&args[0]).map(|redis_value| redis_value.get_value().string_bytes()) {
+        Some(Some(bytes)) => {
+            db.remove(&args[0]);
+            db.notify_keyspace_event('g', "del", &args[0]);
+            bulk_string_bytes(&bytes)
+        }
+        Some(None) => WRONGTYPE_ERROR.into(),
+        None => b"$-1\r\n".to_vec(),
+    }
+}
+
+// Handle the MSET command
+pub async fn handle_mset(db: &Arc<RwLock<RedisDatabase>>, args: &[Vec<u8>]) -> String {
+    if args.is_empty() || !args.len().is_multiple_of(2) {
+        return "-ERR wrong number of arguments for 'mset' command\r\n".to_string();
+    }
+
+    // All pairs are written under a single lock so the update is atomic
+    let mut db = db.write().await;
+    for pair in args.chunks(2) {
+        let key = arg_to_string(&pair[0]);
+        db.insert(key.clone(), RedisValue::new(pair[1].clone(), None));
+        db.notify_keyspace_event('$', "set", &key);
+    }
+    "+OK\r\n".to_string()
+}
+
+// Handle the MGET command
+pub async fn handle_mget(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> Vec<u8> {
+    if args.is_empty() {
+        return b"-ERR wrong number of arguments for 'mget' command\r\n".to_vec();
+    }
+
+    let db = read_live_keys(db, args).await;
+    let mut response = format!("*{}\r\n", args.len()).into_bytes();
+    for key in args {
+        // Non-string values are reported as nil rather than as an error
+        match db.get(key).and_then(|redis_value| redis_value.get_value().string_bytes()) {
+            Some(bytes) => response.extend_from_slice(&bulk_string_bytes(&bytes)),
+            None => response.extend_from_slice(b"$-1\r\n"),
+        }
+    }
+    response
+}
+
+// Handle the APPEND command
+pub async fn handle_append(db: &Arc<RwLock<RedisDatabase>>, args: &[Vec<u8>]) -> String {
+    if args.len() != 2 {
+        return "-ERR wrong number of arguments for 'append' command\r\n".to_string();
+    }
+
+    let key = arg_to_string(&args[0]);
+    let mut db = db.write().await;
+    db.remove_if_expired(&key);
+    let length = match db.get_mut(&key) {
+        Some(redis_value) => match redis_value.get_mut_value().string_bytes_mut() {
+            Some(bytes) => {
+                bytes.extend_from_slice(&args[1]);
+                bytes.len()
+            }
+            None => return WRONGTYPE_ERROR.to_string(),
+        },
+        None => {
+            // Appending to a missing key behaves like SET
+            db.insert(key.clone(), RedisValue::new(args[1].clone(), None));
+            args[1].len()
+        }
+    };
+    db.notify_keyspace_event('$', "append", &key);
+    format!(":{}\r\n", length)
+}
+
+// Handle the STRLEN command
+pub async fn handle_strlen(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() != 1 {
+        return "-ERR wrong number of arguments for 'strlen' command\r\n".to_string();
+    }
+
+    let db = read_live_keys(db, args).await;
+    match db.get(&args[0]) {
+        Some(redis_value) => match redis_value.get_value().string_len() {
+            Some(len) => format!(":{}\r\n", len),
+            None => WRONGTYPE_ERROR.to_string(),
+        },
+        None => ":0\r\n".to_string(),
+    }
+}
+
+// Handle the GETRANGE command
+pub async fn handle_getrange(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> Vec<u8> {
+    if args.len() != 3 {
+        return b"-ERR wrong number of arguments for 'getrange' command\r\n".to_vec();
+    }
+    let (start, end) = match (args[1].parse::<i64>(), args[2].parse::<i64>()) {
+        (Ok(start), Ok(end)) => (start, end),
+        _ => return b"-ERR value is not an integer or out of range\r\n".to_vec(),
+    };
+
+    let db = read_live_keys(db, &args[..1]).await;
+    let bytes = match db.get(&args[0]).map(|redis_value| redis_value.get_value().string_bytes()) {
+        Some(Some(bytes)) => bytes,
+        Some(None) => return WRONGTYPE_ERROR.into(),
+        None => Vec::new(),
+    };
+
+    match normalize_range(start, end, bytes.len()) {
+        Some((start, end)) => bulk_string_bytes(&bytes[start..=end]),
+        None => b"$0\r\n\r\n".to_vec(),
+    }
+}
+
+// Handle the SETRANGE command
+pub async fn handle_setrange(db: &Arc<RwLock<RedisDatabase>>, args: &[Vec<u8>]) -> String {
+    if args.len() != 3 {
+        return "-ERR wrong number of arguments for 'setrange' command\r\n".to_string();
+    }
+    let offset = match arg_to_string(&args[1]).parse::<i64>() {
+        Ok(offset) if offset < 0 => return "-ERR offset is out of range\r\n".to_string(),
+        Ok(offset) => offset as usize,
+        Err(_) => return "-ERR value is not an integer or out of range\r\n".to_string(),
+    };
+    let value = &args[2];
+    if offset + value.len() > MAX_STRING_LENGTH {
+        return "-ERR string exceeds maximum allowed size (proto-max-bulk-len)\r\n".to_string();
+    }
+
+    let key = arg_to_string(&args[0]);
+    let mut db = db.write().await;
+    db.remove_if_expired(&key);
+    if db.get(&key).is_none() {
+        // An empty write to a missing key doesn't create it
+        if value.is_empty() {
+            return ":0\r\n".to_string();
+        }
+        db.insert(key.clone(), RedisValue::new(Vec::new(), None));
+    }
+
+    match db.get_mut(&key).and_then(|redis_value| redis_value.get_mut_value().string_bytes_mut()) {
+        // An empty write leaves the value untouched, even past its end
+        Some(bytes) if value.is_empty() => format!(":{}\r\n", bytes.len()),
+        Some(bytes) => {
+            // Zero-pad up to the offset, then overwrite in place
+            if bytes.len() < offset + value.len() {
+                bytes.resize(offset + value.len(), 0);
+            }
+            bytes[offset..offset + value.len()].copy_from_slice(value);
+            format!(":{}\r\n", bytes.len())
+        }
+        None => WRONGTYPE_ERROR.to_string(),
+    }
+}
+
+// Handle the DEL command
+pub async fn handle_del(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.is_empty() {
+        return "-ERR wrong number of arguments for 'del' command\r\n".to_string();
+    }
+
+    let mut db = db.write().await;
+    let mut deleted = 0;
+    for key in args {
+        if let Some(redis_value) = db.get(key) {
+            // Expired keys are removed but not counted, as if they were already gone
+            let expired = redis_value.is_expired();
+            db.remove(key);
+            if !expired {
+                deleted += 1;
+                db.notify_keyspace_event('g', "del", key);
+            }
+        }
+    }
+    format!(":{}\r\n", deleted)
+}
+
+// Handle the EXISTS command
+pub async fn handle_exists(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.is_empty() {
+        return "-ERR wrong number of arguments for 'exists' command\r\n".to_string();
+    }
+
+    let db = read_live_keys(db, args).await;
+    // Keys repeated in the argument list are counted once per occurrence
+    let count = args.iter().filter(|key| db.get(key).is_some()).count();
+    format!(":{}\r\n", count)
+}
+
+// Handle the EXPIRE command
+pub async fn handle_expire(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() != 2 {
+        return "-ERR wrong number of arguments for 'expire' command\r\n".to_string();
+    }
+    match args[1].parse::<i64>().ok().and_then(|seconds| seconds.checked_mul(1000)) {
+        Some(ttl_millis) => apply_expire(db, &args[0], ttl_millis).await,
+        None => "-ERR value is not an integer or out of range\r\n".to_string(),
+    }
+}
+
+// Handle the PEXPIRE command
+pub async fn handle_pexpire(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() != 2 {
+        return "-ERR wrong number of arguments for 'pexpire' command\r\n".to_string();
+    }
+    match args[1].parse::<i64>() {
+        Ok(ttl_millis) => apply_expire(db, &args[0], ttl_millis).await,
+        Err(_) => "-ERR value is not an integer or out of range\r\n".to_string(),
+    }
+}
+
+// Shared by EXPIRE and PEXPIRE: a non-positive TTL expires the key immediately
+async fn apply_expire(db: &Arc<RwLock<RedisDatabase>>, key: &str, ttl_millis: i64) -> String {
+    let mut db = db.write().await;
+    db.remove_if_expired(key);
+    match db.get_mut(key) {
+        Some(redis_value) => {
+            redis_value.set_ttl(ttl_millis.max(0) as u64);
+            // A TTL that has already passed deletes the key
+            if ttl_millis <= 0 {
+                db.remove(key);
+                db.notify_keyspace_event('g', "del", key);
+            } else {
+                db.notify_keyspace_event('g', "expire", key);
+            }
+            ":1\r\n".to_string()
+        }
+        None => ":0\r\n".to_string(),
+    }
+}
+
+// Handle the PERSIST command
+pub async fn handle_persist(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() != 1 {
+        return "-ERR wrong number of arguments for 'persist' command\r\n".to_string();
+    }
+
+    let mut db = db.write().await;
+    db.remove_if_expired(&args[0]);
+    let cleared = db.get_mut(&args[0]).is_some_and(|redis_value| redis_value.clear_ttl());
+    if cleared {
+        db.notify_keyspace_event('g', "persist", &args[0]);
+    }
+    format!(":{}\r\n", cleared as u8)
+}
+
+// Handle the TTL command
+pub async fn handle_ttl(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() != 1 {
+        return "-ERR wrong number of arguments for 'ttl' command\r\n".to_string();
+    }
+    match remaining_ttl_millis(db, &args[0]).await {
+        // Round to the nearest second like Redis does
+        Some(ttl_millis) if ttl_millis >= 0 => format!(":{}\r\n", (ttl_millis + 500) / 1000),
+        Some(code) => format!(":{}\r\n", code),
+        None => ":-2\r\n".to_string(),
+    }
+}
+
+// Handle the PTTL command
+pub async fn handle_pttl(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() != 1 {
+        return "-ERR wrong number of arguments for 'pttl' command\r\n".to_string();
+    }
+    match remaining_ttl_millis(db, &args[0]).await {
+        Some(ttl_millis) => format!(":{}\r\n", ttl_millis),
+        None => ":-2\r\n".to_string(),
+    }
+}
+
+// Remaining lifetime in milliseconds, -1 for a key without TTL, None for a missing key
+async fn remaining_ttl_millis(db: &Arc<RwLock<RedisDatabase>>, key: &str) -> Option<i64> {
+    let db = read_live_keys(db, &[key]).await;
+    let redis_value = db.get(key)?;
+    match redis_value.remaining_ttl() {
+        Some(remaining) => Some(remaining.as_millis() as i64),
+        None => Some(-1),
+    }
+}
+
+// Handle the TYPE command
+pub async fn handle_type(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() != 1 {
+        return "-ERR wrong number of arguments for 'type' command\r\n".to_string();
+    }
+    let db = read_live_keys(db, args).await;
+    match db.get(&args[0]) {
+        Some(redis_value) => format!("+{}\r\n", redis_value.get_value().type_name()),
+        None => "+none\r\n".to_string(),
+    }
+}
+
+// Handle the OBJECT command (only the ENCODING subcommand)
+pub async fn handle_object(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    let Some(subcommand) = args.first() else {
+        return "-ERR wrong number of arguments for 'object' command\r\n".to_string();
+    };
+    let subcommand = subcommand.to_uppercase();
+    if !matches!(subcommand.as_str(), "ENCODING" | "IDLETIME" | "REFCOUNT" | "FREQ") {
+        return format!("-ERR unknown subcommand '{}'. Try OBJECT HELP.\r\n", args[0]);
+    }
+    if args.len() != 2 {
+        return format!("-ERR wrong number of arguments for 'object|{}' command\r\n", subcommand.to_lowercase());
+    }
+
+    let db = read_live_keys(db, &args[1..2]).await;
+    // Looked up without get() so inspecting a key doesn't count as accessing it
+    let Some(redis_value) = db.data().get(&args[1]) else {
+        return "-ERR no such key\r\n".to_string();
+    };
+    match subcommand.as_str() {
+        "ENCODING" => bulk_string(redis_value.encoding(db.encoding_limits())),
+        "IDLETIME" => format!(":{}\r\n", redis_value.idle_time().as_secs()),
+        // Values are never shared between keys
+        "REFCOUNT" => ":1\r\n".to_string(),
+        _ => "-ERR An LFU maxmemory policy is not selected, access frequency not tracked. Please note that when switching between policies at runtime LRU and LFU data will take some time to adjust.\r\n".to_string(),
+    }
+}
+
+// Handle the INCR command
+pub async fn handle_incr(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() != 1 {
+        return "-ERR wrong number of arguments for 'incr' command\r\n".to_string();
+    }
+    increment_by(db, &args[0], 1).await
+}
+
+// Handle the INCRBY command
+pub async fn handle_incrby(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() != 2 {
+        return "-ERR wrong number of arguments for 'incrby' command\r\n".to_string();
+    }
+    match args[1].parse::<i64>() {
+        Ok(delta) => increment_by(db, &args[0], delta).await,
+        Err(_) => "-ERR value is not an integer or out of range\r\n".to_string(),
+    }
+}
+
+// Handle the DECR command
+pub async fn handle_decr(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() != 1 {
+        return "-ERR wrong number of arguments for 'decr' command\r\n".to_string();
+    }
+    increment_by(db, &args[0], -1).await
+}
+
+// Handle the DECRBY command
+pub async fn handle_decrby(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() != 2 {
+        return "-ERR wrong number of arguments for 'decrby' command\r\n".to_string();
+    }
+    match args[1].parse::<i64>() {
+        Ok(delta) => match delta.checked_neg() {
+            Some(delta) => increment_by(db, &args[0], delta).await,
+            None => "-ERR decrement would overflow\r\n".to_string(),
+        },
+        Err(_) => "-ERR value is not an integer or out of range\r\n".to_string(),
+    }
+}
+
+// Shared by the INCR family: apply `delta` to the integer stored at `key`,
+// treating a missing key as 0 and keeping any existing TTL
+async fn increment_by(db: &Arc<RwLock<RedisDatabase>>, key: &str, delta: i64) -> String {
+    let mut db_lock = db.write().await;
+    db_lock.remove_if_expired(key);
+
+    let current = match db_lock.get(key).map(|redis_value| redis_value.get_value()) {
+        Some(RedisValueType::IntegerValue(int_value)) => *int_value,
+        Some(RedisValueType::StringValue(s)) => match parse_integer(s) {
+            Some(int_value) => int_value,
+            // The value exists but is not an integer
+            None => return "-ERR value is not an integer or out of range\r\n".to_string(),
+        },
+        Some(_) => return WRONGTYPE_ERROR.to_string(),
+        None => 0,
+    };
+    let int_value = match current.checked_add(delta) {
+        Some(int_value) => int_value,
+        None => return "-ERR increment or decrement would overflow\r\n".to_string(),
+    };
+
+    match db_lock.get_mut(key) {
+        Some(redis_value) => *redis_value.get_mut_value() = RedisValueType::IntegerValue(int_value),
+        None => db_lock.insert(key.to_string(), RedisValue::new(RedisValueType::IntegerValue(int_value), None)),
+    }
+    db_lock.notify_keyspace_event('$', "incrby", key);
+    format!(":{}\r\n", int_value)
+}
+
+// Handle the INCRBYFLOAT command
+pub async fn handle_incrbyfloat(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> Vec<u8> {
+    if args.len() != 2 {
+        return b"-ERR wrong number of arguments for 'incrbyfloat' command\r\n".to_vec();
+    }
+    let increment = match args[1].parse::<f64>() {
+        Ok(increment) if !increment.is_nan() => increment,
+        _ => return b"-ERR value is not a valid float\r\n".to_vec(),
+    };
+
+    let key = &args[0];
+    let mut db_lock = db.write().await;
+    db_lock.remove_if_expired(key);
+
+    let current = match db_lock.get(key).map(|redis_value| redis_value.get_value()) {
+        Some(RedisValueType::IntegerValue(int_value)) => *int_value as f64,
+        Some(RedisValueType::StringValue(s)) => {
+            match std::str::from_utf8(s).ok().and_then(|s| s.parse::<f64>().ok()) {
+                Some(float_value) if !float_value.is_nan() => float_value,
+                _ => return b"-ERR value is not a valid float\r\n".to_vec(),
+            }
+        }
+        Some(_) => return WRONGTYPE_ERROR.into(),
+        None => 0.0,
+    };
+    let result = current + increment;
+    if !result.is_finite() {
+        return b"-ERR increment would produce NaN or Infinity\r\n".to_vec();
+    }
+
+    let formatted = format_float_human(result).into_bytes();
+    let reply = bulk_string_bytes(&formatted);
+    match db_lock.get_mut(key) {
+        Some(redis_value) => *redis_value.get_mut_value() = RedisValueType::StringValue(formatted),
+        None => db_lock.insert(key.clone(), RedisValue::new(formatted, None)),
+    }
+    db_lock.notify_keyspace_event('$', "incrbyfloat", key);
+    reply
+}
+
+// Format an INCRBYFLOAT result like Redis's human-friendly ld2string: no exponent and no trailing
+// zeros (3.0 -> "3"), which f64's Display already does. Redis adds in long double and prints 17
+// significant digits; an f64 is only exact to 15, so rounding there first drops the binary noise
+// that makes 0.1 + 0.2 come out as 0.30000000000000004.
+fn format_float_human(value: f64) -> String {
+    let rounded: f64 = format!("{:.14e}", value).parse().unwrap_or(value);
+    rounded.to_string()
+}
+
+// Interpret a stored string as an integer only if it is in canonical form,
+// so values like "007" or "+5" are not silently rewritten by INCR
+fn parse_integer(bytes: &[u8]) -> Option<i64> {
+    let s = std::str::from_utf8(bytes).ok()?;
+    s.parse::<i64>().ok().filter(|int_value| int_value.to_string() == s)
+}
+
+// Handle the LPUSH command
+pub async fn handle_lpush(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    push_to_list(db, args, true, false, "lpush").await
+}
+
+// Handle the RPUSH command
+pub async fn handle_rpush(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    push_to_list(db, args, false, false, "rpush").await
+}
+
+// Handle the LPUSHX command: LPUSH, but only onto a list that already exists
+pub async fn handle_lpushx(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    push_to_list(db, args, true, true, "lpushx").await
+}
+
+// Handle the RPUSHX command: RPUSH, but only onto a list that already exists
+pub async fn handle_rpushx(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    push_to_list(db, args, false, true, "rpushx").await
+}
+
+// Shared by LPUSH and RPUSH: push each element in order, creating the list if needed
+async fn push_to_list(db: &Arc<RwLock<RedisDatabase>>, args: &[String], to_front: bool, only_existing: bool, command: &str) -> String {
+    if args.len() < 2 {
+        return format!("-ERR wrong number of arguments for '{}' command\r\n", command);
+    }
+
+    let mut db = db.write().await;
+    db.remove_if_expired(&args[0]);
+    if db.get(&args[0]).is_none() {
+        if only_existing {
+            return ":0\r\n".to_string();
+        }
+        db.insert(args[0].clone(), RedisValue::new(VecDeque::new(), None));
+    }
+    let length = match db.get_mut(&args[0]).map(|redis_value| redis_value.get_mut_value()) {
+        Some(RedisValueType::ListValue(list)) => {
+            for element in &args[1..] {
+                if to_front {
+                    list.push_front(element.clone());
+                } else {
+                    list.push_back(element.clone());
+                }
+            }
+            list.len()
+        }
+        _ => return WRONGTYPE_ERROR.to_string(),
+    };
+    // LPUSHX and RPUSHX report the same event as LPUSH and RPUSH
+    db.notify_keyspace_event('l', if to_front { "lpush" } else { "rpush" }, &args[0]);
+    format!(":{}\r\n", length)
+}
+
+// Handle the LPOP command
+pub async fn handle_lpop(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    pop_from_list(db, args, true, "lpop").await
+}
+
+// Handle the RPOP command
+pub async fn handle_rpop(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    pop_from_list(db, args, false, "rpop").await
+}
+
+// Shared by LPOP and RPOP: without a count reply with a single element, with one reply with an array
+async fn pop_from_list(db: &Arc<RwLock<RedisDatabase>>, args: &[String], from_front: bool, command: &str) -> String {
+    if args.is_empty() || args.len() > 2 {
+        return format!("-ERR wrong number of arguments for '{}' command\r\n", command);
+    }
+    let count = match args.get(1).map(|count| count.parse::<i64>()) {
+        Some(Ok(count)) if count < 0 => return "-ERR value is out of range, must be positive\r\n".to_string(),
+        Some(Ok(count)) => Some(count as usize),
+        Some(Err(_)) => return "-ERR value is not an integer or out of range\r\n".to_string(),
+        None => None,
+    };
+
+    let mut db = db.write().await;
+    db.remove_if_expired(&args[0]);
+    let list = match db.get_mut(&args[0]).map(|redis_value| redis_value.get_mut_value()) {
+        Some(RedisValueType::ListValue(list)) => list,
+        Some(_) => return WRONGTYPE_ERROR.to_string(),
+        None if count.is_some() => return "*-1\r\n".to_string(),
+        None => return "$-1\r\n".to_string(),
+    };
+
+    let mut popped = Vec::new();
+    for _ in 0..count.unwrap_or(1).min(list.len()) {
+        let element = if from_front { list.pop_front() } else { list.pop_back() };
+        popped.extend(element);
+    }
+    // An emptied list no longer exists
+    let emptied = list.is_empty();
+    if !popped.is_empty() {
+        db.notify_keyspace_event('l', command, &args[0]);
+    }
+    if emptied {
+        db.remove(&args[0]);
+        db.notify_keyspace_event('g', "del", &args[0]);
+    }
+
+    match count {
+        Some(_) => bulk_string_array(popped.iter()),
+        None => popped.first().map_or("$-1\r\n".to_string(), |element| bulk_string(element)),
+    }
+}
+
+// Handle the LRANGE command
+pub async fn handle_lrange(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() != 3 {
+        return "-ERR wrong number of arguments for 'lrange' command\r\n".to_string();
+    }
+    let (start, end) = match (args[1].parse::<i64>(), args[2].parse::<i64>()) {
+        (Ok(start), Ok(end)) => (start, end),
+        _ => return "-ERR value is not an integer or out of range\r\n".to_string(),
+    };
+
+    let db = read_live_keys(db, &args[..1]).await;
+    let list = match db.get(&args[0]).map(|redis_value| redis_value.get_value()) {
+        Some(RedisValueType::ListValue(list)) => list,
+        Some(_) => return WRONGTYPE_ERROR.to_string(),
+        None => return "*0\r\n".to_string(),
+    };
+
+    match normalize_range(start, end, list.len()) {
+        Some((start, end)) => bulk_string_array(list.range(start..=end)),
+        None => "*0\r\n".to_string(),
+    }
+}
+
+// Resolve a possibly negative list index against `len`, or None if it falls outside the list
+fn list_position(index: i64, len: usize) -> Option<usize> {
+    let position = if index < 0 { len as i64 + index } else { index };
+    (0..len as i64).contains(&position).then_some(position as usize)
+}
+
+// Handle the LINDEX command
+pub async fn handle_lindex(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() != 2 {
+        return "-ERR wrong number of arguments for 'lindex' command\r\n".to_string();
+    }
+    let index = match args[1].parse::<i64>() {
+        Ok(index) => index,
+        Err(_) => return "-ERR value is not an integer or out of range\r\n".to_string(),
+    };
+
+    let db = read_live_keys(db, &args[..1]).await;
+    match db.get(&args[0]).map(|redis_value| redis_value.get_value()) {
+        Some(RedisValueType::ListValue(list)) => list_position(index, list.len())
+            .map_or("$-1\r\n".to_string(), |position| bulk_string(&list[position])),
+        Some(_) => WRONGTYPE_ERROR.to_string(),
+        None => "$-1\r\n".to_string(),
+    }
+}
+
+// Handle the LSET command
+pub async fn handle_lset(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() != 3 {
+        return "-ERR wrong number of arguments for 'lset' command\r\n".to_string();
+    }
+    let index = match args[1].parse::<i64>() {
+        Ok(index) => index,
+        Err(_) => return "-ERR value is not an integer or out of range\r\n".to_string(),
+    };
+
+    let mut db = db.write().await;
+    db.remove_if_expired(&args[0]);
+    match db.get_mut(&args[0]).map(|redis_value| redis_value.get_mut_value()) {
+        Some(RedisValueType::ListValue(list)) => match list_position(index, list.len()) {
+            Some(position) => list[position] = args[2].clone(),
+            None => return "-ERR index out of range\r\n".to_string(),
+        },
+        Some(_) => return WRONGTYPE_ERROR.to_string(),
+        None => return "-ERR no such key\r\n".to_string(),
+    }
+    db.notify_keyspace_event('l', "lset", &args[0]);
+    "+OK\r\n".to_string()
+}
+
+// Handle the LLEN command
+pub async fn handle_llen(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() != 1 {
+        return "-ERR wrong number of arguments for 'llen' command\r\n".to_string();
+    }
+
+    let db = read_live_keys(db, &args[..1]).await;
+    match db.get(&args[0]).map(|redis_value| redis_value.get_value()) {
+        Some(RedisValueType::ListValue(list)) => format!(":{}\r\n", list.len()),
+        Some(_) => WRONGTYPE_ERROR.to_string(),
+        None => ":0\r\n".to_string(),
+    }
+}
+
+// Handle the HSET command
+pub async fn handle_hset(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() < 3 || args.len().is_multiple_of(2) {
+        return "-ERR wrong number of arguments for 'hset' command\r\n".to_string();
+    }
+
+    let mut db = db.write().await;
+    db.remove_if_expired(&args[0]);
+    if db.get(&args[0]).is_none() {
+        db.insert(args[0].clone(), RedisValue::new(HashMap::new(), None));
+    }
+    let added = match db.get_mut(&args[0]).map(|redis_value| redis_value.get_mut_value()) {
+        Some(RedisValueType::HashValue(hash)) => {
+            let mut added = 0;
+            for pair in args[1..].chunks(2) {
+                if hash.insert(pair[0].clone(), pair[1].clone()).is_none() {
+                    added += 1;
+                }
+            }
+            added
+        }
+        _ => return WRONGTYPE_ERROR.to_string(),
+    };
+    db.notify_keyspace_event('h', "hset", &args[0]);
+    format!(":{}\r\n", added)
+}
+
+// Handle the HGET command
+pub async fn handle_hget(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() != 2 {
+        return "-ERR wrong number of arguments for 'hget' command\r\n".to_string();
+    }
+
+    let db = read_live_keys(db, &args[..1]).await;
+    match db.get(&args[0]).map(|redis_value| redis_value.get_value()) {
+        Some(RedisValueType::HashValue(hash)) => hash
+            .get(&args[1])
+            .map_or("$-1\r\n".to_string(), |value| bulk_string(value)),
+        Some(_) => WRONGTYPE_ERROR.to_string(),
+        None => "$-1\r\n".to_string(),
+    }
+}
+
+// Handle the HGETALL command
+pub async fn handle_hgetall(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() != 1 {
+        return "-ERR wrong number of arguments for 'hgetall' command\r\n".to_string();
+    }
+
+    let db = read_live_keys(db, &args[..1]).await;
+    match db.get(&args[0]).map(|redis_value| redis_value.get_value()) {
+        Some(RedisValueType::HashValue(hash)) => {
+            let mut response = map_header(hash.len());
+            for (field, value) in hash {
+                response.push_str(&bulk_string(field));
+                response.push_str(&bulk_string(value));
+            }
+            response
+        }
+        Some(_) => WRONGTYPE_ERROR.to_string(),
+        None => map_header(0), // Still a map under RESP3, just an empty one
+    }
+}
+
+// Handle the HDEL command
+pub async fn handle_hdel(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() < 2 {
+        return "-ERR wrong number of arguments for 'hdel' command\r\n".to_string();
+    }
+
+    let mut db = db.write().await;
+    db.remove_if_expired(&args[0]);
+    let hash = match db.get_mut(&args[0]).map(|redis_value| redis_value.get_mut_value()) {
+        Some(RedisValueType::HashValue(hash)) => hash,
+        Some(_) => return WRONGTYPE_ERROR.to_string(),
+        None => return ":0\r\n".to_string(),
+    };
+
+    let removed = args[1..].iter().filter(|field| hash.remove(*field).is_some()).count();
+    // An emptied hash no longer exists
+    let emptied = hash.is_empty();
+    if removed > 0 {
+        db.notify_keyspace_event('h', "hdel", &args[0]);
+    }
+    if emptied {
+        db.remove(&args[0]);
+        db.notify_keyspace_event('g', "del", &args[0]);
+    }
+    format!(":{}\r\n", removed)
+}
+
+// Handle the HEXISTS command
+pub async fn handle_hexists(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() != 2 {
+        return "-ERR wrong number of arguments for 'hexists' command\r\n".to_string();
+    }
+
+    let db = read_live_keys(db, &args[..1]).await;
+    match db.get(&args[0]).map(|redis_value| redis_value.get_value()) {
+        Some(RedisValueType::HashValue(hash)) => format!(":{}\r\n", hash.contains_key(&args[1]) as u8),
+        Some(_) => WRONGTYPE_ERROR.to_string(),
+        None => ":0\r\n".to_string(),
+    }
+}
+
+// Handle the HKEYS command
+pub async fn handle_hkeys(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() != 1 {
+        return "-ERR wrong number of arguments for 'hkeys' command\r\n".to_string();
+    }
+
+    let db = read_live_keys(db, &args[..1]).await;
+    match db.get(&args[0]).map(|redis_value| redis_value.get_value()) {
+        Some(RedisValueType::HashValue(hash)) => bulk_string_array(hash.keys()),
+        Some(_) => WRONGTYPE_ERROR.to_string(),
+        None => "*0\r\n".to_string(),
+    }
+}
+
+// Handle the HVALS command
+pub async fn handle_hvals(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() != 1 {
+        return "-ERR wrong number of arguments for 'hvals' command\r\n".to_string();
+    }
+
+    let db = read_live_keys(db, &args[..1]).await;
+    match db.get(&args[0]).map(|redis_value| redis_value.get_value()) {
+        Some(RedisValueType::HashValue(hash)) => bulk_string_array(hash.values()),
+        Some(_) => WRONGTYPE_ERROR.to_string(),
+        None => "*0\r\n".to_string(),
+    }
+}
+
+// Handle the HLEN command
+pub async fn handle_hlen(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() != 1 {
+        return "-ERR wrong number of arguments for 'hlen' command\r\n".to_string();
+    }
+
+    let db = read_live_keys(db, &args[..1]).await;
+    match db.get(&args[0]).map(|redis_value| redis_value.get_value()) {
+        Some(RedisValueType::HashValue(hash)) => format!(":{}\r\n", hash.len()),
+        Some(_) => WRONGTYPE_ERROR.to_string(),
+        None => ":0\r\n".to_string(),
+    }
+}
+
+// Handle the HINCRBY command
+pub async fn handle_hincrby(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() != 3 {
+        return "-ERR wrong number of arguments for 'hincrby' command\r\n".to_string();
+    }
+    let delta = match args[2].parse::<i64>() {
+        Ok(delta) => delta,
+        Err(_) => return "-ERR value is not an integer or out of range\r\n".to_string(),
+    };
+
+    let mut db = db.write().await;
+    db.remove_if_expired(&args[0]);
+    if db.get(&args[0]).is_none() {
+        db.insert(args[0].clone(), RedisValue::new(HashMap::new(), None));
+    }
+    let hash = match db.get_mut(&args[0]).map(|redis_value| redis_value.get_mut_value()) {
+        Some(RedisValueType::HashValue(hash)) => hash,
+        _ => return WRONGTYPE_ERROR.to_string(),
+    };
+
+    let current = match hash.get(&args[1]) {
+        Some(value) => match parse_integer(value.as_bytes()) {
+            Some(current) => current,
+            None => return "-ERR hash value is not an integer\r\n".to_string(),
+        },
+        None => 0,
+    };
+    match current.checked_add(delta) {
+        Some(new_value) => {
+            hash.insert(args[1].clone(), new_value.to_string());
+            db.notify_keyspace_event('h', "hincrby", &args[0]);
+            format!(":{}\r\n", new_value)
+        }
+        None => "-ERR increment or decrement would overflow\r\n".to_string(),
+    }
+}
+
+// Handle the SADD command
+pub async fn handle_sadd(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() < 2 {
+        return "-ERR wrong number of arguments for 'sadd' command\r\n".to_string();
+    }
+
+    let mut db = db.write().await;
+    db.remove_if_expired(&args[0]);
+    if db.get(&args[0]).is_none() {
+        db.insert(args[0].clone(), RedisValue::new(HashSet::new(), None));
+    }
+    let added = match db.get_mut(&args[0]).map(|redis_value| redis_value.get_mut_value()) {
+        Some(RedisValueType::SetValue(set)) => args[1..].iter().filter(|member| set.insert(member.to_string())).count(),
+        _ => return WRONGTYPE_ERROR.to_string(),
+    };
+    if added > 0 {
+        db.notify_keyspace_event('s', "sadd", &args[0]);
+    }
+    format!(":{}\r\n", added)
+}
+
+// Handle the SREM command
+pub async fn handle_srem(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() < 2 {
+        return "-ERR wrong number of arguments for 'srem' command\r\n".to_string();
+    }
+
+    let mut db = db.write().await;
+    db.remove_if_expired(&args[0]);
+    let set = match db.get_mut(&args[0]).map(|redis_value| redis_value.get_mut_value()) {
+        Some(RedisValueType::SetValue(set)) => set,
+        Some(_) => return WRONGTYPE_ERROR.to_string(),
+        None => return ":0\r\n".to_string(),
+    };
+
+    let removed = args[1..].iter().filter(|member| set.remove(*member)).count();
+    // An emptied set no longer exists
+    let emptied = set.is_empty();
+    if removed > 0 {
+        db.notify_keyspace_event('s', "srem", &args[0]);
+    }
+    if emptied {
+        db.remove(&args[0]);
+        db.notify_keyspace_event('g', "del", &args[0]);
+    }
+    format!(":{}\r\n", removed)
+}
+
+// Handle the SMEMBERS command
+pub async fn handle_smembers(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() != 1 {
+        return "-ERR wrong number of arguments for 'smembers' command\r\n".to_string();
+    }
+
+    let db = read_live_keys(db, &args[..1]).await;
+    match db.get(&args[0]).map(|redis_value| redis_value.get_value()) {
+        Some(RedisValueType::SetValue(set)) => bulk_string_array(set.iter()),
+        Some(_) => WRONGTYPE_ERROR.to_string(),
+        None => "*0\r\n".to_string(),
+    }
+}
+
+// Handle the SISMEMBER command
+pub async fn handle_sismember(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() != 2 {
+        return "-ERR wrong number of arguments for 'sismember' command\r\n".to_string();
+    }
+
+    let db = read_live_keys(db, &args[..1]).await;
+    match db.get(&args[0]).map(|redis_value| redis_value.get_value()) {
+        Some(RedisValueType::SetValue(set)) => format!(":{}\r\n", set.contains(&args[1]) as u8),
+        Some(_) => WRONGTYPE_ERROR.to_string(),
+        None => ":0\r\n".to_string(),
+    }
+}
+
+// Handle the SCARD command
+pub async fn handle_scard(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() != 1 {
+        return "-ERR wrong number of arguments for 'scard' command\r\n".to_string();
+    }
+
+    let db = read_live_keys(db, &args[..1]).await;
+    match db.get(&args[0]).map(|redis_value| redis_value.get_value()) {
+        Some(RedisValueType::SetValue(set)) => format!(":{}\r\n", set.len()),
+        Some(_) => WRONGTYPE_ERROR.to_string(),
+        None => ":0\r\n".to_string(),
+    }
+}
+
+// Handle the SINTER command
+pub async fn handle_sinter(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    let db = read_live_keys(db, args).await;
+    let sets = match sets_for_keys(&db, args, "sinter") {
+        Ok(sets) => sets,
+        Err(error) => return error,
+    };
+
+    // A missing key is an empty set, so the intersection is empty too
+    if sets.iter().any(|set| set.is_none()) {
+        return "*0\r\n".to_string();
+    }
+    let mut sets: Vec<&HashSet<String>> = sets.into_iter().flatten().collect();
+    // Start from the smallest set so as few members as possible are checked
+    sets.sort_by_key(|set| set.len());
+    let (smallest, rest) = sets.split_first().expect("at least one key is required");
+    let intersection: Vec<&String> = smallest
+        .iter()
+        .filter(|member| rest.iter().all(|set| set.contains(*member)))
+        .collect();
+    bulk_string_array(intersection.into_iter())
+}
+
+// Handle the SUNION command
+pub async fn handle_sunion(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    let db = read_live_keys(db, args).await;
+    let sets = match sets_for_keys(&db, args, "sunion") {
+        Ok(sets) => sets,
+        Err(error) => return error,
+    };
+
+    let union: HashSet<&String> = sets.into_iter().flatten().flatten().collect();
+    bulk_string_array(union.into_iter())
+}
+
+// Handle the SDIFF command
+pub async fn handle_sdiff(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    let db = read_live_keys(db, args).await;
+    let sets = match sets_for_keys(&db, args, "sdiff") {
+        Ok(sets) => sets,
+        Err(error) => return error,
+    };
+
+    let (first, rest) = sets.split_first().expect("at least one key is required");
+    let difference: Vec<&String> = first
+        .iter()
+        .flat_map(|set| set.iter())
+        .filter(|member| !rest.iter().flatten().any(|set| set.contains(*member)))
+        .collect();
+    bulk_string_array(difference.into_iter())
+}
+
+// Look up the sets stored at `keys` for the set algebra commands, with None for missing keys.
+// Expired keys must already have been removed, see read_live_keys.
+// Fails on a wrong argument count or if any key holds a non-set value.
+fn sets_for_keys<'a>(db: &'a RedisDatabase, keys: &[String], command: &str) -> Result<Vec<Option<&'a HashSet<String>>>, String> {
+    if keys.is_empty() {
+        return Err(format!("-ERR wrong number of arguments for '{}' command\r\n", command));
+    }
+    keys.iter()
+        .map(|key| match db.get(key).map(|redis_value| redis_value.get_value()) {
+            Some(RedisValueType::SetValue(set)) => Ok(Some(set)),
+            Some(_) => Err(WRONGTYPE_ERROR.to_string()),
+            None => Ok(None),
+        })
+        .collect()
+}
+
+// Parse a sorted set score, rejecting NaN ("inf" and "-inf" are allowed)
+fn parse_score(arg: &str) -> Option<f64> {
+    arg.parse::<f64>().ok().filter(|score| !score.is_nan())
+}
+
+// Handle the ZADD command
+pub async fn handle_zadd(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() < 3 || args.len().is_multiple_of(2) {
+        return "-ERR wrong number of arguments for 'zadd' command\r\n".to_string();
+    }
+    // Validate every score before touching the set so a bad pair adds nothing
+    let mut pairs = Vec::new();
+    for pair in args[1..].chunks(2) {
+        match parse_score(&pair[0]) {
+            Some(score) => pairs.push((score, pair[1].clone())),
+            None => return "-ERR value is not a valid float\r\n".to_string(),
+        }
+    }
+
+    let mut db = db.write().await;
+    db.remove_if_expired(&args[0]);
+    if db.get(&args[0]).is_none() {
+        db.insert(args[0].clone(), RedisValue::new(SortedSet::new(), None));
+    }
+    let (mut added, mut changed) = (0, 0);
+    match db.get_mut(&args[0]).map(|redis_value| redis_value.get_mut_value()) {
+        Some(RedisValueType::ZSetValue(zset)) => {
+            for (score, member) in pairs {
+                // Members already at their score are left alone
+                if zset.score(&member) == Some(score) {
+                    continue;
+                }
+                changed += 1;
+                if zset.insert(member, score) {
+                    added += 1;
+                }
+            }
+        }
+        _ => return WRONGTYPE_ERROR.to_string(),
+    }
+    // Like Redis, only a ZADD that added or updated something is announced
+    if changed > 0 {
+        db.notify_keyspace_event('z', "zadd", &args[0]);
+    }
+    format!(":{}\r\n", added)
+}
+
+// Handle the ZSCORE command
+pub async fn handle_zscore(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() != 2 {
+        return "-ERR wrong number of arguments for 'zscore' command\r\n".to_string();
+    }
+
+    let db = read_live_keys(db, &args[..1]).await;
+    match db.get(&args[0]).map(|redis_value| redis_value.get_value()) {
+        Some(RedisValueType::ZSetValue(zset)) => zset
+            .score(&args[1])
+            .map_or("$-1\r\n".to_string(), |score| bulk_string(&score.to_string())),
+        Some(_) => WRONGTYPE_ERROR.to_string(),
+        None => "$-1\r\n".to_string(),
     }
 }
 
-// Handle the GET command
-pub async fn handle_get(db: &Arc<Mutex<RedisDatabase>>, args: &[String]) -> String {
-    let mut db = db.lock().await;
-    if let Some(redis_value) = db.get(&args[0]) {
-        if redis_value.is_expired() {
-            db.remove(&args[0]);
-            "$-1\r\n".to_string()
-        } else {
-            format!("${}\r\n{}\r\n", redis_value.get_value().len(), redis_value.get_value())
-        }
-    } else {
-        "$-1\r\n".to_string()
-    }
+// Handle the ZRANGE command
+pub async fn handle_zrange(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    let with_scores = match args.len() {
+        3 => false,
+        4 if args[3].eq_ignore_ascii_case("WITHSCORES") => true,
+        4 => return "-ERR syntax error\r\n".to_string(),
+        _ => return "-ERR wrong number of arguments for 'zrange' command\r\n".to_string(),
+    };
+    let (start, end) = match (args[1].parse::<i64>(), args[2].parse::<i64>()) {
+        (Ok(start), Ok(end)) => (start, end),
+        _ => return "-ERR value is not an integer or out of range\r\n".to_string(),
+    };
+
+    let db = read_live_keys(db, &args[..1]).await;
+    let zset = match db.get(&args[0]).map(|redis_value| redis_value.get_value()) {
+        Some(RedisValueType::ZSetValue(zset)) => zset,
+        Some(_) => return WRONGTYPE_ERROR.to_string(),
+        None => return "*0\r\n".to_string(),
+    };
+
+    let (start, end) = match normalize_range(start, end, zset.len()) {
+        Some(range) => range,
+        None => return "*0\r\n".to_string(),
+    };
+    let entries: Vec<(&String, f64)> = zset.iter().skip(start).take(end - start + 1).collect();
+    zset_entries_reply(&entries, with_scores)
 }
 
-// Handle the TYPE command
-pub async fn handle_type(db: &Arc<Mutex<RedisDatabase>>, args: &[String]) -> String {
-    let db = db.lock().await;
-    if let Some(redis_value) = db.get(&args[0]) {
-        match redis_value.get_value() {
-            RedisValueType::StringValue(_) => "+string\r\n".to_string(),
-            RedisValueType::StreamValue(_) => "+stream\r\n".to_string(),
-            RedisValueType::IntegerValue(_) => "+integer\r\n".to_string(),
+// Encode sorted set members as a RESP array, interleaving their scores if requested
+fn zset_entries_reply(entries: &[(&String, f64)], with_scores: bool) -> String {
+    let mut response = format!("*{}\r\n", if with_scores { entries.len() * 2 } else { entries.len() });
+    for (member, score) in entries {
+        response.push_str(&bulk_string(member));
+        if with_scores {
+            response.push_str(&bulk_string(&score.to_string()));
         }
-    } else {
-        "+none\r\n".to_string()
     }
+    response
 }
 
-// Handle the INCR command
-pub async fn handle_incr(db: &Arc<Mutex<RedisDatabase>>, args: &[String]) -> String {
-    if args.is_empty() {
-        return "-ERR wrong number of arguments\r\n".to_string();
+// One end of a ZRANGEBYSCORE interval; a "(" prefix makes it exclusive
+struct ScoreBound {
+    score: f64,
+    exclusive: bool,
+}
+
+impl ScoreBound {
+    fn parse(arg: &str) -> Option<Self> {
+        let (score, exclusive) = match arg.strip_prefix('(') {
+            Some(score) => (score, true),
+            None => (arg, false),
+        };
+        parse_score(score).map(|score| ScoreBound { score, exclusive })
     }
 
-    let key = &args[0];
-    let mut db_lock = db.lock().await;
+    fn admits_above(&self, score: f64) -> bool {
+        if self.exclusive { score > self.score } else { score >= self.score }
+    }
 
-    // Get the value from the database
-    match db_lock.get_mut(key) {
-        Some(redis_value) => {
-            match &mut redis_value.get_mut_value(){
-                RedisValueType::IntegerValue(ref mut int_value) => {
-                    // Increment the integer value by 1
-                    *int_value += 1;
-                    format!(":{}\r\n", int_value)
-                }
-                _ => {
-                    // The value exists but is not an integer
-                    "-ERR value is not an integer or out of range\r\n".to_string()
+    fn admits_below(&self, score: f64) -> bool {
+        if self.exclusive { score < self.score } else { score <= self.score }
+    }
+}
+
+// Handle the ZRANGEBYSCORE command
+pub async fn handle_zrangebyscore(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() < 3 {
+        return "-ERR wrong number of arguments for 'zrangebyscore' command\r\n".to_string();
+    }
+    let (min, max) = match (ScoreBound::parse(&args[1]), ScoreBound::parse(&args[2])) {
+        (Some(min), Some(max)) => (min, max),
+        _ => return "-ERR min or max is not a float\r\n".to_string(),
+    };
+
+    let mut with_scores = false;
+    let mut limit: Option<(i64, i64)> = None;
+    let mut i = 3;
+    while i < args.len() {
+        match args[i].to_uppercase().as_str() {
+            "WITHSCORES" => with_scores = true,
+            "LIMIT" if i + 2 < args.len() => {
+                match (args[i + 1].parse::<i64>(), args[i + 2].parse::<i64>()) {
+                    (Ok(offset), Ok(count)) => limit = Some((offset, count)),
+                    _ => return "-ERR value is not an integer or out of range\r\n".to_string(),
                 }
+                i += 2;
             }
+            _ => return "-ERR syntax error\r\n".to_string(),
         }
-        None => {
-            // Key doesn't exist, so we create it with a value of 1
-            db_lock.insert(
-                key.clone(),
-                RedisValue::new(RedisValueType::IntegerValue(1), None)
-            );
-            ":1\r\n".to_string()
-        }
+        i += 1;
     }
+
+    let db = read_live_keys(db, &args[..1]).await;
+    let zset = match db.get(&args[0]).map(|redis_value| redis_value.get_value()) {
+        Some(RedisValueType::ZSetValue(zset)) => zset,
+        Some(_) => return WRONGTYPE_ERROR.to_string(),
+        None => return "*0\r\n".to_string(),
+    };
+
+    // A negative offset selects nothing; a negative count means no limit
+    let (offset, count) = match limit {
+        Some((offset, _)) if offset < 0 => return "*0\r\n".to_string(),
+        Some((offset, count)) => (offset as usize, usize::try_from(count).unwrap_or(usize::MAX)),
+        None => (0, usize::MAX),
+    };
+    let matches: Vec<(&String, f64)> = zset
+        .iter()
+        .skip_while(|(_, score)| !min.admits_above(*score))
+        .take_while(|(_, score)| max.admits_below(*score))
+        .skip(offset)
+        .take(count)
+        .collect();
+    zset_entries_reply(&matches, with_scores)
 }
 
 // Handle the XADD command
-pub async fn handle_xadd(db: &Arc<Mutex<RedisDatabase>>, args: &[String]) -> String {
-    if args.len() < 4 || args.len() % 2 != 0 {
+pub async fn handle_xadd(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() < 4 {
         return "-ERR wrong number of arguments for 'xadd' command\r\n".to_string();
     }
-    let mut db = db.lock().await;
+    let (options, id_index) = match XaddOptions::parse(args) {
+        Ok(parsed) => parsed,
+        Err(error) => return error,
+    };
+    let fields = &args[id_index + 1..];
+    if fields.is_empty() || !fields.len().is_multiple_of(2) {
+        return "-ERR wrong number of arguments for 'xadd' command\r\n".to_string();
+    }
+    let mut db = db.write().await;
     let stream_key = &args[0];
-    let stream_id_str = &args[1];
+    let stream_id_str = &args[id_index];
+    db.remove_if_expired(stream_key);
+    if let Err(error) = expect_type(db.get(stream_key), "stream") {
+        return error;
+    }
 
     let stream_id = if stream_id_str == "*" {
         // Fully generate the stream ID using the current time
-        if let Some(redis_value) = db.get(stream_key) {
-            if let RedisValueType::StreamValue(stream) = redis_value.get_value() {
-                StreamID::generate(stream)
-            } else {
-                return "-ERR wrong type for 'xadd' command\r\n".to_string();
-            }
+        if let Some(RedisValueType::StreamValue(stream)) = db.get(stream_key).map(RedisValue::get_value) {
+            StreamID::generate(stream.last_id())
         } else {
-            StreamID::generate(&BTreeMap::new()) // Generate if stream does not exist
+            StreamID::generate(StreamID::zero()) // Generate if stream does not exist
         }
-    } else if stream_id_str.contains('-') && stream_id_str.ends_with("-*") {
+    } else if let Some(time_part) = stream_id_str.strip_suffix("-*") {
         // Partially generate stream ID, e.g., 1-*
-        let time_part = stream_id_str.trim_end_matches("-*").parse::<u64>().unwrap();
-        if let Some(redis_value) = db.get(stream_key) {
-            if let RedisValueType::StreamValue(stream) = redis_value.get_value() {
-                StreamID::generate_with_time(time_part, stream)
-            } else {
-                return "-ERR wrong type for 'xadd' command\r\n".to_string();
-            }
+        let Ok(time_part) = time_part.parse::<u64>() else {
+            return "-ERR invalid stream ID\r\n".to_string();
+        };
+        if let Some(RedisValueType::StreamValue(stream)) = db.get(stream_key).map(RedisValue::get_value) {
+            StreamID::generate_with_time(time_part, stream.last_id())
         } else {
             StreamID {
                 milliseconds_time: time_part,
@@ -125,7 +1478,7 @@ pub async fn handle_xadd(db: &Arc<Mutex<RedisDatabase>>, args: &[String]) -> Str
         }
     } else {
         // Parse the full stream ID
-        match StreamID::from_str(stream_id_str) {
+        match StreamID::parse(stream_id_str) {
             Some(id) => id,
             None => return "-ERR invalid stream ID\r\n".to_string(),
         }
@@ -139,39 +1492,179 @@ pub async fn handle_xadd(db: &Arc<Mutex<RedisDatabase>>, args: &[String]) -> Str
 
     // Collect the key-value pairs for the stream entry
     let mut entry = HashMap::new();
-    for i in (2..args.len()).step_by(2) {
-        entry.insert(args[i].clone(), args[i + 1].clone());
-    }
-
-    // Check if the stream already exists in the database
-    if let Some(redis_value) = db.get(stream_key) {
-        if let RedisValueType::StreamValue(stream) = redis_value.get_value() {
-            // Check if the stream has any entries
-            if let Some(last_id) = stream.keys().max() {
-                // Validate the new stream ID
-                if !stream_id.is_valid(last_id) {
-                    return "-ERR The ID specified in XADD is equal or smaller than the target stream top item\r\n".to_string();
-                }
-            }
-            let mut stream = stream.clone(); // Clone the stream to modify it
-            stream.insert(stream_id.clone(), entry);
-            db.insert(stream_key.clone(), RedisValue::new(stream, None)); // Update the stream in the database
-        } else {
-            return "-ERR wrong type for 'xadd' command\r\n".to_string();
+    for pair in fields.chunks(2) {
+        entry.insert(pair[0].clone(), pair[1].clone());
+    }
+
+    // Add the entry in place, so the key keeps its TTL and other metadata
+    if let Some(RedisValueType::StreamValue(stream)) = db.get_mut(stream_key).map(RedisValue::get_mut_value) {
+        // Validate the new stream ID against the last one, even if that entry is gone
+        if !stream_id.is_valid(&stream.last_id()) {
+            return "-ERR The ID specified in XADD is equal or smaller than the target stream top item\r\n".to_string();
+        }
+        stream.insert(stream_id, entry);
+        if let Some(trim) = &options.trim {
+            trim_stream(stream, trim);
         }
     } else {
         //create a new stream if it doesn't exist
-        let mut stream = BTreeMap::new();
-        stream.insert(stream_id.clone(), entry);
+        let mut stream = Stream::new();
+        stream.insert(stream_id, entry);
+        if let Some(trim) = &options.trim {
+            trim_stream(&mut stream, trim);
+        }
         db.insert(stream_key.clone(), RedisValue::new(stream, None));
     }
 
+    // Wake any XREAD blocked on this stream
+    db.notify_stream(stream_key);
+    db.notify_keyspace_event('t', "xadd", stream_key);
+
     // Return the stream_id as a RESP bulk string
     format!("${}\r\n{}\r\n", stream_id.to_string().len(), stream_id)
 }
 
+// Options that can appear between the key and the ID of an XADD
+struct XaddOptions {
+    trim: Option<StreamTrim>,
+}
+
+impl XaddOptions {
+    // Parse the options after the key, returning them along with the index of the ID argument
+    fn parse(args: &[String]) -> Result<(Self, usize), String> {
+        let mut options = XaddOptions { trim: None };
+        let mut i = 1;
+        while i < args.len() && (args[i].eq_ignore_ascii_case("MAXLEN") || args[i].eq_ignore_ascii_case("MINID")) {
+            let (trim, consumed) = StreamTrim::parse(&args[i..])?;
+            options.trim = Some(trim);
+            i += consumed;
+        }
+        Ok((options, i))
+    }
+}
+
+// Handle the XLEN command
+pub async fn handle_xlen(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() != 1 {
+        return "-ERR wrong number of arguments for 'xlen' command\r\n".to_string();
+    }
+
+    let db = read_live_keys(db, &args[..1]).await;
+    match db.get(&args[0]).map(|redis_value| redis_value.get_value()) {
+        Some(RedisValueType::StreamValue(stream)) => format!(":{}\r\n", stream.len()),
+        Some(_) => WRONGTYPE_ERROR.to_string(),
+        None => ":0\r\n".to_string(),
+    }
+}
+
+// Handle the XDEL command
+pub async fn handle_xdel(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() < 2 {
+        return "-ERR wrong number of arguments for 'xdel' command\r\n".to_string();
+    }
+    let mut ids = Vec::new();
+    for id_str in &args[1..] {
+        match StreamID::parse(id_str) {
+            Some(id) => ids.push(id),
+            None => return "-ERR Invalid stream ID specified as stream command argument\r\n".to_string(),
+        }
+    }
+
+    let mut db = db.write().await;
+    db.remove_if_expired(&args[0]);
+    match db.get_mut(&args[0]).map(|redis_value| redis_value.get_mut_value()) {
+        Some(RedisValueType::StreamValue(stream)) => {
+            let deleted = ids.iter().filter(|id| stream.remove(id)).count();
+            if deleted > 0 {
+                db.notify_keyspace_event('t', "xdel", &args[0]);
+            }
+            format!(":{}\r\n", deleted)
+        }
+        Some(_) => WRONGTYPE_ERROR.to_string(),
+        None => ":0\r\n".to_string(),
+    }
+}
+
+// Handle the XTRIM command
+pub async fn handle_xtrim(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() < 3 {
+        return "-ERR wrong number of arguments for 'xtrim' command\r\n".to_string();
+    }
+    if !args[1].eq_ignore_ascii_case("MAXLEN") && !args[1].eq_ignore_ascii_case("MINID") {
+        return "-ERR syntax error\r\n".to_string();
+    }
+    let trim = match StreamTrim::parse(&args[1..]) {
+        Ok((trim, consumed)) if consumed == args.len() - 1 => trim,
+        Ok(_) => return "-ERR syntax error\r\n".to_string(),
+        Err(error) => return error,
+    };
+
+    let mut db = db.write().await;
+    db.remove_if_expired(&args[0]);
+    match db.get_mut(&args[0]).map(|redis_value| redis_value.get_mut_value()) {
+        Some(RedisValueType::StreamValue(stream)) => {
+            let evicted = trim_stream(stream, &trim);
+            if evicted > 0 {
+                db.notify_keyspace_event('t', "xtrim", &args[0]);
+            }
+            format!(":{}\r\n", evicted)
+        }
+        Some(_) => WRONGTYPE_ERROR.to_string(),
+        None => ":0\r\n".to_string(),
+    }
+}
+
+// How XADD and XTRIM trim a stream
+enum StreamTrim {
+    MaxLen(usize), // Keep at most this many of the newest entries
+    MinId(StreamID), // Drop the entries with a lower ID
+}
+
+impl StreamTrim {
+    // Parse `MAXLEN|MINID [=|~] threshold` at the start of `args`, returning it and how many
+    // arguments it took up. The approximate `~` form is treated as exact.
+    fn parse(args: &[String]) -> Result<(Self, usize), String> {
+        let (threshold, consumed) = match args {
+            [_, modifier, threshold, ..] if modifier == "~" || modifier == "=" => (threshold, 3),
+            [_, threshold, ..] => (threshold, 2),
+            _ => return Err("-ERR syntax error\r\n".to_string()),
+        };
+        if args[0].eq_ignore_ascii_case("MINID") {
+            // A bare millisecond time stands for its first ID, as in Redis
+            let min_id = StreamID::parse(threshold).or_else(|| {
+                threshold.parse::<u64>().ok().map(|milliseconds_time| StreamID { milliseconds_time, sequence_number: 0 })
+            });
+            return match min_id {
+                Some(min_id) => Ok((StreamTrim::MinId(min_id), consumed)),
+                None => Err("-ERR Invalid stream ID specified as stream command argument\r\n".to_string()),
+            };
+        }
+        match threshold.parse::<usize>() {
+            Ok(max_len) => Ok((StreamTrim::MaxLen(max_len), consumed)),
+            Err(_) => Err("-ERR value is not an integer or out of range\r\n".to_string()),
+        }
+    }
+}
+
+// Evict the oldest entries until the stream meets `trim`, returning how many were evicted
+fn trim_stream(stream: &mut Stream, trim: &StreamTrim) -> usize {
+    let mut evicted = 0;
+    while let Some((oldest, _)) = stream.entries().first_key_value() {
+        let keep = match trim {
+            StreamTrim::MaxLen(max_len) => stream.len() <= *max_len,
+            StreamTrim::MinId(min_id) => oldest >= min_id,
+        };
+        if keep {
+            break;
+        }
+        stream.pop_first();
+        evicted += 1;
+    }
+    evicted
+}
+
 // Handle the XRANGE command
-pub async fn handle_xrange(db: &Arc<Mutex<RedisDatabase>>, args: &[String]) -> String {
+pub async fn handle_xrange(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
     // Check if we have the correct number of arguments
     if args.len() < 3 {
         return "-ERR wrong number of arguments for 'xrange' command\r\n".to_string();
@@ -179,275 +1672,857 @@ pub async fn handle_xrange(db: &Arc<Mutex<RedisDatabase>>, args: &[String]) -> S
 
     // Step 1: Retrieve the stream from the database
     let stream_key = &args[0];
-    let db = db.lock().await;
-    let redis_value = match db.get(stream_key) {
-        Some(value) => value,
-        None => return "-ERR no such key\r\n".to_string(),
+    let db = read_live_keys(db, &args[..1]).await;
+    let stream = match db.get(stream_key).map(|redis_value| redis_value.get_value()) {
+        Some(RedisValueType::StreamValue(stream)) => stream,
+        Some(_) => return WRONGTYPE_ERROR.to_string(),
+        None => return "*0\r\n".to_string(),
     };
 
-    // Ensure that the value is a stream
-    let stream = if let RedisValueType::StreamValue(ref stream) = redis_value.get_value() {
-        stream
-    } else {
-        return "-ERR wrong type for 'xrange' command\r\n".to_string();
+    // Step 2: Parse start and end StreamIDs ("-" and "+" stand for the lowest and highest IDs)
+    let start_id = match parse_stream_range_bound(&args[1]) {
+        Some(id) => id,
+        None => return "-ERR invalid start StreamID\r\n".to_string(),
+    };
+    let end_id = match parse_stream_range_bound(&args[2]) {
+        Some(id) => id,
+        None => return "-ERR invalid end StreamID\r\n".to_string(),
+    };
+    let count = match parse_stream_count(&args[3..]) {
+        Ok(count) => count,
+        Err(error) => return error,
     };
 
-    // Step 2: Parse start and end StreamIDs
-    let start_id_str = &args[1];
-    let end_id_str = &args[2];
+    // Step 3: Collect entries between start_id and end_id, capped at COUNT if given
+    if start_id > end_id {
+        return "*0\r\n".to_string();
+    }
+    stream_entries_reply(stream.entries().range(start_id..=end_id).take(count.unwrap_or(usize::MAX)))
+}
 
-    // Parse start StreamID (default to lowest if "-")
-    let start_id = if start_id_str == "-" {
-        StreamID::zero()  // Start from the minimum StreamID
-    } else {
-        match StreamID::from_str(start_id_str) {
-            Some(id) => id,
-            None => return "-ERR invalid start StreamID\r\n".to_string(),
-        }
+// Handle the XREVRANGE command
+pub async fn handle_xrevrange(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() < 3 {
+        return "-ERR wrong number of arguments for 'xrevrange' command\r\n".to_string();
+    }
+    // XREVRANGE takes its bounds in the opposite order to XRANGE: end first, then start
+    let (end_id, start_id) = match (parse_stream_range_bound(&args[1]), parse_stream_range_bound(&args[2])) {
+        (Some(end_id), Some(start_id)) => (end_id, start_id),
+        _ => return "-ERR Invalid stream ID specified as stream command argument\r\n".to_string(),
     };
+    let count = match parse_stream_count(&args[3..]) {
+        Ok(count) => count,
+        Err(error) => return error,
+    };
+
+    let db = read_live_keys(db, &args[..1]).await;
+    let stream = match db.get(&args[0]).map(|redis_value| redis_value.get_value()) {
+        Some(RedisValueType::StreamValue(stream)) => stream,
+        Some(_) => return WRONGTYPE_ERROR.to_string(),
+        None => return "*0\r\n".to_string(),
+    };
+
+    if start_id > end_id {
+        return "*0\r\n".to_string();
+    }
+    stream_entries_reply(stream.entries().range(start_id..=end_id).rev().take(count.unwrap_or(usize::MAX)))
+}
 
-    // Parse end StreamID (default to highest if "+")
-    let end_id = if end_id_str == "+" {
-        StreamID {
+// Parse an XRANGE/XREVRANGE bound, where "-" and "+" stand for the lowest and highest possible IDs
+fn parse_stream_range_bound(arg: &str) -> Option<StreamID> {
+    match arg {
+        "-" => Some(StreamID::zero()),
+        "+" => Some(StreamID {
             milliseconds_time: u64::MAX,
             sequence_number: u64::MAX,
+        }),
+        _ => StreamID::parse(arg),
+    }
+}
+
+// Parse the optional trailing `COUNT n` of XRANGE/XREVRANGE; None means no limit
+fn parse_stream_count(args: &[String]) -> Result<Option<usize>, String> {
+    match args {
+        [] => Ok(None),
+        [option, count] if option.eq_ignore_ascii_case("COUNT") => match count.parse::<usize>() {
+            Ok(count) => Ok(Some(count)),
+            Err(_) => Err("-ERR value is not an integer or out of range\r\n".to_string()),
+        },
+        _ => Err("-ERR syntax error\r\n".to_string()),
+    }
+}
+
+// Encode stream entries as a RESP array of [id, [field, value, ...]] pairs
+fn stream_entries_reply<'a>(entries: impl Iterator<Item = (&'a StreamID, &'a HashMap<String, String>)>) -> String {
+    let mut entries_data = String::new();
+    let mut entry_count = 0;
+
+    for (stream_id, entry) in entries {
+        entries_data.push_str(&stream_entry_reply(stream_id, entry));
+        entry_count += 1;
+    }
+
+    let mut result = format!("*{}\r\n", entry_count); // Start with the total count
+    result.push_str(&entries_data); // Append all the entries
+    result
+}
+
+// Encode a single stream entry as [id, [field, value, ...]]
+fn stream_entry_reply(stream_id: &StreamID, entry: &HashMap<String, String>) -> String {
+    // Outer array for the stream entry: *2 (ID and key-value pairs)
+    let mut result = "*2\r\n".to_string();
+
+    // StreamID part: $<length>\r\n<stream_id>\r\n
+    let stream_id_str = stream_id.to_string();
+    result.push_str(&format!("${}\r\n{}\r\n", stream_id_str.len(), stream_id_str));
+
+    // Inner array for the key-value pairs: *<number of key-value pairs * 2>
+    result.push_str(&format!("*{}\r\n", entry.len() * 2));
+
+    // Append each field and value
+    for (field, value) in entry {
+        result.push_str(&format!("${}\r\n{}\r\n", field.len(), field));
+        result.push_str(&format!("${}\r\n{}\r\n", value.len(), value));
+    }
+    result
+}
+
+// Handle the XINFO command (only the STREAM subcommand is supported)
+pub async fn handle_xinfo(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.is_empty() {
+        return "-ERR wrong number of arguments for 'xinfo' command\r\n".to_string();
+    }
+    if !args[0].eq_ignore_ascii_case("STREAM") {
+        return format!("-ERR unknown subcommand '{}'. Try XINFO HELP.\r\n", args[0]);
+    }
+    if args.len() != 2 {
+        return "-ERR wrong number of arguments for 'xinfo|stream' command\r\n".to_string();
+    }
+
+    let db = read_live_keys(db, &args[1..2]).await;
+    let stream = match db.get(&args[1]).map(|redis_value| redis_value.get_value()) {
+        Some(RedisValueType::StreamValue(stream)) => stream,
+        Some(_) => return WRONGTYPE_ERROR.to_string(),
+        None => return "-ERR no such key\r\n".to_string(),
+    };
+
+    let entry_or_nil = |entry: Option<(&StreamID, &HashMap<String, String>)>| {
+        entry.map_or("$-1\r\n".to_string(), |(stream_id, entry)| stream_entry_reply(stream_id, entry))
+    };
+
+    let mut response = "*8\r\n".to_string();
+    response.push_str(&bulk_string("length"));
+    response.push_str(&format!(":{}\r\n", stream.len()));
+    response.push_str(&bulk_string("last-generated-id"));
+    response.push_str(&bulk_string(&stream.last_id().to_string()));
+    response.push_str(&bulk_string("first-entry"));
+    response.push_str(&entry_or_nil(stream.entries().iter().next()));
+    response.push_str(&bulk_string("last-entry"));
+    response.push_str(&entry_or_nil(stream.entries().iter().next_back()));
+    response
+}
+
+pub async fn handle_xread(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    
+    // Parse the BLOCK and COUNT options, which may come in either order before STREAMS
+    let mut is_blocking = false;
+    let mut empty_reply = "*0\r\n";
+    let mut wait_time_ms = 0;
+    let mut count = usize::MAX;
+    let mut args_start = 0;
+    while args_start + 1 < args.len() {
+        match args[args_start].to_uppercase().as_str() {
+            "BLOCK" => {
+                wait_time_ms = match args[args_start + 1].parse::<u64>() {
+                    Ok(ms) => ms,
+                    Err(_) => return "-ERR invalid blocking timeout\r\n".to_string(),
+                };
+                println!("blocking with wait time: {}", wait_time_ms);
+                is_blocking = blocking_allowed();
+                empty_reply = "$-1\r\n"; // What a read that timed out gets
+            }
+            "COUNT" => {
+                count = match args[args_start + 1].parse::<usize>() {
+                    Ok(0) => usize::MAX, // As in Redis, COUNT 0 means no limit
+                    Ok(count) => count,
+                    Err(_) => return "-ERR value is not an integer or out of range\r\n".to_string(),
+                };
+            }
+            _ => break,
+        }
+        args_start += 2;
+    }
+
+    // Ensure the next argument is "STREAMS"
+    if !args.get(args_start).is_some_and(|arg| arg.eq_ignore_ascii_case("STREAMS")) {
+        return "-ERR missing 'STREAMS' argument\r\n".to_string();
+    }
+
+    let num_streams = (args.len() - (args_start + 1)) / 2; // Calculate the number of stream-key/start-id pairs
+    if args.len() < (args_start + 3) || !(args.len() - (args_start + 1)).is_multiple_of(2) {
+        return "-ERR wrong number of arguments for 'xread' command\r\n".to_string();
+    }
+
+    let mut result = String::new();
+    let mut total_streams_with_entries = 0; // Track streams with entries
+
+    // Buffer to store all the streams' data
+    let mut streams_data = String::new();
+
+    // We need to resolve the correct start ID for all streams **before** entering the loop.
+    // None means "every entry": the stream was missing when `$` was resolved.
+    // All IDs are resolved under one lock so `$` is a single snapshot taken at call time.
+    let stream_keys = &args[args_start + 1..=args_start + num_streams];
+    let mut stream_start_ids: Vec<(String, Option<StreamID>)> = Vec::new();
+    {
+        let db = read_live_keys(db, stream_keys).await;
+        for i in 1..=num_streams {
+            let stream_key = &args[args_start + i];
+            let start_id_str = &args[args_start + num_streams + i];
+
+            // Retrieve the last ID the stream was given, if it exists
+            let last_id = match db.get(stream_key).map(|redis_value| redis_value.get_value()) {
+                Some(RedisValueType::StreamValue(stream)) => Some(stream.last_id()),
+                Some(_) => return format!("-ERR key '{}' is not a stream\r\n", stream_key),
+                None => None, // A missing stream may still be created while we block
+            };
+
+            // Resolve the start ID:
+            // - If the start ID is `$`, only entries added after this call count.
+            // - Otherwise, parse the provided start ID as normal.
+            let start_id = if start_id_str == "$" {
+                last_id
+            } else {
+                // Parse the start StreamID (exclusive)
+                match StreamID::parse(start_id_str) {
+                    Some(id) => Some(id),
+                    None => return format!("-ERR invalid StreamID '{}'\r\n", start_id_str),
+                }
+            };
+
+            // Store the resolved start ID for each stream
+            stream_start_ids.push((stream_key.clone(), start_id));
         }
+    }
+    
+    // XADD wakes these, so a blocked read doesn't have to poll the database. Only a read that
+    // may block needs them.
+    let notifiers: Vec<Arc<Notify>> = if is_blocking {
+        let mut db = db.write().await;
+        stream_start_ids.iter().map(|(stream_key, _)| db.stream_notifier(stream_key)).collect()
     } else {
-        match StreamID::from_str(end_id_str) {
-            Some(id) => id,
-            None => return "-ERR invalid end StreamID\r\n".to_string(),
+        Vec::new()
+    };
+
+    // This is the async block for handling blocking logic and timeout
+    let blocking_task = async {
+        loop {
+            // Register for wakeups before looking at the streams so an XADD in between isn't missed
+            let mut wakeups: Vec<_> = notifiers.iter().map(|notifier| Box::pin(notifier.notified())).collect();
+            for wakeup in &mut wakeups {
+                wakeup.as_mut().enable();
+            }
+
+            // Look at the streams again, sharing the lock with other readers
+            let db_lock = read_live_keys(db, stream_keys).await;
+            for (stream_key, start_id) in &stream_start_ids {
+                let redis_value = match db_lock.get(stream_key) {
+                    Some(value) => value,
+                    None => continue, // Skip if the key does not exist
+                };
+
+                // Ensure that the value is a stream
+                let stream = if let RedisValueType::StreamValue(ref stream) = redis_value.get_value() {
+                    stream
+                } else {
+                    return format!("-ERR key '{}' is not a stream\r\n", stream_key);
+                };
+
+                // Collect up to COUNT entries strictly larger than start_id
+                let lower_bound = match start_id {
+                    Some(start_id) => Bound::Excluded(*start_id),
+                    None => Bound::Unbounded,
+                };
+                let mut stream_entries = stream
+                    .entries()
+                    .range((lower_bound, Bound::Unbounded))
+                    .take(count)
+                    .peekable();
+
+                // If we collected any entries for this stream, add to the final result
+                if stream_entries.peek().is_some() {
+                    total_streams_with_entries += 1;
+
+                    // First, append the stream key and then append all collected entries
+                    streams_data.push_str("*2\r\n");
+                    streams_data.push_str(&format!("${}\r\n{}\r\n", stream_key.len(), stream_key));
+                    streams_data.push_str(&stream_entries_reply(stream_entries));
+                }
+            }
+            drop(db_lock);
+            // If entries were found, return the result
+            if total_streams_with_entries > 0 {
+                result.push_str(&format!("*{}\r\n", total_streams_with_entries)); // Number of streams with entries
+                result.push_str(&streams_data); // Append the stream data
+                return result;
+            }
+
+            // Step 4: If no entries were collected from any stream and it's not in blocking mode, return an empty array
+            if !is_blocking {
+                return empty_reply.to_string();
+            }
+
+            // Wait until one of the streams gets a new entry, then check again
+            poll_fn(|cx| {
+                if wakeups.iter_mut().any(|wakeup| wakeup.as_mut().poll(cx).is_ready()) {
+                    Poll::Ready(())
+                } else {
+                    Poll::Pending
+                }
+            })
+            .await;
         }
     };
 
-    // Step 3: Collect entries between start_id and end_id
-    let mut entries = String::new();
-    let mut entry_count = 0;
+    // Step 5: Handle timeout for blocking mode
+    if !is_blocking {
+        return blocking_task.await; // If not blocking, just run the task normally
+    }
+    let reply = if wait_time_ms == 0 {
+        // BLOCK 0: Wait indefinitely
+        blocking_task.await
+    } else {
+        // Apply the timeout only if wait_time_ms > 0
+        match timeout(Duration::from_millis(wait_time_ms), blocking_task).await {
+            Ok(result) => {println!("data found within timeout: {}", result); result}, // Return the result if data is found within the timeout
+            Err(_) => {println!("timeout expired"); "$-1\r\n".to_string()}, // Timeout expired, return null bulk string
+        }
+    };
+    // Done waiting, so let go of the notifiers and drop those no other reader is waiting on
+    drop(notifiers);
+    db.write().await.release_stream_notifiers(stream_keys);
+    reply
+}
+
+// Handle the KEYS command
+pub async fn handle_keys(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() != 1 {
+        return "-ERR wrong number of arguments for 'keys' command\r\n".to_string();
+    }
+    let pattern = args[0].as_bytes();
+    let db = db.read().await;
+    let keys: Vec<&String> = db
+        .data()
+        .iter()
+        .filter(|(key, redis_value)| !redis_value.is_expired() && glob_match(pattern, key.as_bytes()))
+        .map(|(key, _)| key)
+        .collect();
+    let mut response = format!("*{}\r\n", keys.len());
+    for key in keys {
+        response.push_str(&format!("${}\r\n{}\r\n", key.len(), key));
+    }
+    response
+}
+
+// Handle the SCAN command. Keys are visited by their position in the keyspace from the last one
+// down, and the cursor is the position to continue below. A removal only moves the last entry into
+// the freed position, so no entry still to visit is moved past the cursor: a key present for the
+// whole scan is returned no matter what else changes, though possibly more than once.
+pub async fn handle_scan(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.is_empty() {
+        return "-ERR wrong number of arguments for 'scan' command\r\n".to_string();
+    }
+    let cursor = match args[0].parse::<u64>() {
+        Ok(cursor) => cursor,
+        Err(_) => return "-ERR invalid cursor\r\n".to_string(),
+    };
+    let mut pattern: Option<&str> = None;
+    let mut count = 10;
+    for option in args[1..].chunks(2) {
+        match option {
+            [name, value] if name.eq_ignore_ascii_case("MATCH") => pattern = Some(value),
+            [name, value] if name.eq_ignore_ascii_case("COUNT") => {
+                count = match value.parse::<usize>() {
+                    Ok(count) if count > 0 => count,
+                    Ok(_) => return "-ERR syntax error\r\n".to_string(),
+                    Err(_) => return "-ERR value is not an integer or out of range\r\n".to_string(),
+                }
+            }
+            _ => return "-ERR syntax error\r\n".to_string(),
+        }
+    }
+
+    let db = db.read().await;
+    let data = db.data();
+    // Cursor 0 starts a new scan; deletions since the last call may have left it past the end
+    let start = if cursor == 0 { data.len() } else { (cursor as usize).min(data.len()) };
+    let end = start.saturating_sub(count);
+
+    // As in Redis, MATCH filters the batch after it is picked, so a batch may come back empty
+    let keys: Vec<&String> = (end..start)
+        .rev()
+        .filter_map(|position| data.entry_at(position))
+        .filter(|(key, redis_value)| !redis_value.is_expired() && pattern.is_none_or(|pattern| glob_match(pattern.as_bytes(), key.as_bytes())))
+        .map(|(key, _)| key)
+        .collect();
+    // Cursor 0 again once every position has been visited
+    format!("*2\r\n{}{}", bulk_string(&end.to_string()), bulk_string_array(keys.into_iter()))
+}
+
+// Handle the RANDOMKEY command
+pub async fn handle_randomkey(db: &Arc<RwLock<RedisDatabase>>) -> String {
+    let db = db.read().await;
+    let live_keys: Vec<&String> = db
+        .data()
+        .iter()
+        .filter(|(_, redis_value)| !redis_value.is_expired())
+        .map(|(key, _)| key)
+        .collect();
+    if live_keys.is_empty() {
+        return "$-1\r\n".to_string();
+    }
+    bulk_string(live_keys[(random_u64() % live_keys.len() as u64) as usize])
+}
+
+// Handle the RENAME command
+pub async fn handle_rename(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() != 2 {
+        return "-ERR wrong number of arguments for 'rename' command\r\n".to_string();
+    }
+    match rename_key(db, &args[0], &args[1], false).await {
+        Ok(_) => "+OK\r\n".to_string(),
+        Err(error) => error,
+    }
+}
 
-    for (stream_id, entry) in stream.range(start_id..=end_id) {
-        // Outer array for each stream entry: *2 (ID and key-value pairs)
-        entries.push_str("*2\r\n");
+// Handle the RENAMENX command
+pub async fn handle_renamenx(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() != 2 {
+        return "-ERR wrong number of arguments for 'renamenx' command\r\n".to_string();
+    }
+    match rename_key(db, &args[0], &args[1], true).await {
+        Ok(renamed) => format!(":{}\r\n", renamed as u8),
+        Err(error) => error,
+    }
+}
 
-        // StreamID part: $<length>\r\n<stream_id>\r\n
-        let stream_id_str = stream_id.to_string();
-        entries.push_str(&format!("${}\r\n{}\r\n", stream_id_str.len(), stream_id_str));
+// Shared by RENAME and RENAMENX. The RedisValue is moved as a whole, so its TTL keeps counting
+// down from the original creation time. Returns whether the key was renamed.
+async fn rename_key(db: &Arc<RwLock<RedisDatabase>>, source: &str, destination: &str, only_if_absent: bool) -> Result<bool, String> {
+    let mut db = db.write().await;
+    db.remove_if_expired(source);
+    db.remove_if_expired(destination);
+    if db.get(source).is_none() {
+        return Err("-ERR no such key\r\n".to_string());
+    }
+    if source == destination {
+        return Ok(!only_if_absent);
+    }
+    if only_if_absent && db.get(destination).is_some() {
+        return Ok(false);
+    }
+    if let Some(redis_value) = db.remove(source) {
+        db.insert(destination.to_string(), redis_value);
+        db.notify_keyspace_event('g', "rename_from", source);
+        db.notify_keyspace_event('g', "rename_to", destination);
+    }
+    Ok(true)
+}
 
-        // Inner array for the key-value pairs: *<number of key-value pairs * 2>
-        entries.push_str(&format!("*{}\r\n", entry.len() * 2));
+// Handle the COPY command
+pub async fn handle_copy(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    let replace = match args.len() {
+        2 => false,
+        3 if args[2].eq_ignore_ascii_case("REPLACE") => true,
+        3 => return "-ERR syntax error\r\n".to_string(),
+        _ => return "-ERR wrong number of arguments for 'copy' command\r\n".to_string(),
+    };
+    let (source, destination) = (&args[0], &args[1]);
+    if source == destination {
+        return "-ERR source and destination objects are the same\r\n".to_string();
+    }
 
-        // Append each field and value
-        for (field, value) in entry {
-            entries.push_str(&format!("${}\r\n{}\r\n", field.len(), field));
-            entries.push_str(&format!("${}\r\n{}\r\n", value.len(), value));
+    let mut db = db.write().await;
+    db.remove_if_expired(source);
+    db.remove_if_expired(destination);
+    if !replace && db.get(destination).is_some() {
+        return ":0\r\n".to_string();
+    }
+    // The clone shares the source's creation time and TTL state, so both expire together
+    match db.get(source).cloned() {
+        Some(redis_value) => {
+            db.insert(destination.clone(), redis_value);
+            db.notify_keyspace_event('g', "copy_to", destination);
+            ":1\r\n".to_string()
         }
-
-        entry_count += 1;
+        None => ":0\r\n".to_string(),
     }
+}
 
-    // If no entries are found, return empty RESP array
-    if entry_count == 0 {
-        return "*0\r\n".to_string();
+// Parse a database index as SELECT, SWAPDB and MOVE take it
+fn parse_db_index(arg: &str) -> Result<usize, String> {
+    match arg.parse::<i64>() {
+        Ok(index) if (0..DATABASE_COUNT as i64).contains(&index) => Ok(index as usize),
+        Ok(_) => Err("-ERR DB index is out of range\r\n".to_string()),
+        Err(_) => Err("-ERR value is not an integer or out of range\r\n".to_string()),
     }
-    let mut result = format!("*{}\r\n", entry_count); // Start with the total count
-    result.push_str(&entries); // Append all the entries
+}
 
-    //println!("xrange result: {}", result);
-    result
+// Handle the SELECT command
+pub fn handle_select(args: &[String], client_state: &mut ClientState) -> String {
+    if args.len() != 1 {
+        return "-ERR wrong number of arguments for 'select' command\r\n".to_string();
+    }
+    match parse_db_index(&args[0]) {
+        Ok(index) => {
+            client_state.select(index);
+            "+OK\r\n".to_string()
+        }
+        Err(e) => e,
+    }
 }
 
-pub async fn handle_xread(db: &Arc<Mutex<RedisDatabase>>, args: &[String]) -> String {
-    
-    // Check if blocking mode is enabled
-    let (is_blocking, wait_time_ms, args_start) = if args[0].to_uppercase() == "BLOCK" {
-        let wait_time_ms = match args[1].parse::<u64>() {
-            Ok(ms) => ms,
-            Err(_) => return "-ERR invalid blocking timeout\r\n".to_string(),
-        };
-        println!("blocking with wait time: {}", wait_time_ms);
-        (true, wait_time_ms, 2)
-    } else {
-        (false, 0, 0)
+// Handle the SWAPDB command
+pub async fn handle_swapdb(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() != 2 {
+        return "-ERR wrong number of arguments for 'swapdb' command\r\n".to_string();
+    }
+    let (first, second) = match (parse_db_index(&args[0]), parse_db_index(&args[1])) {
+        (Ok(first), Ok(second)) => (first, second),
+        (Err(e), _) | (_, Err(e)) => return e,
     };
+    // Connections keep their index, so each now sees the other database's keys
+    db.write().await.databases_mut().swap(first, second);
+    "+OK\r\n".to_string()
+}
 
-    // Ensure the next argument is "STREAMS"
-    if args[args_start].to_uppercase() != "STREAMS" {
-        return "-ERR missing 'STREAMS' argument\r\n".to_string();
+// Handle the MOVE command
+pub async fn handle_move(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() != 2 {
+        return "-ERR wrong number of arguments for 'move' command\r\n".to_string();
     }
-
-    let num_streams = (args.len() - (args_start + 1)) / 2; // Calculate the number of stream-key/start-id pairs
-    if args.len() < (args_start + 3) || (args.len() - (args_start + 1)) % 2 != 0 {
-        return "-ERR wrong number of arguments for 'xread' command\r\n".to_string();
+    let destination = match parse_db_index(&args[1]) {
+        Ok(index) => index,
+        Err(e) => return e,
+    };
+    if destination == selected_db() {
+        return "-ERR source and destination objects are the same\r\n".to_string();
     }
 
-    let mut result = String::new();
-    let mut total_streams_with_entries = 0; // Track streams with entries
-
-    // Buffer to store all the streams' data
-    let mut streams_data = String::new();
+    let key = &args[0];
+    let mut db = db.write().await;
+    db.remove_if_expired(key);
+    if db.get(key).is_none() {
+        return ":0\r\n".to_string();
+    }
+    let target = db.database_mut(destination);
+    if target.get(key).is_some_and(|redis_value| !redis_value.is_expired()) {
+        return ":0\r\n".to_string();
+    }
+    // The TTL moves along with the value
+    if let Some(redis_value) = db.remove(key) {
+        db.database_mut(destination).insert(key.clone(), redis_value);
+        db.notify_keyspace_event('g', "move_from", key);
+        db.publish_keyspace_event(destination, 'g', "move_to", key);
+    }
+    ":1\r\n".to_string()
+}
 
-    // We need to resolve the correct start ID for all streams **before** entering the loop
-    let mut stream_start_ids: Vec<(String, StreamID)> = Vec::new();
+// Handle the DBSIZE command
+pub async fn handle_dbsize(db: &Arc<RwLock<RedisDatabase>>) -> String {
+    let db = db.read().await;
+    // Keys past their TTL may not have been removed yet, so leave them out of the count
+    let live_keys = db.data().values().filter(|redis_value| !redis_value.is_expired()).count();
+    format!(":{}\r\n", live_keys)
+}
 
-    for i in 1..=num_streams {
-        let stream_key = &args[args_start + i];
-        let start_id_str = &args[args_start + num_streams + i];
+// Handle the FLUSHDB command
+pub async fn handle_flushdb(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    flush(db, args, "flushdb").await
+}
 
-        // Retrieve the stream from the database
-        let db = db.lock().await;
-        let redis_value = match db.get(stream_key) {
-            Some(value) => value,
-            None => continue, // Skip if the key does not exist
-        };
+// Handle the FLUSHALL command
+pub async fn handle_flushall(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    flush(db, args, "flushall").await
+}
 
-        // Ensure that the value is a stream
-        let stream = if let RedisValueType::StreamValue(ref stream) = redis_value.get_value() {
-            stream
+// Shared by FLUSHDB (selected database) and FLUSHALL (every database). ASYNC and SYNC are accepted, but the clear always happens inline.
+async fn flush(db: &Arc<RwLock<RedisDatabase>>, args: &[String], command: &str) -> String {
+    match args {
+        [] => {}
+        [mode] if mode.eq_ignore_ascii_case("ASYNC") || mode.eq_ignore_ascii_case("SYNC") => {}
+        [_] => return "-ERR syntax error\r\n".to_string(),
+        _ => return format!("-ERR wrong number of arguments for '{}' command\r\n", command),
+    }
+    let mut db = db.write().await;
+    let flushed = if command == "flushall" { 0..DATABASE_COUNT } else { selected_db()..selected_db() + 1 };
+    for index in flushed {
+        // Every key flushed is reported as deleted, so only collect them if anyone listens
+        let keys: Vec<String> = if db.keyspace_events_enabled('g') {
+            db.databases()[index].iter().map(|(key, _)| key.clone()).collect()
         } else {
-            return format!("-ERR key '{}' is not a stream\r\n", stream_key);
+            Vec::new()
         };
+        db.database_mut(index).clear();
+        for key in keys {
+            db.publish_keyspace_event(index, 'g', "del", &key);
+        }
+    }
+    "+OK\r\n".to_string()
+}
 
-        // Resolve the start ID:
-        // - If the start ID is `$`, we want to wait for new entries after the last one.
-        // - Otherwise, parse the provided start ID as normal.
-        let start_id = if start_id_str == "$" {
-            // Get the last stream ID if it exists, or default to a value that ensures blocking for new entries
-            match stream.iter().next_back() {
-                Some((last_id, _)) => last_id.clone(),
-                None => StreamID::zero(), // No entries exist yet, so wait for the first entry
+// Handle the CONFIG command
+pub async fn handle_config(db: &Arc<RwLock<RedisDatabase>>, config_map: &SharedConfig, args: &[String]) -> String {
+    match args.first().map(|subcommand| subcommand.to_uppercase()).as_deref() {
+        // Each argument is a glob; parameters matched by several patterns are only returned once
+        Some("GET") if args.len() >= 2 => {
+            let patterns: Vec<String> = args[1..].iter().map(|pattern| pattern.to_lowercase()).collect();
+            let config_map = config_map.read().await;
+            let matches: BTreeMap<&String, &String> = config_map
+                .iter()
+                .filter(|(name, _)| patterns.iter().any(|pattern| glob_match(pattern.as_bytes(), name.as_bytes())))
+                .collect();
+            let mut response = map_header(matches.len());
+            for (name, value) in matches {
+                response.push_str(&bulk_string(name));
+                response.push_str(&bulk_string(value));
             }
-        } else {
-            // Parse the start StreamID (exclusive)
-            match StreamID::from_str(start_id_str) {
-                Some(id) => id,
-                None => return format!("-ERR invalid StreamID '{}'\r\n", start_id_str),
+            response
+        }
+        // CONFIG SET takes one or more parameter/value pairs, applied together
+        Some("SET") if args.len() >= 3 && args.len() % 2 == 1 => {
+            let pairs: Vec<(String, &String)> = args[1..].chunks(2).map(|pair| (pair[0].to_lowercase(), &pair[1])).collect();
+            // Every pair is checked before any is applied, so a bad one leaves the config as it was
+            let mut seen = HashSet::new();
+            if let Some((name, _)) = pairs.iter().find(|(name, _)| !seen.insert(name.as_str())) {
+                return format!("-ERR CONFIG SET failed (possibly related to argument '{}') - duplicate parameter\r\n", name);
             }
-        };
+            if let Some((name, value)) = pairs.iter().find(|(name, value)| !RedisDatabase::is_valid_config(name, value)) {
+                return format!("-ERR Invalid argument '{}' for CONFIG SET '{}'\r\n", value, name);
+            }
+            let mut db = db.write().await;
+            let mut config_map = config_map.write().await;
+            for (name, value) in pairs {
+                if !db.apply_config(&name, value) {
+                    return format!("-ERR Invalid argument '{}' for CONFIG SET '{}'\r\n", value, name);
+                }
+                config_map.insert(name, value.clone());
+            }
+            "+OK\r\n".to_string()
+        }
+        Some("GET") | Some("SET") => format!("-ERR wrong number of arguments for 'config|{}' command\r\n", args[0].to_lowercase()),
+        _ => "-ERR syntax error\r\n".to_string(),
+    }
+}
 
-        // Store the resolved start ID for each stream
-        stream_start_ids.push((stream_key.clone(), start_id));
+// Handle the SAVE command
+pub async fn handle_save(db: &Arc<RwLock<RedisDatabase>>, config_map: &SharedConfig) -> String {
+    match save_rdb(db, config_map).await {
+        Ok(()) => "+OK\r\n".to_string(),
+        Err(e) => format!("-ERR failed to save RDB file: {}\r\n", e),
     }
-    
-    // This is the async block for handling blocking logic and timeout
-    let blocking_task = async {
-        loop {
-            for (stream_key, start_id) in &stream_start_ids {
-                // Retrieve the stream from the database again
-                let db = db.lock().await;
-                let redis_value = match db.get(stream_key) {
-                    Some(value) => value,
-                    None => continue, // Skip if the key does not exist
-                };
+}
 
-                // Ensure that the value is a stream
-                let stream = if let RedisValueType::StreamValue(ref stream) = redis_value.get_value() {
-                    stream
-                } else {
-                    return format!("-ERR key '{}' is not a stream\r\n", stream_key);
-                };
+// Write the dataset to the configured RDB file, blocking other commands meanwhile
+pub async fn save_rdb(db: &Arc<RwLock<RedisDatabase>>, config_map: &SharedConfig) -> std::io::Result<()> {
+    let (path, checksum) = {
+        let config_map = config_map.read().await;
+        (rdb_file_path(&config_map), rdb_checksum_enabled(&config_map))
+    };
+    let db = db.read().await;
+    write_rdb_file(&path, &snapshot(&db), checksum)
+}
 
-                let mut stream_entries = String::new();
-                let mut entry_count = 0;
+// Handle the DEBUG command, with the subcommands test suites use: SLEEP to stand in for a slow
+// command and SET-ACTIVE-EXPIRE to stop or restart the background expiration cycle. Unlike in
+// Redis, SLEEP only holds up its own connection.
+pub async fn handle_debug(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    let Some(subcommand) = args.first() else {
+        return "-ERR wrong number of arguments for 'debug' command\r\n".to_string();
+    };
+    match (subcommand.to_uppercase().as_str(), &args[1..]) {
+        ("SLEEP", [seconds]) => {
+            let Some(duration) = seconds.parse::<f64>().ok().and_then(|seconds| Duration::try_from_secs_f64(seconds).ok()) else {
+                return "-ERR value is not a valid float\r\n".to_string();
+            };
+            tokio::time::sleep(duration).await;
+            "+OK\r\n".to_string()
+        }
+        ("SET-ACTIVE-EXPIRE", [enabled]) => match enabled.parse::<i64>() {
+            Ok(enabled) => {
+                db.write().await.set_active_expire(enabled != 0);
+                "+OK\r\n".to_string()
+            }
+            Err(_) => "-ERR value is not an integer or out of range\r\n".to_string(),
+        },
+        ("SLEEP" | "SET-ACTIVE-EXPIRE", _) => format!("-ERR wrong number of arguments for 'debug|{}' command\r\n", subcommand.to_lowercase()),
+        _ => format!("-ERR unknown subcommand '{}'. Try DEBUG HELP.\r\n", subcommand),
+    }
+}
 
-                // Collect entries strictly larger than start_id
-                for (stream_id, entry) in stream.range(start_id..) {
-                    // Only collect IDs strictly larger than the provided start_id
-                    if !stream_id.is_valid(start_id) {
-                        continue;
-                    }
+// Handle the SHUTDOWN [NOSAVE|SAVE] command. Without an option the dataset is saved when an RDB
+// file is configured. There is no reply on success; the connection closes as the server stops.
+pub async fn handle_shutdown(db: &Arc<RwLock<RedisDatabase>>, config_map: &SharedConfig, args: &[String]) -> String {
+    let save = match args.iter().map(|arg| arg.to_uppercase()).collect::<Vec<_>>().as_slice() {
+        [] => config_map.read().await.contains_key("dbfilename"),
+        [option] if option == "NOSAVE" => false,
+        [option] if option == "SAVE" => true,
+        _ => return "-ERR syntax error\r\n".to_string(),
+    };
+    if save {
+        if let Err(e) = save_rdb(db, config_map).await {
+            eprintln!("Error trying to save the DB before shutting down: {}", e);
+            return "-ERR Errors trying to SHUTDOWN. Check logs.\r\n".to_string();
+        }
+        println!("DB saved on disk");
+    }
+    println!("User requested shutdown...");
+    db.read().await.shutdown_notifier().notify_one();
+    String::new()
+}
 
-                    // Create an array for each stream entry
-                    stream_entries.push_str("*2\r\n");
+// Handle the BGSAVE command
+pub async fn handle_bgsave(db: &Arc<RwLock<RedisDatabase>>, config_map: &SharedConfig) -> String {
+    let (path, checksum) = {
+        let config_map = config_map.read().await;
+        (rdb_file_path(&config_map), rdb_checksum_enabled(&config_map))
+    };
+    // Take the snapshot under the lock so writes after BGSAVE don't end up in the dump
+    let (entries, in_progress) = {
+        let db = db.read().await;
+        let Some(in_progress) = db.start_background_save() else {
+            return "-ERR Background save already in progress\r\n".to_string();
+        };
+        (snapshot(&db), in_progress)
+    };
+    tokio::task::spawn_blocking(move || {
+        match write_rdb_file(&path, &entries, checksum) {
+            Ok(()) => println!("Background saving finished: {}", path.display()),
+            Err(e) => eprintln!("Background saving failed: {}", e),
+        }
+        in_progress.store(false, Ordering::Release);
+    });
+    "+Background saving started\r\n".to_string()
+}
 
-                    // StreamID part: $<length>\r\n<stream_id>\r\n
-                    let stream_id_str = stream_id.to_string();
-                    stream_entries.push_str(&format!("${}\r\n{}\r\n", stream_id_str.len(), stream_id_str));
+// Handle the ECHO command
+pub fn handle_echo(args: &[String]) -> String {
+    if args.len() == 1 {
+        format!("${}\r\n{}\r\n", args[0].len(), args[0])
+    } else {
+        "-ERR wrong number of arguments for 'echo' command\r\n".to_string()
+    }
+}
 
-                    // Inner array for the key-value pairs: *<number of key-value pairs * 2>
-                    stream_entries.push_str(&format!("*{}\r\n", entry.len() * 2));
+// The frame a subscribed connection receives for a published message
+pub fn pubsub_message_frame(message: &PubSubMessage, version: u8) -> Vec<u8> {
+    let mut frame = match &message.pattern {
+        Some(pattern) => resp_push_header(version, 4) + &bulk_string("pmessage") + &bulk_string(pattern),
+        None => resp_push_header(version, 3) + &bulk_string("message"),
+    };
+    frame.push_str(&bulk_string(&message.channel));
+    frame.push_str(&bulk_string(&message.payload));
+    frame.into_bytes()
+}
 
-                    // Append each field and value
-                    for (field, value) in entry {
-                        stream_entries.push_str(&format!("${}\r\n{}\r\n", field.len(), field));
-                        stream_entries.push_str(&format!("${}\r\n{}\r\n", value.len(), value));
-                    }
+// Confirmation of a (un)subscription, with the connection's remaining subscription count
+fn subscription_reply(kind: &str, channel: Option<&str>, count: usize) -> String {
+    let mut reply = resp_push_header(current_resp_version(), 3);
+    reply.push_str(&bulk_string(kind));
+    reply.push_str(&channel.map_or_else(|| "$-1\r\n".to_string(), bulk_string));
+    reply.push_str(&format!(":{}\r\n", count));
+    reply
+}
 
-                    entry_count += 1;
-                }
+// Handle the SUBSCRIBE command
+pub async fn handle_subscribe(db: &Arc<RwLock<RedisDatabase>>, args: &[String], client_state: &mut ClientState) -> String {
+    subscribe_to(db, args, client_state, false, "subscribe").await
+}
 
-                // If we collected any entries for this stream, add to the final result
-                if entry_count > 0 {
-                    total_streams_with_entries += 1;
+// Handle the PSUBSCRIBE command
+pub async fn handle_psubscribe(db: &Arc<RwLock<RedisDatabase>>, args: &[String], client_state: &mut ClientState) -> String {
+    subscribe_to(db, args, client_state, true, "psubscribe").await
+}
 
-                    // First, append the stream key and then append all collected entries
-                    streams_data.push_str("*2\r\n");
-                    streams_data.push_str(&format!("${}\r\n{}\r\n", stream_key.len(), stream_key));
-                    streams_data.push_str(&format!("*{}\r\n", entry_count)); // Number of entries for this stream
-                    streams_data.push_str(&stream_entries);
-                }
-            }
-            // If entries were found, return the result
-            if total_streams_with_entries > 0 {
-                result.push_str(&format!("*{}\r\n", total_streams_with_entries)); // Number of streams with entries
-                result.push_str(&streams_data); // Append the stream data
-                return result;
-            }
+// Shared by SUBSCRIBE (channels) and PSUBSCRIBE (glob patterns)
+async fn subscribe_to(db: &Arc<RwLock<RedisDatabase>>, args: &[String], client_state: &mut ClientState, pattern: bool, command: &str) -> String {
+    if args.is_empty() {
+        return format!("-ERR wrong number of arguments for '{}' command\r\n", command);
+    }
+    // Only client connections can receive messages; the replication link has nowhere to push them
+    let Some(subscriber) = client_state.push_sender() else {
+        return format!("-ERR {} is not allowed on this connection\r\n", command.to_uppercase());
+    };
 
-            // Step 4: If no entries were collected from any stream and it's not in blocking mode, return an empty array
-            if !is_blocking {
-                return "*0\r\n".to_string();
-            }
+    let mut db = db.write().await;
+    let mut response = String::new();
+    for name in args {
+        db.subscribe(name, &subscriber, pattern);
+        client_state.subscriptions_mut(pattern).insert(name.clone());
+        response.push_str(&subscription_reply(command, Some(name), client_state.subscription_count()));
+    }
+    response
+}
 
-            // Sleep for a small period before checking again (you can adjust this depending on how frequently you want to check)
-            tokio::time::sleep(Duration::from_millis(1)).await;
-        }
-    };
+// Handle the UNSUBSCRIBE command. Without arguments it leaves every subscribed channel.
+pub async fn handle_unsubscribe(db: &Arc<RwLock<RedisDatabase>>, args: &[String], client_state: &mut ClientState) -> String {
+    unsubscribe_from(db, args, client_state, false, "unsubscribe").await
+}
 
-    // Step 5: Handle timeout for blocking mode
-    if is_blocking {
-        if wait_time_ms == 0 {
-            // BLOCK 0: Wait indefinitely
-            blocking_task.await
-        } else {
-            // Apply the timeout only if wait_time_ms > 0
-            match timeout(Duration::from_millis(wait_time_ms), blocking_task).await {
-                Ok(result) => {println!("data found within timeout: {}", result); result}, // Return the result if data is found within the timeout
-                Err(_) => {println!("timeout expired"); "$-1\r\n".to_string()}, // Timeout expired, return null bulk string
-            }
-        }
+// Handle the PUNSUBSCRIBE command. Without arguments it leaves every subscribed pattern.
+pub async fn handle_punsubscribe(db: &Arc<RwLock<RedisDatabase>>, args: &[String], client_state: &mut ClientState) -> String {
+    unsubscribe_from(db, args, client_state, true, "punsubscribe").await
+}
+
+// Shared by UNSUBSCRIBE (channels) and PUNSUBSCRIBE (glob patterns)
+async fn unsubscribe_from(db: &Arc<RwLock<RedisDatabase>>, args: &[String], client_state: &mut ClientState, pattern: bool, command: &str) -> String {
+    let names: Vec<String> = if args.is_empty() {
+        client_state.subscriptions(pattern).iter().cloned().collect()
     } else {
-        blocking_task.await // If not blocking, just run the task normally
+        args.to_vec()
+    };
+    if names.is_empty() {
+        return subscription_reply(command, None, client_state.subscription_count());
     }
-}
 
-// Handle the KEYS command
-pub async fn handle_keys(db: &Arc<Mutex<RedisDatabase>>) -> String {
-    let db = db.lock().await;
-    let keys: Vec<&String> = db.data.keys().collect();
-    let mut response = format!("*{}\r\n", keys.len());
-    for key in keys {
-        response.push_str(&format!("${}\r\n{}\r\n", key.len(), key));
+    let mut db = db.write().await;
+    let mut response = String::new();
+    for name in &names {
+        if let Some(subscriber) = client_state.push_sender() {
+            db.unsubscribe(name, &subscriber, pattern);
+        }
+        client_state.subscriptions_mut(pattern).remove(name);
+        response.push_str(&subscription_reply(command, Some(name), client_state.subscription_count()));
     }
     response
 }
 
-// Handle the CONFIG command
-pub fn handle_config(config_map: &HashMap<String, String>, args: &[String]) -> String {
-    if args.len() == 2 && args[0].to_uppercase() == "GET" {
-        if let Some(value) = config_map.get(&args[1]) {
-            format!("*2\r\n${}\r\n{}\r\n${}\r\n{}\r\n", args[1].len(), args[1], value.len(), value)
-        } else {
-            "$-1\r\n".to_string()
-        }
-    } else {
-        "-ERR syntax error\r\n".to_string()
+// Handle the PUBLISH command
+pub async fn handle_publish(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() != 2 {
+        return "-ERR wrong number of arguments for 'publish' command\r\n".to_string();
     }
+    let receivers = db.write().await.publish(&args[0], &args[1]);
+    format!(":{}\r\n", receivers)
 }
 
-// Handle the ECHO command
-pub fn handle_echo(args: &[String]) -> String {
-    if args.len() == 1 {
-        format!("${}\r\n{}\r\n", args[0].len(), args[0])
-    } else {
-        "-ERR wrong number of arguments for 'echo' command\r\n".to_string()
+// Under RESP2 a subscribed connection may only manage its subscriptions and PING. Returns the
+// reply for commands handled differently in that mode, or None to run the command as usual.
+pub fn subscribe_mode_reply(command: &str, args: &[String]) -> Option<String> {
+    match command {
+        "SUBSCRIBE" | "UNSUBSCRIBE" | "PSUBSCRIBE" | "PUNSUBSCRIBE" | "QUIT" | "RESET" => None,
+        // PING replies as a pub/sub frame so it can be told apart from messages
+        "PING" if args.len() <= 1 => {
+            let mut reply = resp_push_header(2, 2);
+            reply.push_str(&bulk_string("pong"));
+            reply.push_str(&bulk_string(args.first().map_or("", String::as_str)));
+            Some(reply)
+        }
+        "PING" => None,
+        _ => Some(format!(
+            "-ERR Can't execute '{}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context\r\n",
+            command.to_lowercase()
+        )),
     }
 }
 
@@ -463,8 +2538,8 @@ pub fn handle_ping(args: &[String]) -> String {
 }
 
 // Handle the INFO REPLICATION command
-pub async fn handle_info(db: &Arc<Mutex<RedisDatabase>>, args: &[String]) -> String {
-    let db = db.lock().await;
+pub async fn handle_info(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    let db = db.read().await;
     if args.len() == 1 && args[0].to_uppercase() == "REPLICATION" {
         let mut response = String::new();
         for (key, value) in &db.replication_info {
@@ -476,14 +2551,296 @@ pub async fn handle_info(db: &Arc<Mutex<RedisDatabase>>, args: &[String]) -> Str
     }
 }
 
+// Handle the CLIENT command (SETNAME, GETNAME and ID)
+pub fn handle_client_cmd(args: &[String], client_state: &mut ClientState) -> String {
+    let Some(subcommand) = args.first() else {
+        return "-ERR wrong number of arguments for 'client' command\r\n".to_string();
+    };
+    match (subcommand.to_uppercase().as_str(), &args[1..]) {
+        // An empty name clears it
+        ("SETNAME", [name]) => {
+            if name.bytes().any(|byte| !(b'!'..=b'~').contains(&byte)) {
+                return "-ERR Client names cannot contain spaces, newlines or special characters.\r\n".to_string();
+            }
+            client_state.set_name((!name.is_empty()).then(|| name.clone()));
+            "+OK\r\n".to_string()
+        }
+        ("GETNAME", []) => bulk_string(client_state.name().unwrap_or_default()),
+        ("ID", []) => format!(":{}\r\n", client_state.id()),
+        ("SETNAME" | "GETNAME" | "ID", _) => format!("-ERR wrong number of arguments for 'client|{}' command\r\n", subcommand.to_lowercase()),
+        _ => format!("-ERR unknown subcommand '{}'. Try CLIENT HELP.\r\n", subcommand),
+    }
+}
+
+// Handle the WATCH command: remember each key's current version so EXEC can tell whether it
+// was written in the meantime
+pub async fn handle_watch(db: &Arc<RwLock<RedisDatabase>>, args: &[String], client_state: &mut ClientState) -> String {
+    if args.is_empty() {
+        return "-ERR wrong number of arguments for 'watch' command\r\n".to_string();
+    }
+    let db = db.read().await;
+    let database = client_state.selected_db();
+    for key in args {
+        client_state.watch(database, key, db.key_version(database, key));
+    }
+    "+OK\r\n".to_string()
+}
+
+// Handle the UNWATCH command
+pub fn handle_unwatch(args: &[String], client_state: &mut ClientState) -> String {
+    if !args.is_empty() {
+        return "-ERR wrong number of arguments for 'unwatch' command\r\n".to_string();
+    }
+    client_state.unwatch();
+    "+OK\r\n".to_string()
+}
+
+// Handle the RESET command: return the connection to the state of a fresh one. The
+// unsubscribe replies are dropped since RESET answers with a single +RESET.
+pub async fn handle_reset(db: &Arc<RwLock<RedisDatabase>>, args: &[String], client_state: &mut ClientState) -> String {
+    if !args.is_empty() {
+        return "-ERR wrong number of arguments for 'reset' command\r\n".to_string();
+    }
+    client_state.deactivate_multiqueue();
+    client_state.unwatch();
+    unsubscribe_from(db, &[], client_state, false, "unsubscribe").await;
+    unsubscribe_from(db, &[], client_state, true, "punsubscribe").await;
+    client_state.select(0);
+    client_state.set_protocol(2);
+    client_state.set_name(None);
+    client_state.set_authenticated(false);
+    client_state.set_user("default");
+    "+RESET\r\n".to_string()
+}
+
+// Handle the COMMAND command: the whole command table, or COUNT, LIST, INFO and DOCS
+pub fn handle_command(args: &[String]) -> String {
+    let Some(subcommand) = args.first() else {
+        let mut response = format!("*{}\r\n", COMMAND_TABLE.len());
+        for spec in COMMAND_TABLE {
+            response.push_str(&command_info_reply(spec));
+        }
+        return response;
+    };
+    // INFO and DOCS with no names describe every command
+    let requested: Vec<&CommandSpec> = if args.len() > 1 {
+        args[1..].iter().filter_map(|name| command_spec(name)).collect()
+    } else {
+        COMMAND_TABLE.iter().collect()
+    };
+    match subcommand.to_uppercase().as_str() {
+        "COUNT" if args.len() == 1 => format!(":{}\r\n", COMMAND_TABLE.len()),
+        "LIST" if args.len() == 1 => {
+            let mut response = format!("*{}\r\n", COMMAND_TABLE.len());
+            for spec in COMMAND_TABLE {
+                response.push_str(&bulk_string(spec.name));
+            }
+            response
+        }
+        "INFO" if args.len() == 1 => handle_command(&[]),
+        // Unknown names get a nil in their place
+        "INFO" => {
+            let mut response = format!("*{}\r\n", args.len() - 1);
+            for name in &args[1..] {
+                match command_spec(name) {
+                    Some(spec) => response.push_str(&command_info_reply(spec)),
+                    None => response.push_str("$-1\r\n"),
+                }
+            }
+            response
+        }
+        // Unknown names are left out
+        "DOCS" => {
+            let mut response = map_header(requested.len());
+            for spec in requested {
+                response.push_str(&bulk_string(spec.name));
+                response.push_str(&map_header(1));
+                response.push_str(&bulk_string("group"));
+                response.push_str(&bulk_string(spec.group));
+            }
+            response
+        }
+        "COUNT" | "LIST" => format!("-ERR wrong number of arguments for 'command|{}' command\r\n", subcommand.to_lowercase()),
+        _ => format!("-ERR unknown subcommand '{}'. Try COMMAND HELP.\r\n", subcommand),
+    }
+}
+
+// One command's entry in the COMMAND and COMMAND INFO replies: name, arity, flags, key positions,
+// then ACL categories, tips, key specs and subcommands, which aren't tracked and are left empty
+fn command_info_reply(spec: &CommandSpec) -> String {
+    let mut response = "*10\r\n".to_string();
+    response.push_str(&bulk_string(spec.name));
+    response.push_str(&format!(":{}\r\n", spec.arity));
+    response.push_str(&format!("*{}\r\n", spec.flags.len()));
+    for flag in spec.flags {
+        response.push_str(&format!("+{}\r\n", flag));
+    }
+    response.push_str(&format!(":{}\r\n:{}\r\n:{}\r\n", spec.first_key, spec.last_key, spec.step));
+    response.push_str("*0\r\n*0\r\n*0\r\n*0\r\n");
+    response
+}
+
+// Handle the AUTH command: `AUTH password` for the default user, or `AUTH username password`.
+// Only the default user exists, with requirepass (if set) as its password
+pub async fn handle_auth(config_map: &SharedConfig, args: &[String], client_state: &mut ClientState) -> String {
+    let (username, password) = match args {
+        [password] => {
+            if !password_required(config_map).await {
+                return "-ERR AUTH <password> called without any password configured for the default user. Are you sure your configuration is correct?\r\n".to_string();
+            }
+            ("default", password)
+        }
+        [username, password] => (username.as_str(), password),
+        _ => return "-ERR wrong number of arguments for 'auth' command\r\n".to_string(),
+    };
+    match authenticate(config_map, username, password, client_state).await {
+        Ok(()) => "+OK\r\n".to_string(),
+        Err(error) => error,
+    }
+}
+
+// Handle the ACL command (WHOAMI and LIST). Users can't be managed; the only one is default,
+// whose password is requirepass
+pub async fn handle_acl(config_map: &SharedConfig, args: &[String], client_state: &ClientState) -> String {
+    let Some(subcommand) = args.first() else {
+        return "-ERR wrong number of arguments for 'acl' command\r\n".to_string();
+    };
+    match (subcommand.to_uppercase().as_str(), &args[1..]) {
+        ("WHOAMI", []) => bulk_string(client_state.user()),
+        ("LIST", []) => {
+            let password = match config_map.read().await.get("requirepass") {
+                Some(password) if !password.is_empty() => format!("#{}", sha256_hex(password.as_bytes())),
+                _ => "nopass".to_string(),
+            };
+            format!("*1\r\n{}", bulk_string(&format!("user default on {} sanitize-payload ~* &* +@all", password)))
+        }
+        ("WHOAMI" | "LIST", _) => format!("-ERR wrong number of arguments for 'acl|{}' command\r\n", subcommand.to_lowercase()),
+        _ => format!("-ERR unknown subcommand '{}'. Try ACL HELP.\r\n", subcommand),
+    }
+}
+
+// Whether requirepass is set, so connections have to authenticate before running commands
+pub async fn password_required(config_map: &SharedConfig) -> bool {
+    config_map.read().await.get("requirepass").is_some_and(|password| !password.is_empty())
+}
+
+// Check credentials for AUTH and HELLO AUTH, marking the connection authenticated if they're
+// right. Without requirepass the default user accepts any password
+async fn authenticate(config_map: &SharedConfig, username: &str, password: &str, client_state: &mut ClientState) -> Result<(), String> {
+    let password_matches = match config_map.read().await.get("requirepass") {
+        Some(required) if !required.is_empty() => required == password,
+        _ => true,
+    };
+    if username != "default" || !password_matches {
+        return Err("-WRONGPASS invalid username-password pair or user is disabled.\r\n".to_string());
+    }
+    client_state.set_authenticated(true);
+    client_state.set_user(username);
+    Ok(())
+}
+
+// Handle the HELLO command: switch protocol version, optionally authenticating first with
+// AUTH username password. SETNAME is not supported.
+pub async fn handle_hello(db: &Arc<RwLock<RedisDatabase>>, config_map: &SharedConfig, args: &[String], client_state: &mut ClientState) -> String {
+    let version = match args.first() {
+        None => None,
+        Some(protover) => match protover.parse::<u8>() {
+            Ok(version @ (2 | 3)) => Some(version),
+            Ok(_) => return "-NOPROTO unsupported protocol version\r\n".to_string(),
+            Err(_) => return "-ERR Protocol version is not an integer or out of range\r\n".to_string(),
+        },
+    };
+    match args.get(1..).unwrap_or_default() {
+        [] => {}
+        [option, username, password] if option.eq_ignore_ascii_case("AUTH") => {
+            if let Err(error) = authenticate(config_map, username, password, client_state).await {
+                return error;
+            }
+        }
+        _ => return "-ERR syntax error\r\n".to_string(),
+    }
+    // The protocol only changes once the connection is allowed to run commands
+    if !client_state.is_authenticated() && password_required(config_map).await {
+        return "-NOAUTH HELLO must be called with the client already authenticated, otherwise the HELLO <proto> AUTH <user> <pass> option can be used to authenticate the client and select the RESP protocol version at the same time\r\n".to_string();
+    }
+    if let Some(version) = version {
+        client_state.set_protocol(version);
+    }
+
+    let role = match db.read().await.get_replication_info("role") {
+        Some(ReplicationInfoValue::StringValue(role)) => role.clone(),
+        _ => "master".to_string(),
+    };
+    // The reply already uses the protocol just negotiated
+    let version = client_state.protocol();
+    let mut response = resp_map_header(version, 6);
+    response.push_str(&bulk_string("server"));
+    response.push_str(&bulk_string("redis"));
+    response.push_str(&bulk_string("version"));
+    response.push_str(&bulk_string(SERVER_VERSION));
+    response.push_str(&bulk_string("proto"));
+    response.push_str(&format!(":{}\r\n", version));
+    response.push_str(&bulk_string("mode"));
+    response.push_str(&bulk_string("standalone"));
+    response.push_str(&bulk_string("role"));
+    response.push_str(&bulk_string(&role));
+    response.push_str(&bulk_string("modules"));
+    response.push_str("*0\r\n");
+    response
+}
+
+// Handle the WAIT command: block until `numreplicas` replicas have acknowledged every write sent
+// before the WAIT, or until the timeout (0 blocks forever), replying with how many have
+pub async fn handle_wait(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    if args.len() != 2 {
+        return "-ERR wrong number of arguments for 'wait' command\r\n".to_string();
+    }
+    let (num_replicas, timeout_millis) = match (args[0].parse::<usize>(), args[1].parse::<i64>()) {
+        (Ok(_), Ok(timeout_millis)) if timeout_millis < 0 => return "-ERR timeout is negative\r\n".to_string(),
+        (Ok(num_replicas), Ok(timeout_millis)) => (num_replicas, timeout_millis as u64),
+        _ => return "-ERR value is not an integer or out of range\r\n".to_string(),
+    };
+
+    let (target_offset, notifier) = {
+        let db = db.read().await;
+        let target_offset = db.master_repl_offset();
+        let acked = db.replicas_acked(target_offset);
+        if acked >= num_replicas || !blocking_allowed() {
+            return format!(":{}\r\n", acked);
+        }
+        (target_offset, db.replica_ack_notifier())
+    };
+
+    // Ask every replica for its offset; the GETACK itself is part of the stream but isn't waited for
+    let getack_message = "*3\r\n$8\r\nREPLCONF\r\n$6\r\nGETACK\r\n$1\r\n*\r\n";
+    db.write().await.propagate(getack_message.as_bytes()).await;
+
+    let deadline = (timeout_millis > 0).then(|| tokio::time::Instant::now() + Duration::from_millis(timeout_millis));
+    loop {
+        // Register for wakeups before counting so an ACK in between isn't missed
+        let mut wakeup = Box::pin(notifier.notified());
+        wakeup.as_mut().enable();
+
+        let acked = db.read().await.replicas_acked(target_offset);
+        if acked >= num_replicas {
+            return format!(":{}\r\n", acked);
+        }
+        match deadline {
+            Some(deadline) => {
+                if tokio::time::timeout_at(deadline, wakeup).await.is_err() {
+                    return format!(":{}\r\n", db.read().await.replicas_acked(target_offset));
+                }
+            }
+            None => wakeup.await,
+        }
+    }
+}
+
 // Handle the REPLCONF command
-pub async fn handle_replconf(db: &Arc<Mutex<RedisDatabase>>, args: &[String]) -> String {
-    let db = db.lock().await;
+pub async fn handle_replconf(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    let db = db.read().await;
     if args.len() == 2 && args[0].to_uppercase() == "GETACK" && args[1] == "*" {
-        let bytes_processed = match db.get_replication_info("slave_repl_offset") {
-            Some(ReplicationInfoValue::ByteValue(bytes)) => *bytes,  // Dereference to get the usize value
-            _ => 0,  // Default to 0 if not found
-        };
+        let bytes_processed = db.slave_repl_offset();
 
         format!("*3\r\n$8\r\nREPLCONF\r\n$3\r\nACK\r\n${}\r\n{}\r\n", bytes_processed.to_string().len(), bytes_processed)
     } else {
@@ -492,17 +2849,17 @@ pub async fn handle_replconf(db: &Arc<Mutex<RedisDatabase>>, args: &[String]) ->
 }
 
 // Handle the PSYNC command
-pub async fn handle_psync(db: &Arc<Mutex<RedisDatabase>>, args: &[String]) -> String {
-    let db = db.lock().await;
+pub async fn handle_psync(db: &Arc<RwLock<RedisDatabase>>, args: &[String]) -> String {
+    let db = db.read().await;
     if args.len() == 2 {
         if let Some(master_replid) = db.replication_info.get("master_replid") {
-            if let Some(master_repl_offset) = db.replication_info.get("master_repl_offset") {
-                return format!("+FULLRESYNC {} {}\r\n", master_replid, master_repl_offset);
+            if let Some(ReplicationInfoValue::ByteValue(master_repl_offset)) = db.replication_info.get("master_repl_offset") {
+                format!("+FULLRESYNC {} {}\r\n", master_replid, master_repl_offset)
             } else {
-                return "-ERR master_repl_offset not found\r\n".to_string();
+                "-ERR master_repl_offset not found\r\n".to_string()
             }
         } else {
-            return "-ERR master_replid not found\r\n".to_string();
+            "-ERR master_replid not found\r\n".to_string()
         }
     } else {
         "-ERR wrong number of arguments for 'psync' command\r\n".to_string()
@@ -541,26 +2898,22 @@ fn hex_to_bytes(hex: &str) -> Vec<u8> {
 
 // Asynchronously process commands after receiving RDB file
 pub async fn process_commands_after_rdb(
-    partial_message: &mut String,
-    db: Arc<Mutex<RedisDatabase>>,
-    config_map: &HashMap<String, String>,
-    stream: &mut TcpStream,  // Added to send a response back to master
+    partial_message: &mut Vec<u8>,
+    db: Arc<RwLock<RedisDatabase>>,
+    config_map: &SharedConfig,
+    writer: &Arc<Mutex<OwnedWriteHalf>>,  // Added to send a response back to master
+    client_state: &mut ClientState, // The master link's state, kept across reads so SELECT sticks
 ) -> io::Result<()> {
-    
-    let mut client_state = ClientState::new();
-
     let parsed_results = {
-        parse_redis_message(&partial_message, &db, config_map, &mut client_state).await
+        parse_redis_message(partial_message, &db, config_map, client_state).await
     };
 
-    for (command, args, response, command_msg_length_bytes) in parsed_results {
-        let partial_message_bytes = partial_message.as_bytes();
-
-        if command_msg_length_bytes > partial_message_bytes.len() {
+    for (command, _, response, command_msg_length_bytes) in parsed_results {
+        if command_msg_length_bytes > partial_message.len() {
             eprintln!(
                 "Error: consumed_length ({}) exceeds partial_message byte length ({}).",
                 command_msg_length_bytes,
-                partial_message_bytes.len()
+                partial_message.len()
             );
             return Err(io::Error::new(
                 io::ErrorKind::InvalidData,
@@ -568,26 +2921,54 @@ pub async fn process_commands_after_rdb(
             ));
         }
 
-        let remaining_bytes = &partial_message_bytes[command_msg_length_bytes..];
-        *partial_message = String::from_utf8_lossy(remaining_bytes).to_string();
+        partial_message.drain(..command_msg_length_bytes);
 
-        if let Some(cmd) = command {
-            match cmd.as_str() {
-                "SET" => {
-                    if args.len() >= 2 {
-                        let key = args[0].clone();
-                        let value = args[1].clone();
-                        let mut db_lock = db.lock().await;
-                        db_lock.insert(key.clone(), RedisValue::new(value.clone(), None));
-                    }
-                },
-                "REPLCONF" => {
-                    stream.write_all(response.as_bytes()).await?;
-                },
-                _ => println!("Unknown command: {}", cmd),
-            }
+        // parse_redis_message has already applied write commands to the database,
+        // so only REPLCONF needs a reply sent back to the master
+        if command.as_deref() == Some("REPLCONF") {
+            writer.lock().await.write_all(&response).await?;
         }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_score_bound_parses_inclusive_and_exclusive_scores() {
+        let inclusive = ScoreBound::parse("1.5").unwrap();
+        assert_eq!((inclusive.score, inclusive.exclusive), (1.5, false));
+        let exclusive = ScoreBound::parse("(1.5").unwrap();
+        assert_eq!((exclusive.score, exclusive.exclusive), (1.5, true));
+    }
+
+    #[test]
+    fn test_score_bound_parses_infinities() {
+        assert_eq!(ScoreBound::parse("-inf").unwrap().score, f64::NEG_INFINITY);
+        assert_eq!(ScoreBound::parse("+inf").unwrap().score, f64::INFINITY);
+        assert_eq!(ScoreBound::parse("inf").unwrap().score, f64::INFINITY);
+        let exclusive = ScoreBound::parse("(-inf").unwrap();
+        assert_eq!((exclusive.score, exclusive.exclusive), (f64::NEG_INFINITY, true));
+    }
+
+    #[test]
+    fn test_score_bound_rejects_non_numbers() {
+        for arg in ["", "(", "((1", "abc", "nan", "(nan", "1.5)"] {
+            assert!(ScoreBound::parse(arg).is_none(), "{:?} should not parse", arg);
+        }
+    }
+
+    #[test]
+    fn test_score_bound_admits_scores_on_its_side() {
+        let inclusive = ScoreBound::parse("2").unwrap();
+        assert!(inclusive.admits_above(2.0) && inclusive.admits_below(2.0));
+        assert!(inclusive.admits_above(3.0) && !inclusive.admits_above(1.0));
+
+        let exclusive = ScoreBound::parse("(2").unwrap();
+        assert!(!exclusive.admits_above(2.0) && !exclusive.admits_below(2.0));
+        assert!(exclusive.admits_above(2.5) && exclusive.admits_below(1.5));
+    }
+}
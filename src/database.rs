@@ -1,55 +1,52 @@
-use std::sync::Arc;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering as AtomicOrdering};
+use std::sync::{Arc, OnceLock};
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
 use tokio::net::tcp::OwnedWriteHalf;
-use tokio::sync::{Mutex, RwLock};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::{Mutex, Notify, RwLock};
 use std::fmt::{self, Debug};
 use std::cmp::Ordering;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use crate::utils::{glob_match, parse_memory_size, random_u64};
 
 // Define the StreamID struct
-#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
 pub struct StreamID {
     pub milliseconds_time: u64,  // The first part of the ID
     pub sequence_number: u64,    // The second part of the ID
 }
 
 impl StreamID {
-    // Generate a StreamID with the current Unix time and a sequence number
-    pub fn generate(stream: &BTreeMap<StreamID, HashMap<String, String>>) -> Self {
+    // Generate the ID after `last_id` for the current Unix time. If the clock is behind the last
+    // ID, its time is kept and the sequence number bumped, as in Redis.
+    pub fn generate(last_id: StreamID) -> Self {
         let current_time = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .expect("Time went backwards")
             .as_millis() as u64;
-        let sequence_number = stream
-            .iter()
-            .rev()
-            .find(|(id, _)| id.milliseconds_time == current_time)
-            .map_or(0, |(id, _)| id.sequence_number + 1);
-
-        StreamID {
-            milliseconds_time: current_time,
-            sequence_number,
+        if current_time > last_id.milliseconds_time {
+            StreamID {
+                milliseconds_time: current_time,
+                sequence_number: 0,
+            }
+        } else {
+            StreamID {
+                milliseconds_time: last_id.milliseconds_time,
+                sequence_number: last_id.sequence_number + 1,
+            }
         }
     }
 
-    // Generate a StreamID with the provided milliseconds_time and the first available sequence number
-    pub fn generate_with_time(
-        milliseconds_time: u64,
-        stream: &BTreeMap<StreamID, HashMap<String, String>>,
-    ) -> Self {
-        // For time 0, the first sequence number must be 1 (since 0-0 is not allowed)
-        let sequence_number = if milliseconds_time == 0 {
-            stream
-                .iter()
-                .rev()
-                .find(|(id, _)| id.milliseconds_time == 0)
-                .map_or(1, |(id, _)| id.sequence_number + 1)
+    // Generate a StreamID with the provided milliseconds_time and the first sequence number after `last_id`
+    pub fn generate_with_time(milliseconds_time: u64, last_id: StreamID) -> Self {
+        let sequence_number = if milliseconds_time == last_id.milliseconds_time {
+            last_id.sequence_number + 1
+        } else if milliseconds_time == 0 {
+            1 // 0-0 is not allowed
         } else {
-            stream
-                .iter()
-                .rev()
-                .find(|(id, _)| id.milliseconds_time == milliseconds_time)
-                .map_or(0, |(id, _)| id.sequence_number + 1)
+            0
         };
 
         StreamID {
@@ -59,7 +56,7 @@ impl StreamID {
     }
 
     // Parse a string like "12345-1" into a StreamID
-    pub fn from_str(id_str: &str) -> Option<StreamID> {
+    pub fn parse(id_str: &str) -> Option<StreamID> {
         let parts: Vec<&str> = id_str.split('-').collect();
         if parts.len() != 2 {
             return None;
@@ -129,43 +126,793 @@ impl fmt::Display for ReplicationInfoValue {
     }
 }
 
+// A sorted set score; wraps f64 with a total order so it can key the score index
+#[derive(Debug, Clone, Copy)]
+pub struct Score(pub f64);
+
+impl PartialEq for Score {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Score {}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Members ordered by (score, member), with a member -> score map for direct lookups
+#[derive(Debug, Clone, Default)]
+pub struct SortedSet {
+    by_score: BTreeSet<(Score, String)>,
+    scores: HashMap<String, f64>,
+}
+
+impl SortedSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Add the member or update its score, returning true if it was not already present
+    pub fn insert(&mut self, member: String, score: f64) -> bool {
+        let previous = self.scores.insert(member.clone(), score);
+        if let Some(previous) = previous {
+            self.by_score.remove(&(Score(previous), member.clone()));
+        }
+        self.by_score.insert((Score(score), member));
+        previous.is_none()
+    }
+
+    pub fn score(&self, member: &str) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    // Members and scores in ascending score order, ties broken by member
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&String, f64)> + ExactSizeIterator {
+        self.by_score.iter().map(|(score, member)| (member, score.0))
+    }
+}
+
+// A stream's entries in ID order, with the last ID it was given. The last ID only moves forward,
+// so XADD never reuses the ID of an entry that was deleted or trimmed away.
+#[derive(Debug, Clone, Default)]
+pub struct Stream {
+    entries: BTreeMap<StreamID, HashMap<String, String>>,
+    last_id: StreamID,
+}
+
+impl Stream {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn entries(&self) -> &BTreeMap<StreamID, HashMap<String, String>> {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // The highest ID the stream has had, or 0-0 if it never had an entry
+    pub fn last_id(&self) -> StreamID {
+        self.last_id
+    }
+
+    // Raise the last ID, e.g. to the one stored with the stream in an RDB file
+    pub fn set_last_id(&mut self, id: StreamID) {
+        self.last_id = self.last_id.max(id);
+    }
+
+    pub fn insert(&mut self, id: StreamID, fields: HashMap<String, String>) {
+        self.entries.insert(id, fields);
+        self.set_last_id(id);
+    }
+
+    // Delete an entry, returning whether it was there
+    pub fn remove(&mut self, id: &StreamID) -> bool {
+        self.entries.remove(id).is_some()
+    }
+
+    pub fn pop_first(&mut self) -> Option<(StreamID, HashMap<String, String>)> {
+        self.entries.pop_first()
+    }
+}
+
+// A message published to a channel, on its way to one subscribed connection
+#[derive(Debug, Clone)]
+pub struct PubSubMessage {
+    pub pattern: Option<String>, // The pattern that matched, for PSUBSCRIBE subscribers
+    pub channel: String,
+    pub payload: String,
+}
+
+// Classes accepted in notify-keyspace-events
+const KEYSPACE_EVENT_FLAGS: &str = "KEg$lshzxetA";
+
+// Keys sampled per database when looking for one to evict, as with Redis's `maxmemory-samples 5`
+const MAXMEMORY_SAMPLES: usize = 5;
+
+// What to do when a write would go over maxmemory (the `maxmemory-policy` config)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EvictionPolicy {
+    NoEviction, // Refuse the write
+    AllKeysLru, // Evict the least recently used keys
+    VolatileLru, // Evict the least recently used keys that have a TTL
+}
+
+impl EvictionPolicy {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "noeviction" => Some(EvictionPolicy::NoEviction),
+            "allkeys-lru" => Some(EvictionPolicy::AllKeysLru),
+            "volatile-lru" => Some(EvictionPolicy::VolatileLru),
+            _ => None,
+        }
+    }
+}
+
+// A key with its value, as stored in a Keyspace
+#[derive(Debug)]
+struct KeyspaceEntry {
+    key: String,
+    value: RedisValue,
+    memory: usize, // Bytes the entry was estimated to use when last refreshed
+    volatile_slot: Option<usize>, // Where the entry is listed in Keyspace::volatile, if it has a TTL
+}
+
+// The keys of one logical database. Entries are kept in a Vec indexed by key, so random keys can
+// be sampled without walking the map. The keys with a TTL are listed separately for active expiry
+// and volatile eviction, and the memory the entries use is tallied as they change.
+#[derive(Default)]
+pub struct Keyspace {
+    entries: Vec<KeyspaceEntry>,
+    positions: HashMap<String, usize>, // Index into entries of each key
+    volatile: Vec<usize>, // Indexes into entries of the keys with a TTL
+    used_memory: usize, // Sum of the entries' memory
+}
+
+impl Keyspace {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.positions.contains_key(key)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&RedisValue> {
+        self.positions.get(key).map(|&position| &self.entries[position].value)
+    }
+
+    // A value changed in place should be passed to refresh afterwards
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut RedisValue> {
+        self.positions.get(key).map(|&position| &mut self.entries[position].value)
+    }
+
+    // Store the value at `key`, returning the one it replaces
+    pub fn insert(&mut self, key: String, value: RedisValue) -> Option<RedisValue> {
+        let previous = match self.positions.get(&key) {
+            Some(&position) => Some(std::mem::replace(&mut self.entries[position].value, value)),
+            None => {
+                self.positions.insert(key.clone(), self.entries.len());
+                self.entries.push(KeyspaceEntry { key: key.clone(), value, memory: 0, volatile_slot: None });
+                None
+            }
+        };
+        self.refresh(&key);
+        previous
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<RedisValue> {
+        let position = self.positions.remove(key)?;
+        self.unlist_volatile(position);
+        let entry = self.entries.swap_remove(position);
+        self.used_memory -= entry.memory;
+        // The last entry took the removed one's place
+        if let Some(moved) = self.entries.get(position) {
+            self.positions.insert(moved.key.clone(), position);
+            if let Some(slot) = moved.volatile_slot {
+                self.volatile[slot] = position;
+            }
+        }
+        Some(entry.value)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.positions.clear();
+        self.volatile.clear();
+        self.used_memory = 0;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &RedisValue)> {
+        self.entries.iter().map(|entry| (&entry.key, &entry.value))
+    }
+
+    // The entry at `position`, from 0 to len() - 1, for walking the keyspace a piece at a time
+    pub fn entry_at(&self, position: usize) -> Option<(&String, &RedisValue)> {
+        self.entries.get(position).map(|entry| (&entry.key, &entry.value))
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &RedisValue> {
+        self.entries.iter().map(|entry| &entry.value)
+    }
+
+    // Bring the bookkeeping for `key` up to date after its value changed in place
+    pub fn refresh(&mut self, key: &str) {
+        let Some(&position) = self.positions.get(key) else {
+            return;
+        };
+        let entry = &mut self.entries[position];
+        let memory = entry_memory_usage(&entry.key, &entry.value);
+        self.used_memory = self.used_memory - entry.memory + memory;
+        entry.memory = memory;
+        match (entry.value.remaining_ttl().is_some(), entry.volatile_slot) {
+            (true, None) => {
+                entry.volatile_slot = Some(self.volatile.len());
+                self.volatile.push(position);
+            }
+            (false, Some(_)) => self.unlist_volatile(position),
+            _ => {}
+        }
+    }
+
+    // Approximate bytes used by the keys and values, as of each entry's last refresh
+    pub fn used_memory(&self) -> usize {
+        self.used_memory
+    }
+
+    // Up to `count` distinct keys, starting at a random one
+    pub fn sample(&self, count: usize) -> impl Iterator<Item = (&String, &RedisValue)> {
+        let start = if self.entries.is_empty() { 0 } else { (random_u64() % self.entries.len() as u64) as usize };
+        (0..count.min(self.entries.len())).map(move |offset| {
+            let entry = &self.entries[(start + offset) % self.entries.len()];
+            (&entry.key, &entry.value)
+        })
+    }
+
+    pub fn volatile_len(&self) -> usize {
+        self.volatile.len()
+    }
+
+    // Up to `count` distinct keys with a TTL, starting at a random one
+    pub fn sample_volatile(&self, count: usize) -> impl Iterator<Item = (&String, &RedisValue)> {
+        let start = if self.volatile.is_empty() { 0 } else { (random_u64() % self.volatile.len() as u64) as usize };
+        (0..count.min(self.volatile.len())).map(move |offset| {
+            let entry = &self.entries[self.volatile[(start + offset) % self.volatile.len()]];
+            (&entry.key, &entry.value)
+        })
+    }
+
+    // Take the entry at `position` off the list of keys with a TTL
+    fn unlist_volatile(&mut self, position: usize) {
+        if let Some(slot) = self.entries[position].volatile_slot.take() {
+            self.volatile.swap_remove(slot);
+            if let Some(&moved) = self.volatile.get(slot) {
+                self.entries[moved].volatile_slot = Some(slot);
+            }
+        }
+    }
+}
+
+impl Debug for Keyspace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+// Number of logical databases, as with Redis's default `databases 16`
+pub const DATABASE_COUNT: usize = 16;
+
+tokio::task_local! {
+    // The database a command works on. Connections run each command inside a scope set to
+    // the index they SELECTed; code outside any scope (startup, background tasks) sees database 0.
+    pub static SELECTED_DB: usize;
+}
+
+pub fn selected_db() -> usize {
+    SELECTED_DB.try_with(|index| *index).unwrap_or(0)
+}
+
+// All server state, shared by connections behind a single RwLock. Each handler takes the lock
+// itself, for reading or writing, and only for as long as one step of its work needs; read_live_keys
+// briefly takes the write lock to drop expired keys before reading. A command that changes several
+// keys (MSET, RENAME, SWAPDB, FLUSHALL) does so under one write lock, so others never see it half
+// done. The lock is let go between commands, so other clients' reads can run between those of a
+// transaction and see it part way through. Writes don't, though: see write_turn, which orders them
+// against each other, WATCH checks and the replication stream. Waiting commands (WAIT, blocking
+// XREAD) hold neither while they sleep.
 pub struct RedisDatabase {
-    pub data: HashMap<String, RedisValue>,
+    databases: Vec<Keyspace>, // One keyspace per logical database
     pub replication_info: HashMap<String, ReplicationInfoValue>, // Changed to use the enum
     pub slave_connections: RwLock<Vec<Arc<Mutex<OwnedWriteHalf>>>>, // Changed to store multiple slave connections
-    pub ack_counter: Arc<Mutex<usize>>,
+    replica_ack_offsets: HashMap<SocketAddr, usize>, // Replication offset each replica last acknowledged
+    replica_acks: Arc<Notify>, // Wakes WAIT when a replica acknowledges
+    shutdown_requested: Arc<Notify>, // Wakes main when SHUTDOWN asks the server to stop
+    stream_notifiers: HashMap<String, Arc<Notify>>, // Wakes blocked XREADs when a stream gets a new entry
+    pubsub_channels: HashMap<String, Vec<UnboundedSender<PubSubMessage>>>, // Subscribed connections per channel
+    pubsub_patterns: HashMap<String, Vec<UnboundedSender<PubSubMessage>>>, // Subscribed connections per glob pattern
+    keyspace_events: String, // Enabled notify-keyspace-events flags, with A expanded
+    dels_to_propagate: Vec<(usize, String)>, // Keys expired or evicted on the master whose DEL replicas haven't been sent
+    maxmemory: usize, // Memory limit in bytes for the keyspace; 0 means unlimited
+    eviction_policy: EvictionPolicy, // How to make room once over maxmemory
+    replication_db: Option<usize>, // Database last SELECTed on the replication stream
+    last_key_version: u64, // Last version given to a written key, so every write gets a new one
+    encoding_limits: EncodingLimits, // When collections switch to their larger encodings
+    active_expire: bool, // Whether the background cycle removes expired keys; DEBUG SET-ACTIVE-EXPIRE turns it off
+    background_save: Arc<AtomicBool>, // Set while a BGSAVE is writing its snapshot
+    write_turn: Arc<Mutex<()>>, // Held by each write command, and by EXEC for its whole transaction
+}
+
+impl Default for RedisDatabase {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl RedisDatabase {
     pub fn new() -> Self {
         // Create a broadcast channel with a capacity of 16 messages (adjust as needed)
         Self {
-            data: HashMap::new(),
+            databases: (0..DATABASE_COUNT).map(|_| Keyspace::default()).collect(),
             replication_info: HashMap::new(),
             slave_connections: vec![].into(),
-            ack_counter: Arc::new(Mutex::new(0))
+            replica_ack_offsets: HashMap::new(),
+            replica_acks: Arc::new(Notify::new()),
+            shutdown_requested: Arc::new(Notify::new()),
+            stream_notifiers: HashMap::new(),
+            pubsub_channels: HashMap::new(),
+            pubsub_patterns: HashMap::new(),
+            keyspace_events: String::new(),
+            dels_to_propagate: Vec::new(),
+            maxmemory: 0,
+            eviction_policy: EvictionPolicy::NoEviction,
+            replication_db: None,
+            last_key_version: 0,
+            encoding_limits: EncodingLimits::default(),
+            active_expire: true,
+            background_save: Arc::new(AtomicBool::new(false)),
+            write_turn: Arc::new(Mutex::new(())),
         }
     }
 
+    // The keyspace of the selected database
+    pub fn data(&self) -> &Keyspace {
+        &self.databases[selected_db()]
+    }
+
+    pub fn data_mut(&mut self) -> &mut Keyspace {
+        &mut self.databases[selected_db()]
+    }
+
+    // The keyspace of a database by index, regardless of which one is selected
+    pub fn database_mut(&mut self, index: usize) -> &mut Keyspace {
+        &mut self.databases[index]
+    }
+
+    pub fn databases(&self) -> &[Keyspace] {
+        &self.databases
+    }
+
+    pub fn databases_mut(&mut self) -> &mut [Keyspace] {
+        &mut self.databases
+    }
+
     pub fn insert(&mut self, key: String, value: RedisValue) {
-        self.data.insert(key, value);
+        self.data_mut().insert(key, value);
     }
 
+    // Look up a key for a command, counting as an access to it
     pub fn get(&self, key: &str) -> Option<&RedisValue> {
-        self.data.get(key)
+        self.data().get(key).inspect(|value| value.touch())
     }
 
     pub fn get_mut(&mut self, key: &str) -> Option<&mut RedisValue> {
-        self.data.get_mut(key)
+        self.data_mut().get_mut(key).inspect(|value| value.touch())
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<RedisValue> {
+        self.data_mut().remove(key)
+    }
+
+    // Give the keys a write command named in `database` a new version, so clients WATCHing them
+    // see the change at EXEC
+    pub fn mark_modified(&mut self, database: usize, keys: &[&str]) {
+        for key in keys {
+            self.last_key_version += 1;
+            let version = self.last_key_version;
+            if let Some(value) = self.databases[database].get_mut(key) {
+                value.version = version;
+                // Collections change encoding as they grow past the limits, so work it out as
+                // of this write
+                value.encoding = Some(value.value.encoding(value.encoding, &self.encoding_limits));
+            }
+            // The write may have resized the value or set or cleared its TTL
+            self.databases[database].refresh(key);
+        }
+    }
+
+    // The version of a live key for WATCH, or None if it doesn't exist
+    pub fn key_version(&self, database: usize, key: &str) -> Option<u64> {
+        self.databases[database].get(key).filter(|value| !value.is_expired()).map(|value| value.version)
+    }
+
+    // Lazily drop the key if its TTL has elapsed so callers only see live values
+    pub fn remove_if_expired(&mut self, key: &str) {
+        if self.data().get(key).is_some_and(|value| value.is_expired()) {
+            self.remove(key);
+            self.notify_keyspace_event('x', "expired", key);
+            self.queue_replica_del(selected_db(), key);
+        }
+    }
+
+    // In every database, remove the expired keys among up to `sample_size` keys with a TTL starting
+    // at a random one, returning how many were sampled and how many of those were removed
+    pub fn remove_expired_sample(&mut self, sample_size: usize) -> (usize, usize) {
+        let (mut sampled_count, mut removed_count) = (0, 0);
+        let mut removed = Vec::new();
+        for (index, data) in self.databases.iter_mut().enumerate().filter(|(_, data)| data.volatile_len() > 0) {
+            let expired: Vec<String> = data
+                .sample_volatile(sample_size)
+                .inspect(|_| sampled_count += 1)
+                .filter(|(_, value)| value.is_expired())
+                .map(|(key, _)| key.clone())
+                .collect();
+            for key in &expired {
+                data.remove(key);
+            }
+            removed_count += expired.len();
+            removed.extend(expired.into_iter().map(|key| (index, key)));
+        }
+        for (index, key) in removed {
+            self.publish_keyspace_event(index, 'x', "expired", &key);
+            self.queue_replica_del(index, &key);
+        }
+        (sampled_count, removed_count)
+    }
+
+    // Replicas don't expire or evict keys on their own, so a master remembers each key it expires
+    // or evicts to send the replicas a DEL for it with the next propagate_dels
+    fn queue_replica_del(&mut self, database: usize, key: &str) {
+        if !matches!(self.replication_info.get("role"), Some(ReplicationInfoValue::StringValue(role)) if role == "slave") {
+            self.dels_to_propagate.push((database, key.to_string()));
+        }
+    }
+
+    pub fn has_dels_to_propagate(&self) -> bool {
+        !self.dels_to_propagate.is_empty()
+    }
+
+    // Send replicas a DEL for every key expired or evicted since the last call
+    pub async fn propagate_dels(&mut self) {
+        for (database, key) in std::mem::take(&mut self.dels_to_propagate) {
+            let del_message = format!("*2\r\n$3\r\nDEL\r\n${}\r\n{}\r\n", key.len(), key);
+            self.propagate_command(database, del_message.as_bytes()).await;
+        }
+    }
+
+    // Whether a config parameter the database reads (see apply_config) has a valid value
+    pub fn is_valid_config(name: &str, value: &str) -> bool {
+        match name {
+            "notify-keyspace-events" => value.chars().all(|flag| KEYSPACE_EVENT_FLAGS.contains(flag)),
+            "maxmemory" => parse_memory_size(value).is_some(),
+            "maxmemory-policy" => EvictionPolicy::from_name(value).is_some(),
+            "timeout" | "tcp-keepalive" | "hz" => value.parse::<u64>().is_ok(),
+            name if EncodingLimits::PARAMETERS.contains(&name) => EncodingLimits::default().set(name, value),
+            "tcp-nodelay" => value == "yes" || value == "no",
+            _ => true,
+        }
+    }
+
+    // Apply a config parameter the database itself reads; other parameters are ignored.
+    // Returns false, changing nothing, if the value is invalid.
+    pub fn apply_config(&mut self, name: &str, value: &str) -> bool {
+        match name {
+            "notify-keyspace-events" => self.set_keyspace_events(value),
+            "maxmemory" => parse_memory_size(value).map(|bytes| self.maxmemory = bytes).is_some(),
+            "maxmemory-policy" => EvictionPolicy::from_name(value).map(|policy| self.eviction_policy = policy).is_some(),
+            name if EncodingLimits::PARAMETERS.contains(&name) => self.encoding_limits.set(name, value),
+            _ => true,
+        }
+    }
+
+    // Approximate bytes used by the keys and values of every database. Each keyspace keeps a
+    // running total, so this doesn't walk the keys.
+    pub fn used_memory(&self) -> usize {
+        self.databases.iter().map(Keyspace::used_memory).sum()
+    }
+
+    // Evict keys following the eviction policy until usage is back under maxmemory. Returns
+    // false if it is still over the limit because the policy allows nothing (more) to be evicted.
+    pub fn evict_if_needed(&mut self) -> bool {
+        if self.maxmemory == 0 {
+            return true;
+        }
+        while self.used_memory() > self.maxmemory {
+            let Some((index, key)) = self.eviction_candidate() else {
+                return false;
+            };
+            self.databases[index].remove(&key);
+            self.publish_keyspace_event(index, 'e', "evicted", &key);
+            self.queue_replica_del(index, &key);
+        }
+        true
+    }
+
+    // Approximated LRU as in Redis: of a few keys sampled from each database that the policy
+    // allows evicting, the one idle the longest
+    fn eviction_candidate(&self) -> Option<(usize, String)> {
+        let volatile_only = match self.eviction_policy {
+            EvictionPolicy::NoEviction => return None,
+            EvictionPolicy::AllKeysLru => false,
+            EvictionPolicy::VolatileLru => true,
+        };
+        let mut candidate: Option<(usize, &String, Duration)> = None;
+        for (index, data) in self.databases.iter().enumerate() {
+            let sampled: Vec<(&String, &RedisValue)> = if volatile_only {
+                data.sample_volatile(MAXMEMORY_SAMPLES).collect()
+            } else {
+                data.sample(MAXMEMORY_SAMPLES).collect()
+            };
+            for (key, value) in sampled {
+                let idle_time = value.idle_time();
+                if candidate.is_none_or(|(_, _, longest)| idle_time > longest) {
+                    candidate = Some((index, key, idle_time));
+                }
+            }
+        }
+        candidate.map(|(index, key, _)| (index, key.clone()))
+    }
+
+    // Claim the background save for a BGSAVE, returning the flag to clear once it is written,
+    // or None if another one is still running
+    pub fn start_background_save(&self) -> Option<Arc<AtomicBool>> {
+        let already_running = self.background_save.swap(true, AtomicOrdering::AcqRel);
+        (!already_running).then(|| Arc::clone(&self.background_save))
+    }
+
+    // The notifier XREAD waits on for new entries in the stream at `key`
+    pub fn stream_notifier(&mut self, key: &str) -> Arc<Notify> {
+        Arc::clone(self.stream_notifiers.entry(key.to_string()).or_default())
+    }
+
+    // Drop the notifiers of the streams at `keys` that no blocked XREAD holds any more
+    pub fn release_stream_notifiers(&mut self, keys: &[String]) {
+        for key in keys {
+            if self.stream_notifiers.get(key).is_some_and(|notifier| Arc::strong_count(notifier) == 1) {
+                self.stream_notifiers.remove(key);
+            }
+        }
+    }
+
+    // Wake every XREAD currently blocked on the stream at `key`
+    pub fn notify_stream(&mut self, key: &str) {
+        if let Some(notifier) = self.stream_notifiers.get(key) {
+            notifier.notify_waiters();
+            // Drop the notifier once no reader holds it any more
+            if Arc::strong_count(notifier) == 1 {
+                self.stream_notifiers.remove(key);
+            }
+        }
     }
 
-    pub fn remove(&mut self, key: &str) {
-        self.data.remove(key);
+    // Subscribe to a channel, or with `pattern` set to every channel matching a glob
+    pub fn subscribe(&mut self, name: &str, subscriber: &UnboundedSender<PubSubMessage>, pattern: bool) {
+        let registry = if pattern { &mut self.pubsub_patterns } else { &mut self.pubsub_channels };
+        let subscribers = registry.entry(name.to_string()).or_default();
+        if !subscribers.iter().any(|existing| existing.same_channel(subscriber)) {
+            subscribers.push(subscriber.clone());
+        }
+    }
+
+    pub fn unsubscribe(&mut self, name: &str, subscriber: &UnboundedSender<PubSubMessage>, pattern: bool) {
+        let registry = if pattern { &mut self.pubsub_patterns } else { &mut self.pubsub_channels };
+        if let Some(subscribers) = registry.get_mut(name) {
+            subscribers.retain(|existing| !existing.same_channel(subscriber));
+            if subscribers.is_empty() {
+                registry.remove(name);
+            }
+        }
     }
 
-    // Update the replication info with either a string or a number
-    pub fn update_replication_info(&mut self, key: String, value: ReplicationInfoValue) {
-        self.replication_info.insert(key, value);
+    // Send the message to every connection subscribed to `channel` or to a pattern matching it,
+    // returning how many deliveries were made (a connection matching twice counts twice)
+    pub fn publish(&mut self, channel: &str, payload: &str) -> usize {
+        let mut receivers = 0;
+        if let Some(subscribers) = self.pubsub_channels.get_mut(channel) {
+            let message = PubSubMessage { pattern: None, channel: channel.to_string(), payload: payload.to_string() };
+            // Connections that closed without unsubscribing fail to receive and are dropped here
+            subscribers.retain(|subscriber| subscriber.send(message.clone()).is_ok());
+            receivers += subscribers.len();
+        }
+        for (pattern, subscribers) in self.pubsub_patterns.iter_mut() {
+            if glob_match(pattern.as_bytes(), channel.as_bytes()) {
+                let message = PubSubMessage { pattern: Some(pattern.clone()), channel: channel.to_string(), payload: payload.to_string() };
+                subscribers.retain(|subscriber| subscriber.send(message.clone()).is_ok());
+                receivers += subscribers.len();
+            }
+        }
+        self.pubsub_channels.retain(|_, subscribers| !subscribers.is_empty());
+        self.pubsub_patterns.retain(|_, subscribers| !subscribers.is_empty());
+        receivers
+    }
+
+    // Set the notify-keyspace-events flags. Returns false if they contain an unknown class.
+    pub fn set_keyspace_events(&mut self, flags: &str) -> bool {
+        if !flags.chars().all(|flag| KEYSPACE_EVENT_FLAGS.contains(flag)) {
+            return false;
+        }
+        self.keyspace_events = flags.replace('A', "g$lshzxet");
+        true
+    }
+
+    // Publish a keyspace notification for a change to `key` in the selected database, if
+    // notifications for the event's class (a notify-keyspace-events flag) are enabled
+    pub fn notify_keyspace_event(&mut self, class: char, event: &str, key: &str) {
+        self.publish_keyspace_event(selected_db(), class, event, key);
+    }
+
+    // Whether notify-keyspace-events has the class turned on
+    pub fn keyspace_events_enabled(&self, class: char) -> bool {
+        self.keyspace_events.contains(class)
+    }
+
+    // Send the notification for a key in `database`, whichever one is selected
+    pub fn publish_keyspace_event(&mut self, database: usize, class: char, event: &str, key: &str) {
+        if !self.keyspace_events.contains(class) {
+            return;
+        }
+        if self.keyspace_events.contains('K') {
+            self.publish(&format!("__keyspace@{}__:{}", database, key), event);
+        }
+        if self.keyspace_events.contains('E') {
+            self.publish(&format!("__keyevent@{}__:{}", database, event), key);
+        }
+    }
+
+    // Bytes of replication stream sent to replicas so far
+    pub fn master_repl_offset(&self) -> usize {
+        match self.replication_info.get("master_repl_offset") {
+            Some(ReplicationInfoValue::ByteValue(offset)) => *offset,
+            _ => 0,
+        }
+    }
+
+    fn add_master_repl_offset(&mut self, bytes: usize) {
+        let offset = self.master_repl_offset() + bytes;
+        self.replication_info.insert("master_repl_offset".to_string(), ReplicationInfoValue::ByteValue(offset));
+    }
+
+    // Send bytes down the replication stream to every replica. This is the only place the
+    // master offset moves, so it always equals the bytes replicas are given to consume.
+    pub async fn propagate(&mut self, message: &[u8]) {
+        for replica in self.slave_connections.read().await.iter() {
+            if let Err(e) = replica.lock().await.write_all(message).await {
+                eprintln!("Error sending message to slave: {:?}", e);
+            }
+        }
+        self.add_master_repl_offset(message.len());
+    }
+
+    // Propagate a command that acts on `database`, preceded by a SELECT when the replicas are
+    // currently applying the stream to a different database
+    pub async fn propagate_command(&mut self, database: usize, message: &[u8]) {
+        if let Some(select_message) = self.select_for_replicas(database) {
+            self.propagate(&select_message).await;
+        }
+        self.propagate(message).await;
+    }
+
+    // Propagate the writes an EXEC ran, each with the database it acted on, wrapped in MULTI and
+    // EXEC so replicas apply them all at once too. SELECTs go inside the block where needed.
+    pub async fn propagate_transaction(&mut self, writes: &[(usize, Vec<u8>)]) {
+        let mut message = b"*1\r\n$5\r\nMULTI\r\n".to_vec();
+        for (database, write) in writes {
+            if let Some(select_message) = self.select_for_replicas(*database) {
+                message.extend_from_slice(&select_message);
+            }
+            message.extend_from_slice(write);
+        }
+        message.extend_from_slice(b"*1\r\n$4\r\nEXEC\r\n");
+        self.propagate(&message).await;
+    }
+
+    // The SELECT replicas need before a command on `database`, if they are applying the stream to
+    // a different one, noting that they will have switched
+    fn select_for_replicas(&mut self, database: usize) -> Option<Vec<u8>> {
+        if self.replication_db == Some(database) {
+            return None;
+        }
+        self.replication_db = Some(database);
+        let index = database.to_string();
+        Some(format!("*2\r\n$6\r\nSELECT\r\n${}\r\n{}\r\n", index.len(), index).into_bytes())
+    }
+
+    // Start streaming to a replica that has just been sent the RDB file
+    pub async fn add_replica(&mut self, replica: SocketAddr, writer: Arc<Mutex<OwnedWriteHalf>>) {
+        self.slave_connections.write().await.push(writer);
+        // It has everything up to now once it has loaded the RDB file
+        let offset = self.master_repl_offset();
+        self.record_replica_ack(replica, offset);
+        // The new replica starts out in database 0, so the next command must SELECT again
+        self.replication_db = None;
+    }
+
+    // Bytes of the master's replication stream this replica has consumed
+    pub fn slave_repl_offset(&self) -> usize {
+        match self.replication_info.get("slave_repl_offset") {
+            Some(ReplicationInfoValue::ByteValue(offset)) => *offset,
+            _ => 0,
+        }
+    }
+
+    pub fn add_slave_repl_offset(&mut self, bytes: usize) {
+        let offset = self.slave_repl_offset() + bytes;
+        self.replication_info.insert("slave_repl_offset".to_string(), ReplicationInfoValue::ByteValue(offset));
+    }
+
+    pub fn record_replica_ack(&mut self, replica: SocketAddr, offset: usize) {
+        self.replica_ack_offsets.insert(replica, offset);
+        self.replica_acks.notify_waiters();
+    }
+
+    // How many replicas have acknowledged at least `offset` bytes of the replication stream
+    pub fn replicas_acked(&self, offset: usize) -> usize {
+        self.replica_ack_offsets.values().filter(|acked| **acked >= offset).count()
+    }
+
+    // The notifier WAIT waits on for new acknowledgements
+    pub fn replica_ack_notifier(&self) -> Arc<Notify> {
+        Arc::clone(&self.replica_acks)
+    }
+
+    pub fn shutdown_notifier(&self) -> Arc<Notify> {
+        Arc::clone(&self.shutdown_requested)
+    }
+
+    // Locked by a write command before it runs and by EXEC before it checks WATCHed keys, until
+    // the writes have reached the replicas. Writes then apply, bump versions and are forwarded
+    // one at a time, and none comes between a transaction's WATCH check and its last command.
+    pub fn write_turn(&self) -> Arc<Mutex<()>> {
+        Arc::clone(&self.write_turn)
+    }
+
+    pub fn encoding_limits(&self) -> &EncodingLimits {
+        &self.encoding_limits
+    }
+
+    pub fn active_expire_enabled(&self) -> bool {
+        self.active_expire
+    }
+
+    pub fn set_active_expire(&mut self, enabled: bool) {
+        self.active_expire = enabled;
     }
 
     // Get replication info as a string (for display or logging)
@@ -174,26 +921,74 @@ impl RedisDatabase {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 enum TtlState {
     Waiting(Duration),
     Expired,
 }
 
+impl TtlState {
+    fn from_millis(ttl_millis: u64) -> Self {
+        if ttl_millis == 0 {
+            TtlState::Expired
+        } else {
+            TtlState::Waiting(Duration::from_millis(ttl_millis))
+        }
+    }
+}
+
 // Define the value that Redis can hold
-#[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Clone)]
 pub enum RedisValueType {
-    IntegerValue(u64),
-    StringValue(String),
-    StreamValue(BTreeMap<StreamID, HashMap<String, String>>), // Stream is now a BTreeMap for ordered entries
+    IntegerValue(i64),
+    StringValue(Vec<u8>), // Raw bytes so binary payloads survive a round trip
+    StreamValue(Stream),
+    ListValue(VecDeque<String>), // Deque so both ends can be pushed and popped cheaply
+    HashValue(HashMap<String, String>),
+    SetValue(HashSet<String>),
+    ZSetValue(SortedSet),
 }
 
 
+// Milliseconds since the server started, the clock access times are kept in
+fn millis_since_start() -> u64 {
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_millis() as u64
+}
+
+// When a value was last accessed. Atomic so commands holding only the read lock can record it.
 #[derive(Debug)]
+struct LastAccess(AtomicU64);
+
+impl LastAccess {
+    fn now() -> Self {
+        LastAccess(AtomicU64::new(millis_since_start()))
+    }
+
+    fn touch(&self) {
+        self.0.store(millis_since_start(), AtomicOrdering::Relaxed);
+    }
+
+    fn elapsed(&self) -> Duration {
+        Duration::from_millis(millis_since_start().saturating_sub(self.0.load(AtomicOrdering::Relaxed)))
+    }
+}
+
+impl Clone for LastAccess {
+    fn clone(&self) -> Self {
+        LastAccess(AtomicU64::new(self.0.load(AtomicOrdering::Relaxed)))
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct RedisValue {
     value: RedisValueType,
     creation_time: Instant,
     ttl_state: Option<TtlState>,
+    last_access: LastAccess, // When a command last read or wrote the value, for OBJECT IDLETIME
+    version: u64, // Changed by every write to the key, see RedisDatabase::mark_modified
+    encoding: Option<&'static str>, // Encoding as of the last write; None until the first one
 }
 
 impl RedisValue {
@@ -202,21 +997,38 @@ impl RedisValue {
     where
         V: Into<RedisValueType>,
     {
-        let ttl_state = ttl_millis.map(|ttl| {
-            if ttl == 0 {
-                TtlState::Expired
-            } else {
-                TtlState::Waiting(Duration::from_millis(ttl))
-            }
-        });
-
         RedisValue {
             value: value.into(),
             creation_time: Instant::now(),
-            ttl_state,
+            ttl_state: ttl_millis.map(TtlState::from_millis),
+            last_access: LastAccess::now(),
+            version: 0,
+            encoding: None,
         }
     }
 
+    // The encoding OBJECT ENCODING reports. Values not written since they were loaded get
+    // theirs from their contents alone.
+    pub fn encoding(&self, limits: &EncodingLimits) -> &'static str {
+        self.encoding.unwrap_or_else(|| self.value.encoding(None, limits))
+    }
+
+    // Record an access; keyspace lookups through RedisDatabase::get and get_mut do this
+    pub fn touch(&self) {
+        self.last_access.touch();
+    }
+
+    // How long since the value was last accessed
+    pub fn idle_time(&self) -> Duration {
+        self.last_access.elapsed()
+    }
+
+    // Replace the TTL so the value expires `ttl_millis` from now
+    pub fn set_ttl(&mut self, ttl_millis: u64) {
+        self.creation_time = Instant::now();
+        self.ttl_state = Some(TtlState::from_millis(ttl_millis));
+    }
+
     pub fn is_expired(&self) -> bool {
         match self.ttl_state {
             Some(TtlState::Waiting(ttl)) => {
@@ -228,6 +1040,20 @@ impl RedisValue {
         }
     }
 
+    // Remove the TTL, returning whether there was one to remove
+    pub fn clear_ttl(&mut self) -> bool {
+        self.ttl_state.take().is_some()
+    }
+
+    // Time left before the value expires, or None if it has no TTL
+    pub fn remaining_ttl(&self) -> Option<Duration> {
+        match self.ttl_state {
+            Some(TtlState::Waiting(ttl)) => Some(ttl.saturating_sub(self.creation_time.elapsed())),
+            Some(TtlState::Expired) => Some(Duration::ZERO),
+            None => None,
+        }
+    }
+
     pub fn get_value(&self) -> &RedisValueType  {
         &self.value
     }
@@ -238,30 +1064,252 @@ impl RedisValue {
 }
 
 // Implement the conversion from String to RedisValueType
+// Strings are stored verbatim; only INCR decides to treat a value as an integer
 impl From<String> for RedisValueType {
     fn from(s: String) -> Self {
-        // Attempt to parse the string as a u64
-        if let Ok(int_value) = s.parse::<u64>() {
-            RedisValueType::IntegerValue(int_value)
-        } else {
-            RedisValueType::StringValue(s)
-        }
+        RedisValueType::StringValue(s.into_bytes())
+    }
+}
+
+// Implement the conversion from raw bytes to RedisValueType
+impl From<Vec<u8>> for RedisValueType {
+    fn from(bytes: Vec<u8>) -> Self {
+        RedisValueType::StringValue(bytes)
     }
 }
 
-// Implement the conversion from HashMap<StreamID, HashMap<String, String>> to RedisValueType
-impl From<BTreeMap<StreamID, HashMap<String, String>>> for RedisValueType {
-    fn from(stream: BTreeMap<StreamID, HashMap<String, String>>) -> Self {
+// Implement the conversion from Stream to RedisValueType
+impl From<Stream> for RedisValueType {
+    fn from(stream: Stream) -> Self {
         RedisValueType::StreamValue(stream)
     }
 }
 
+// Implement the conversion from VecDeque<String> to RedisValueType
+impl From<VecDeque<String>> for RedisValueType {
+    fn from(list: VecDeque<String>) -> Self {
+        RedisValueType::ListValue(list)
+    }
+}
+
+// Implement the conversion from HashMap<String, String> to RedisValueType
+impl From<HashMap<String, String>> for RedisValueType {
+    fn from(hash: HashMap<String, String>) -> Self {
+        RedisValueType::HashValue(hash)
+    }
+}
+
+// Implement the conversion from HashSet<String> to RedisValueType
+impl From<HashSet<String>> for RedisValueType {
+    fn from(set: HashSet<String>) -> Self {
+        RedisValueType::SetValue(set)
+    }
+}
+
+// Implement the conversion from SortedSet to RedisValueType
+impl From<SortedSet> for RedisValueType {
+    fn from(zset: SortedSet) -> Self {
+        RedisValueType::ZSetValue(zset)
+    }
+}
+
+// Rough per-entry bookkeeping cost (hash table slot, RedisValue, allocation headers), added to
+// the payload bytes when estimating memory use
+const ENTRY_OVERHEAD: usize = 64;
+const ELEMENT_OVERHEAD: usize = 16;
+
+fn entry_memory_usage(key: &str, value: &RedisValue) -> usize {
+    ENTRY_OVERHEAD + key.len() + value.get_value().memory_usage()
+}
+
+// Size limits below which Redis keeps small values in their compact encodings
+const EMBSTR_SIZE_LIMIT: usize = 44;
+
+// Bytes a listpack spends on its header and end marker, and at most on each entry besides its data
+const LISTPACK_HEADER_SIZE: usize = 7;
+const LISTPACK_ENTRY_OVERHEAD: usize = 2;
+
+// The thresholds at which collections outgrow their compact encodings, set with the
+// *-max-listpack-* and set-max-intset-entries configs (defaults as in Redis)
+#[derive(Debug, Clone, Copy)]
+pub struct EncodingLimits {
+    list_max_listpack_size: i64, // Entries per list if positive; if negative, -1 to -5 cap it at 4KB to 64KB
+    hash_max_listpack_entries: usize,
+    hash_max_listpack_value: usize,
+    set_max_intset_entries: usize,
+    set_max_listpack_entries: usize,
+    set_max_listpack_value: usize,
+    zset_max_listpack_entries: usize,
+    zset_max_listpack_value: usize,
+}
+
+impl Default for EncodingLimits {
+    fn default() -> Self {
+        Self {
+            list_max_listpack_size: -2,
+            hash_max_listpack_entries: 128,
+            hash_max_listpack_value: 64,
+            set_max_intset_entries: 512,
+            set_max_listpack_entries: 128,
+            set_max_listpack_value: 64,
+            zset_max_listpack_entries: 128,
+            zset_max_listpack_value: 64,
+        }
+    }
+}
+
+impl EncodingLimits {
+    pub const PARAMETERS: &'static [&'static str] = &[
+        "list-max-listpack-size",
+        "hash-max-listpack-entries",
+        "hash-max-listpack-value",
+        "set-max-intset-entries",
+        "set-max-listpack-entries",
+        "set-max-listpack-value",
+        "zset-max-listpack-entries",
+        "zset-max-listpack-value",
+    ];
+
+    // Set the threshold a config parameter controls; false if it isn't one or the value is invalid
+    pub fn set(&mut self, name: &str, value: &str) -> bool {
+        let limit = match name {
+            "list-max-listpack-size" => return value.parse().map(|size| self.list_max_listpack_size = size).is_ok(),
+            "hash-max-listpack-entries" => &mut self.hash_max_listpack_entries,
+            "hash-max-listpack-value" => &mut self.hash_max_listpack_value,
+            "set-max-intset-entries" => &mut self.set_max_intset_entries,
+            "set-max-listpack-entries" => &mut self.set_max_listpack_entries,
+            "set-max-listpack-value" => &mut self.set_max_listpack_value,
+            "zset-max-listpack-entries" => &mut self.zset_max_listpack_entries,
+            "zset-max-listpack-value" => &mut self.zset_max_listpack_value,
+            _ => return false,
+        };
+        value.parse().map(|value| *limit = value).is_ok()
+    }
+
+    // Whether a list fits in one listpack with the limit divided by `divisor`. Redis converts a
+    // quicklist back to a listpack only once it fits in half the limit, so it doesn't flip
+    // back and forth around the threshold.
+    fn list_fits(&self, list: &VecDeque<String>, divisor: usize) -> bool {
+        if self.list_max_listpack_size > 0 {
+            return list.len() <= self.list_max_listpack_size as usize / divisor;
+        }
+        let max_bytes = 4096 << (self.list_max_listpack_size.unsigned_abs().clamp(1, 5) - 1);
+        let bytes = LISTPACK_HEADER_SIZE + list.iter().map(|element| element.len() + LISTPACK_ENTRY_OVERHEAD).sum::<usize>();
+        bytes <= max_bytes / divisor
+    }
+}
+
+// Whether a string is an integer Redis would store as one (canonical form that round-trips)
+fn is_integer_string(bytes: &[u8]) -> bool {
+    std::str::from_utf8(bytes)
+        .ok()
+        .and_then(|string| string.parse::<i64>().ok())
+        .is_some_and(|integer| integer.to_string().as_bytes() == bytes)
+}
+
+fn fits_listpack<'a>(len: usize, mut values: impl Iterator<Item = &'a String>, max_entries: usize, max_value: usize) -> bool {
+    len <= max_entries && values.all(|value| value.len() <= max_value)
+}
+
 impl RedisValueType {
-    pub fn len(&self) -> usize {
+    // The type name TYPE reports; integers are strings as far as clients can tell
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            RedisValueType::IntegerValue(_) | RedisValueType::StringValue(_) => "string",
+            RedisValueType::StreamValue(_) => "stream",
+            RedisValueType::ListValue(_) => "list",
+            RedisValueType::HashValue(_) => "hash",
+            RedisValueType::SetValue(_) => "set",
+            RedisValueType::ZSetValue(_) => "zset",
+        }
+    }
+
+    // Approximate bytes the value takes up: its payload plus a fixed overhead per element
+    pub fn memory_usage(&self) -> usize {
+        let strings = |strings: &mut dyn Iterator<Item = &String>| strings.map(|string| ELEMENT_OVERHEAD + string.len()).sum::<usize>();
+        match self {
+            RedisValueType::IntegerValue(_) => 8,
+            RedisValueType::StringValue(bytes) => bytes.len(),
+            RedisValueType::StreamValue(stream) => stream
+                .entries()
+                .values()
+                .map(|fields| ELEMENT_OVERHEAD + strings(&mut fields.iter().flat_map(|(field, value)| [field, value])))
+                .sum(),
+            RedisValueType::ListValue(list) => strings(&mut list.iter()),
+            RedisValueType::HashValue(hash) => strings(&mut hash.iter().flat_map(|(field, value)| [field, value])),
+            RedisValueType::SetValue(set) => strings(&mut set.iter()),
+            RedisValueType::ZSetValue(zset) => zset.iter().map(|(member, _)| 2 * (ELEMENT_OVERHEAD + member.len()) + 8).sum(),
+        }
+    }
+
+    // The encoding to report after a write, given the value's encoding before it (None for a
+    // new value). As in Redis, hashes, sets and sorted sets keep their larger encoding once
+    // they have outgrown a compact one, even if they shrink again.
+    pub fn encoding(&self, previous: Option<&'static str>, limits: &EncodingLimits) -> &'static str {
+        match self {
+            RedisValueType::IntegerValue(_) => "int",
+            RedisValueType::StringValue(bytes) if bytes.len() <= 20 && is_integer_string(bytes) => "int",
+            RedisValueType::StringValue(bytes) if bytes.len() <= EMBSTR_SIZE_LIMIT => "embstr",
+            RedisValueType::StringValue(_) => "raw",
+            RedisValueType::StreamValue(_) => "stream",
+            RedisValueType::ListValue(list) if previous == Some("quicklist") && !limits.list_fits(list, 2) => "quicklist",
+            RedisValueType::ListValue(list) if limits.list_fits(list, 1) => "listpack",
+            RedisValueType::ListValue(_) => "quicklist",
+            RedisValueType::HashValue(_) if previous == Some("hashtable") => "hashtable",
+            RedisValueType::HashValue(hash)
+                if fits_listpack(hash.len(), hash.iter().flat_map(|(field, value)| [field, value]), limits.hash_max_listpack_entries, limits.hash_max_listpack_value) =>
+            {
+                "listpack"
+            }
+            RedisValueType::HashValue(_) => "hashtable",
+            RedisValueType::SetValue(_) if previous == Some("hashtable") => "hashtable",
+            // Once a set has held a non-integer it stays a listpack
+            RedisValueType::SetValue(set)
+                if matches!(previous, None | Some("intset"))
+                    && set.len() <= limits.set_max_intset_entries
+                    && set.iter().all(|member| is_integer_string(member.as_bytes())) =>
+            {
+                "intset"
+            }
+            RedisValueType::SetValue(set) if fits_listpack(set.len(), set.iter(), limits.set_max_listpack_entries, limits.set_max_listpack_value) => "listpack",
+            RedisValueType::SetValue(_) => "hashtable",
+            RedisValueType::ZSetValue(_) if previous == Some("skiplist") => "skiplist",
+            RedisValueType::ZSetValue(zset)
+                if fits_listpack(zset.len(), zset.iter().map(|(member, _)| member), limits.zset_max_listpack_entries, limits.zset_max_listpack_value) =>
+            {
+                "listpack"
+            }
+            RedisValueType::ZSetValue(_) => "skiplist",
+        }
+    }
+
+    // The exact bytes a string value replies with, or None for non-string types
+    pub fn string_bytes(&self) -> Option<Vec<u8>> {
+        match self {
+            RedisValueType::StringValue(bytes) => Some(bytes.clone()),
+            RedisValueType::IntegerValue(integer) => Some(integer.to_string().into_bytes()),
+            _ => None,
+        }
+    }
+
+    // Length of a string value as GET would return it, or None for non-string types
+    pub fn string_len(&self) -> Option<usize> {
+        match self {
+            RedisValueType::StringValue(bytes) => Some(bytes.len()),
+            RedisValueType::IntegerValue(integer) => Some(integer.to_string().len()),
+            _ => None,
+        }
+    }
+
+    // Mutable access to a string value's bytes, turning an integer back into its string form.
+    // Returns None for non-string types.
+    pub fn string_bytes_mut(&mut self) -> Option<&mut Vec<u8>> {
+        if let RedisValueType::IntegerValue(integer) = self {
+            *self = RedisValueType::StringValue(integer.to_string().into_bytes());
+        }
         match self {
-            RedisValueType::IntegerValue(integer) => {let int_str = integer.to_string(); int_str.len()},
-            RedisValueType::StringValue(s) => s.len(),
-            RedisValueType::StreamValue(map) => map.len(),
+            RedisValueType::StringValue(bytes) => Some(bytes),
+            _ => None,
         }
     }
 }
@@ -271,19 +1319,23 @@ impl fmt::Display for RedisValueType {
         match self {
             RedisValueType::IntegerValue(integer) => {let int_str = integer.to_string(); write!(f, "{}", int_str)}
             RedisValueType::StringValue(s) => {
-                write!(f, "{}", s)
+                write!(f, "{}", String::from_utf8_lossy(s))
             }
-            RedisValueType::StreamValue(map) => {
-                write!(f, "{{\n")?;
-                for (key, inner_map) in map {
-                    write!(f, "  {}: {{\n", key)?; // StreamID's Display will handle key
+            RedisValueType::StreamValue(stream) => {
+                writeln!(f, "{{")?;
+                for (key, inner_map) in stream.entries() {
+                    writeln!(f, "  {}: {{", key)?; // StreamID's Display will handle key
                     for (k, v) in inner_map {
-                        write!(f, "    {}: {},\n", k, v)?; // Indent key-value pairs
+                        writeln!(f, "    {}: {},", k, v)?; // Indent key-value pairs
                     }
-                    write!(f, "  }},\n")?;
+                    writeln!(f, "  }},")?;
                 }
                 write!(f, "}}")
             }
+            RedisValueType::ListValue(list) => write!(f, "{:?}", list),
+            RedisValueType::HashValue(hash) => write!(f, "{:?}", hash),
+            RedisValueType::SetValue(set) => write!(f, "{:?}", set),
+            RedisValueType::ZSetValue(zset) => write!(f, "{:?}", zset.iter().collect::<Vec<_>>()),
         }
     }
 }
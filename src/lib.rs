@@ -0,0 +1,115 @@
+use std::{collections::HashMap, path::Path, sync::Arc, time::Duration};
+use tokio::sync::{watch, RwLock};
+use tokio::task::{self, JoinHandle};
+
+pub mod replication;
+pub mod network;
+pub mod database;
+pub mod commands;
+pub mod command_table;
+pub mod parsing;
+pub mod rdb_parser;
+pub mod rdb_writer;
+pub mod utils;
+
+use replication::initialize_replication;
+use network::{bind_listeners, serve};
+use database::RedisDatabase;
+use rdb_parser::parse_rdb_file;
+use rdb_writer::rdb_checksum_enabled;
+
+// Load the RDB file named by dir and dbfilename, if there is one, and apply the config
+// parameters the database reads
+pub fn initialize_database(config_map: &HashMap<String, String>) -> RedisDatabase {
+    let mut db = RedisDatabase::new();
+    if let Some(dir) = config_map.get("dir") {
+        if let Some(dbfilename) = config_map.get("dbfilename") {
+            let rdb_path = Path::new(dir).join(dbfilename);
+            if let Err(e) = parse_rdb_file(rdb_path.to_str().unwrap(), &mut db, rdb_checksum_enabled(config_map)) {
+                println!("Failed to parse RDB file: {}. Starting with an empty database.", e);
+            }
+        }
+    }
+    for (name, value) in config_map {
+        if !db.apply_config(name, value) {
+            println!("Ignoring invalid {}: {}", name, value);
+        }
+    }
+    println!("Database data: {:?}", db.databases());
+    db
+}
+
+// The port replicas announce to their master; with several ports, the first one
+pub fn replication_port(config_map: &HashMap<String, String>) -> String {
+    config_map.get("port").and_then(|ports| ports.split(',').next()).unwrap_or("6379").trim().to_string()
+}
+
+// How many times per second active expiration runs: the hz config, which Redis defaults to 10
+// and accepts from 1 to 500
+pub fn active_expire_hz(config_map: &HashMap<String, String>) -> u64 {
+    config_map.get("hz").and_then(|hz| hz.parse::<u64>().ok()).unwrap_or(10).clamp(1, 500)
+}
+
+// How many keys each active expiration cycle looks at, and the share of them that must
+// turn out expired for the cycle to keep going straight away (as in Redis)
+const ACTIVE_EXPIRE_SAMPLE_SIZE: usize = 20;
+const ACTIVE_EXPIRE_REPEAT_PERCENT: usize = 25;
+
+// Periodically remove expired keys that nobody reads, `hz` times per second
+pub async fn run_active_expiration(db: Arc<RwLock<RedisDatabase>>, hz: u64) {
+    let mut interval = tokio::time::interval(Duration::from_millis(1000 / hz));
+    loop {
+        interval.tick().await;
+        loop {
+            let (sampled, removed) = {
+                let mut db_lock = db.write().await;
+                if !db_lock.active_expire_enabled() {
+                    break;
+                }
+                let sample = db_lock.remove_expired_sample(ACTIVE_EXPIRE_SAMPLE_SIZE);
+                db_lock.propagate_dels().await;
+                sample
+            };
+            if sampled == 0 || removed * 100 <= sampled * ACTIVE_EXPIRE_REPEAT_PERCENT {
+                break;
+            }
+        }
+    }
+}
+
+// Start a master with an empty dataset and the default config on `port`, for integration tests.
+// See start_test_server_with_config.
+pub async fn start_test_server(port: u16) -> JoinHandle<()> {
+    start_test_server_with_config(HashMap::from([("port".to_string(), port.to_string())])).await
+}
+
+// Start a server from command-line style config the way main does: load the dataset, run
+// active expiration and replication, and serve clients. It returns once the listeners are
+// bound, so tests can connect straight away, and the server runs until the returned task is
+// aborted or a client sends SHUTDOWN. Unlike main it never saves on the way out.
+pub async fn start_test_server_with_config(config_map: HashMap<String, String>) -> JoinHandle<()> {
+    let port = replication_port(&config_map);
+    let hz = active_expire_hz(&config_map);
+    let db = Arc::new(RwLock::new(initialize_database(&config_map)));
+    let config_map = Arc::new(RwLock::new(config_map));
+    let listeners = bind_listeners(&config_map).await.expect("Test server could not bind");
+    task::spawn(async move {
+        let (shutdown_sender, shutdown) = watch::channel(false);
+        let shutdown_requested = db.read().await.shutdown_notifier();
+        let background = async {
+            tokio::join!(
+                initialize_replication(&config_map, Arc::clone(&db), &port),
+                run_active_expiration(Arc::clone(&db), hz),
+            )
+        };
+        // SHUTDOWN closes the connections and ends the task once serve has returned
+        let stop = async {
+            shutdown_requested.notified().await;
+            let _ = shutdown_sender.send(true);
+        };
+        tokio::select! {
+            _ = background => {}
+            _ = async { tokio::join!(serve(listeners, Arc::clone(&config_map), Arc::clone(&db), shutdown), stop) } => {}
+        }
+    })
+}
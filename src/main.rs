@@ -1,33 +1,12 @@
-use std::{env, collections::HashMap, path::Path, sync::Arc};
-use tokio::sync::Mutex;
+use std::{env, collections::HashMap, sync::Arc};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{watch, RwLock};
 use tokio::task;
 
-mod replication;
-mod network;
-mod database;
-mod commands;
-mod parsing;
-mod rdb_parser;
-mod utils;
-
-use replication::initialize_replication;
-use network::start_server;
-use database::RedisDatabase;
-use rdb_parser::parse_rdb_file;
-
-fn initialize_database(config_map: &HashMap<String, String>) -> RedisDatabase {
-    let mut db = RedisDatabase::new();
-    if let Some(dir) = config_map.get("dir") {
-        if let Some(dbfilename) = config_map.get("dbfilename") {
-            let rdb_path = Path::new(dir).join(dbfilename);
-            if let Err(e) = parse_rdb_file(rdb_path.to_str().unwrap(), &mut db) {
-                println!("Failed to parse RDB file: {}. Starting with an empty database.", e);
-            }
-        }
-    }
-    println!("Database data: {:?}", db.data);
-    db
-}
+use redis_starter_rust::{active_expire_hz, initialize_database, replication_port, run_active_expiration};
+use redis_starter_rust::commands::save_rdb;
+use redis_starter_rust::replication::initialize_replication;
+use redis_starter_rust::network::start_server;
 
 #[tokio::main]
 async fn main() {
@@ -37,11 +16,10 @@ async fn main() {
     let mut i = 1;
     while i < args.len() {
         let key = &args[i];
-        if key.starts_with("--") {
+        if let Some(name) = key.strip_prefix("--") {
             if i + 1 < args.len() {
                 let value = args[i + 1].clone();
-                let key = key[2..].to_string();
-                config_map.insert(key, value);
+                config_map.insert(name.to_string(), value);
                 i += 2;
             } else {
                 eprintln!("Missing value for key: {}", key);
@@ -53,31 +31,62 @@ async fn main() {
         }
     }
 
-    let default_port = "6379".to_string();
-    let port = config_map.get("port").unwrap_or(&default_port).to_string();
+    let port = replication_port(&config_map);
 
     println!("Starting server with config: {:?}", config_map);
 
-    // Use tokio::sync::Mutex for async use
-    let db = Arc::new(Mutex::new(initialize_database(&config_map)));
-    
+    // Read-only commands share the lock, see read_live_keys in commands.rs
+    let db = Arc::new(RwLock::new(initialize_database(&config_map)));
+    let hz = active_expire_hz(&config_map);
+    let config_map = Arc::new(RwLock::new(config_map));
+
     let server_db = Arc::clone(&db);
-    let server_config = config_map.clone();
+    let server_config = Arc::clone(&config_map);
+    let (shutdown_sender, shutdown) = watch::channel(false);
     
     // Use tokio::spawn to create asynchronous tasks
-    let server_task = task::spawn(async move {
-        if let Err(e) = start_server(server_config, server_db).await {
+    let mut server_task = task::spawn(async move {
+        if let Err(e) = start_server(server_config, server_db, shutdown).await {
             eprintln!("Server failed: {}", e);
         }
     });
 
+    task::spawn(run_active_expiration(Arc::clone(&db), hz));
+
     let replication_db = Arc::clone(&db);
-    let replication_config = config_map.clone();
+    let replication_config = Arc::clone(&config_map);
     
     let replication_task = task::spawn(async move {
         initialize_replication(&replication_config, replication_db, &port).await;
     });
 
-    // Wait for both tasks to complete
-    let _ = tokio::try_join!(server_task, replication_task);
+    // Run until the server fails, or a signal or the SHUTDOWN command asks it to stop
+    let shutdown_requested = db.read().await.shutdown_notifier();
+    let save_on_exit = tokio::select! {
+        _ = &mut server_task => return,
+        _ = shutdown_signal() => true,
+        // SHUTDOWN has already saved if it was going to
+        _ = shutdown_requested.notified() => false,
+    };
+    println!("Shutting down, closing connections");
+    let _ = shutdown_sender.send(true);
+    let _ = server_task.await;
+    replication_task.abort();
+
+    // Only save when an RDB file is configured, so a plain run doesn't leave a dump behind
+    if save_on_exit && config_map.read().await.contains_key("dbfilename") {
+        match save_rdb(&db, &config_map).await {
+            Ok(()) => println!("DB saved on disk"),
+            Err(e) => eprintln!("Failed to save the RDB file on shutdown: {}", e),
+        }
+    }
+}
+
+// Resolves on Ctrl-C (SIGINT) or SIGTERM
+async fn shutdown_signal() {
+    let mut terminate = signal(SignalKind::terminate()).expect("Failed to install the SIGTERM handler");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
 }
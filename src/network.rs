@@ -1,33 +1,112 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::sync::Mutex;
-use tokio::net::{TcpListener, TcpStream};
+use bytes::BytesMut;
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use tokio::sync::{Mutex, RwLock};
+use tokio::net::{TcpListener, TcpSocket, TcpStream};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::time::{timeout, Duration};
-use crate::commands::send_rdb_file;
-use crate::database::{RedisDatabase, ReplicationInfoValue};
+use crate::commands::{pubsub_message_frame, send_rdb_file};
+use crate::database::{PubSubMessage, RedisDatabase, ReplicationInfoValue};
 use crate::parsing::parse_redis_message;
-use crate::utils::{get_end_of_redis_message, should_forward_to_slaves};
+use crate::utils::{get_end_of_redis_message, READ_CHUNK_SIZE};
 
-pub async fn start_server(config_map: HashMap<String, String>, db: Arc<Mutex<RedisDatabase>>) -> std::io::Result<()> {
-    let default_port = "6379".to_string();
-    let port = config_map.get("port").unwrap_or(&default_port).to_string();
-    let address = format!("127.0.0.1:{}", port);
+// Server config shared by every connection, so CONFIG SET is visible to all clients
+pub type SharedConfig = Arc<RwLock<HashMap<String, String>>>;
 
-    let listener = TcpListener::bind(&address).await?;
-    println!("Server listening on {}", address);
+// How long open connections get to finish the command they are running once shutdown starts
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(2);
 
+// Serve clients until `shutdown` becomes true, then stop accepting and return once the open
+// connections have closed or the grace period is over
+pub async fn start_server(
+    config_map: SharedConfig,
+    db: Arc<RwLock<RedisDatabase>>,
+    shutdown: watch::Receiver<bool>,
+) -> std::io::Result<()> {
+    let listeners = bind_listeners(&config_map).await?;
+    serve(listeners, config_map, db, shutdown).await;
+    Ok(())
+}
+
+// Bind every configured address. One that can't be bound is logged and doesn't stop the
+// others; it is only an error if none of them can be.
+pub async fn bind_listeners(config_map: &SharedConfig) -> std::io::Result<Vec<TcpListener>> {
+    let addresses = listen_addresses(&*config_map.read().await)?;
+
+    let mut listeners = Vec::new();
+    for address in addresses {
+        match bind_listener(address) {
+            Ok(listener) => {
+                println!("Server listening on {}", address);
+                listeners.push(listener);
+            }
+            Err(e) => eprintln!("Could not bind to {}: {}", address, e),
+        }
+    }
+    if listeners.is_empty() {
+        return Err(std::io::Error::new(std::io::ErrorKind::AddrNotAvailable, "Could not bind to any address"));
+    }
+    Ok(listeners)
+}
+
+// Run an accept loop per listener until `shutdown` becomes true. Dropping the future stops
+// them all, along with their connections.
+pub async fn serve(
+    listeners: Vec<TcpListener>,
+    config_map: SharedConfig,
+    db: Arc<RwLock<RedisDatabase>>,
+    shutdown: watch::Receiver<bool>,
+) {
+    let mut accept_loops = JoinSet::new();
+    for listener in listeners {
+        accept_loops.spawn(accept_connections(listener, Arc::clone(&config_map), Arc::clone(&db), shutdown.clone()));
+    }
+    while accept_loops.join_next().await.is_some() {}
+}
+
+async fn accept_connections(
+    listener: TcpListener,
+    config_map: SharedConfig,
+    db: Arc<RwLock<RedisDatabase>>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let mut connections = JoinSet::new();
     loop {
-        match listener.accept().await {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            Ok(()) = shutdown.changed() => break,
+        };
+        match accepted {
             Ok((stream, addr)) => {
                 println!("New client connection from {}", addr);
 
+                // Read as of this connection, so CONFIG SET applies to every new one
+                let (nodelay, keepalive_seconds) = {
+                    let config_map = config_map.read().await;
+                    (config_map.get("tcp-nodelay").is_none_or(|value| value == "yes"), tcp_keepalive_seconds(&config_map))
+                };
+                // Replies are mostly small, so don't let Nagle's algorithm hold them back
+                if let Err(e) = stream.set_nodelay(nodelay) {
+                    eprintln!("Could not set TCP_NODELAY for {}: {}", addr, e);
+                }
+                if keepalive_seconds > 0 {
+                    if let Err(e) = set_keepalive(&stream, keepalive_seconds) {
+                        eprintln!("Could not enable TCP keepalive for {}: {}", addr, e);
+                    }
+                }
+
                 let db = Arc::clone(&db);
-                let config_map = config_map.clone();
+                let config_map = Arc::clone(&config_map);
+                let shutdown = shutdown.clone();
 
                 // Spawn a new async task to handle the client connection
-                tokio::spawn(async move {
-                    if let Err(e) = handle_client(stream, db, &config_map).await {
+                connections.spawn(async move {
+                    if let Err(e) = handle_client(stream, db, &config_map, shutdown).await {
                         eprintln!("Error handling client: {}", e);
                     }
                 });
@@ -36,280 +115,387 @@ pub async fn start_server(config_map: HashMap<String, String>, db: Arc<Mutex<Red
                 eprintln!("Connection failed: {}", e);
             }
         }
+        // Forget connections that have closed
+        while connections.try_join_next().is_some() {}
     }
+
+    // Connections close after their current command; any still open after the grace period
+    // are dropped along with the set
+    let drained = timeout(SHUTDOWN_GRACE_PERIOD, async { while connections.join_next().await.is_some() {} }).await;
+    if drained.is_err() {
+        eprintln!("Closing {} connections that didn't finish in time", connections.len());
+    }
+}
+
+fn bind_listener(address: SocketAddr) -> std::io::Result<TcpListener> {
+    let socket = if address.is_ipv4() { TcpSocket::new_v4()? } else { TcpSocket::new_v6()? };
+    socket.set_reuseaddr(true)?;
+    socket.bind(address)?;
+    socket.listen(1024)
+}
+
+// The tcp-keepalive config: seconds a connection may sit idle before the kernel probes the
+// peer, 300 by default as in Redis, or 0 to not probe at all
+fn tcp_keepalive_seconds(config_map: &HashMap<String, String>) -> u64 {
+    config_map.get("tcp-keepalive").and_then(|seconds| seconds.parse().ok()).unwrap_or(300)
 }
 
+// Socket options from the Linux headers, which std links against but doesn't expose
+#[cfg(target_os = "linux")]
+mod sockopt {
+    pub const SOL_SOCKET: i32 = 1;
+    pub const SO_KEEPALIVE: i32 = 9;
+    pub const IPPROTO_TCP: i32 = 6;
+    pub const TCP_KEEPIDLE: i32 = 4;
+    pub const TCP_KEEPINTVL: i32 = 5;
+    pub const TCP_KEEPCNT: i32 = 6;
+
+    extern "C" {
+        pub fn setsockopt(socket: i32, level: i32, name: i32, value: *const std::ffi::c_void, length: u32) -> i32;
+    }
+}
+
+// Have the kernel probe the peer once the connection has been idle for `seconds`, then every
+// third of that, and drop it after three unanswered probes, as Redis does for tcp-keepalive
+#[cfg(target_os = "linux")]
+fn set_keepalive(stream: &TcpStream, seconds: u64) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+    let idle = i32::try_from(seconds).unwrap_or(i32::MAX);
+    let options = [
+        (sockopt::SOL_SOCKET, sockopt::SO_KEEPALIVE, 1),
+        (sockopt::IPPROTO_TCP, sockopt::TCP_KEEPIDLE, idle),
+        (sockopt::IPPROTO_TCP, sockopt::TCP_KEEPINTVL, (idle / 3).max(1)),
+        (sockopt::IPPROTO_TCP, sockopt::TCP_KEEPCNT, 3),
+    ];
+    for (level, name, value) in options {
+        // SAFETY: the descriptor stays open for the call, and `value` outlives it with the length given
+        let result = unsafe {
+            sockopt::setsockopt(stream.as_raw_fd(), level, name, (&value as *const i32).cast(), size_of::<i32>() as u32)
+        };
+        if result != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+// Elsewhere the kernel's own keepalive settings are left alone
+#[cfg(not(target_os = "linux"))]
+fn set_keepalive(_stream: &TcpStream, _seconds: u64) -> std::io::Result<()> {
+    Ok(())
+}
+
+// The addresses to listen on: every combination of the comma-separated bind (default
+// 127.0.0.1, or 0.0.0.0 for all interfaces) and port (default 6379) options
+fn listen_addresses(config_map: &HashMap<String, String>) -> std::io::Result<Vec<SocketAddr>> {
+    let invalid = |message: String| std::io::Error::new(std::io::ErrorKind::InvalidInput, message);
+    let mut ips = Vec::new();
+    for bind in config_map.get("bind").map_or("127.0.0.1", String::as_str).split(',') {
+        ips.push(bind.trim().parse::<IpAddr>().map_err(|_| invalid(format!("Invalid bind address '{}'", bind)))?);
+    }
+    let mut addresses = Vec::new();
+    for port in config_map.get("port").map_or("6379", String::as_str).split(',') {
+        let port: u16 = port.trim().parse().map_err(|_| invalid(format!("Invalid port '{}'", port)))?;
+        addresses.extend(ips.iter().map(|&ip| SocketAddr::new(ip, port)));
+    }
+    Ok(addresses)
+}
+
+// A command name and its raw arguments, as queued between MULTI and EXEC
+pub type QueuedCommand = (String, Vec<Vec<u8>>);
+
+// Source of connection IDs for CLIENT ID, which start at 1 and are never reused
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
+
 pub struct ClientState {
-    multi_queue: Option<Vec<(String, Vec<String>)>>, // Commands queued after MULTI
-    in_transaction: bool, // Whether the client is in MULTI/EXEC mode
+    id: u64, // Unique ID reported by CLIENT ID
+    name: Option<String>, // Set with CLIENT SETNAME
+    multi_queue: Option<Vec<QueuedCommand>>, // Commands queued after MULTI; Some while in a transaction
+    multi_error: bool, // Whether a command failed to queue, which makes EXEC discard the transaction
+    authenticated: bool, // Whether AUTH succeeded, which requirepass makes necessary
+    user: String, // ACL user the connection runs as, reported by ACL WHOAMI
+    replication_link: bool, // Whether this is a replica's connection to its master
+    selected_db: usize, // Database chosen with SELECT
+    protocol: u8, // RESP version negotiated with HELLO
+    push_sender: Option<UnboundedSender<PubSubMessage>>, // Where published messages for this connection are sent
+    channels: HashSet<String>, // Channels subscribed to
+    patterns: HashSet<String>, // Glob patterns subscribed to with PSUBSCRIBE
+    watched_keys: Vec<(usize, String, Option<u64>)>, // WATCHed keys with their database and version at the time
+}
+
+impl Default for ClientState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ClientState {
     pub fn new() -> Self{
         ClientState{
+            id: NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed),
+            name: None,
             multi_queue: None,
-            in_transaction: false,
+            multi_error: false,
+            authenticated: false,
+            user: "default".to_string(),
+            replication_link: false,
+            selected_db: 0,
+            protocol: 2,
+            push_sender: None,
+            channels: HashSet::new(),
+            patterns: HashSet::new(),
+            watched_keys: Vec::new(),
         }
     }
+    pub fn id(&self) -> u64{
+        self.id
+    }
+    pub fn name(&self) -> Option<&str>{
+        self.name.as_deref()
+    }
+    pub fn set_name(&mut self, name: Option<String>){
+        self.name = name;
+    }
     pub fn in_transaction(&self) -> bool{
-        self.in_transaction
+        self.multi_queue.is_some()
     }
+    // Start a transaction with an empty queue
     pub fn initialiaze_multiqueue(&mut self){
-        self.in_transaction = true;
+        self.multi_error = false;
         self.multi_queue = Some(Vec::new());
     }
-    pub fn deactivate_multiqueue(&mut self){
-        self.in_transaction = false;
-        self.multi_queue = None;
+    // End the transaction, handing back the queued commands for EXEC to run; DISCARD just
+    // drops them
+    pub fn deactivate_multiqueue(&mut self) -> Vec<QueuedCommand>{
+        self.multi_error = false;
+        self.multi_queue.take().unwrap_or_default()
+    }
+    pub fn queue_command(&mut self, command: QueuedCommand){
+        if let Some(queue) = self.multi_queue.as_mut() {
+            queue.push(command);
+        }
+    }
+    pub fn flag_multi_error(&mut self){
+        self.multi_error = true;
+    }
+    pub fn has_multi_error(&self) -> bool{
+        self.multi_error
+    }
+    pub fn watch(&mut self, database: usize, key: &str, version: Option<u64>){
+        if !self.watched_keys.iter().any(|(watched_db, watched_key, _)| *watched_db == database && watched_key == key) {
+            self.watched_keys.push((database, key.to_string(), version));
+        }
+    }
+    pub fn unwatch(&mut self){
+        self.watched_keys.clear();
+    }
+    pub fn watched_keys(&self) -> &[(usize, String, Option<u64>)]{
+        &self.watched_keys
+    }
+    pub fn is_authenticated(&self) -> bool{
+        self.authenticated
+    }
+    pub fn set_authenticated(&mut self, authenticated: bool){
+        self.authenticated = authenticated;
+    }
+    pub fn user(&self) -> &str{
+        &self.user
     }
-    pub fn get_multi_queue_ref(&self) -> &Option<Vec<(String, Vec<String>)>>{
-        &self.multi_queue
+    pub fn set_user(&mut self, user: &str){
+        self.user = user.to_string();
     }
-    pub fn get_mut_multi_queue_ref(&mut self) -> &mut Option<Vec<(String, Vec<String>)>>{
-        &mut self.multi_queue
+    pub fn mark_replication_link(&mut self){
+        self.replication_link = true;
+    }
+    pub fn is_replication_link(&self) -> bool{
+        self.replication_link
+    }
+    pub fn selected_db(&self) -> usize{
+        self.selected_db
+    }
+    pub fn select(&mut self, index: usize){
+        self.selected_db = index;
+    }
+    pub fn protocol(&self) -> u8{
+        self.protocol
+    }
+    pub fn set_protocol(&mut self, protocol: u8){
+        self.protocol = protocol;
+    }
+    pub fn set_push_sender(&mut self, push_sender: UnboundedSender<PubSubMessage>){
+        self.push_sender = Some(push_sender);
+    }
+    pub fn push_sender(&self) -> Option<UnboundedSender<PubSubMessage>>{
+        self.push_sender.clone()
+    }
+    // The channels, or with `pattern` set the patterns, this connection is subscribed to
+    pub fn subscriptions(&self, pattern: bool) -> &HashSet<String>{
+        if pattern { &self.patterns } else { &self.channels }
+    }
+    pub fn subscriptions_mut(&mut self, pattern: bool) -> &mut HashSet<String>{
+        if pattern { &mut self.patterns } else { &mut self.channels }
+    }
+    // Number of subscriptions; while it is non-zero the connection is in subscribe mode
+    pub fn subscription_count(&self) -> usize{
+        self.channels.len() + self.patterns.len()
     }
 }
 
 async fn handle_client(
     stream: TcpStream,
-    db: Arc<Mutex<RedisDatabase>>,
-    config_map: &HashMap<String, String>,
+    db: Arc<RwLock<RedisDatabase>>,
+    config_map: &SharedConfig,
+    mut shutdown: watch::Receiver<bool>,
 ) -> std::io::Result<()> {
+    let peer_addr = stream.peer_addr()?;
     let (mut reader, writer) = stream.into_split(); // Split stream into reader and writer
     let writer = Arc::new(Mutex::new(writer)); // Wrap the TcpStream in an Arc<Mutex>
-    let mut buffer = vec![0; 4096];
-    let mut partial_message = String::new();
-    let mut has_sent_commands_to_slaves = false;
+    // Grows as needed to hold a whole frame, however large its bulk strings are
+    let mut partial_message = BytesMut::with_capacity(READ_CHUNK_SIZE);
     let mut client_state = ClientState::new();
+    let (push_sender, mut push_receiver) = mpsc::unbounded_channel();
+    client_state.set_push_sender(push_sender);
+
+    let mut serves_replica = false; // Set once a replica has synced over this connection
 
-    // Set the connection timeout duration (for example, 30 seconds)
-    let connection_timeout = Duration::from_secs(30);
+    loop {
+        // Idle clients are closed after `timeout` seconds, with 0 (the default) meaning never.
+        // Subscribers and replicas are expected to sit idle, so they never time out.
+        let timeout_seconds = config_map.read().await.get("timeout").and_then(|seconds| seconds.parse::<u64>().ok());
+        let idle_timeout = match timeout_seconds {
+            Some(seconds) if seconds > 0 && client_state.subscription_count() == 0 && !serves_replica => Duration::from_secs(seconds),
+            _ => Duration::MAX,
+        };
+        let protocol = client_state.protocol();
+        partial_message.reserve(READ_CHUNK_SIZE);
+        let Ok(bytes_read) = timeout(idle_timeout, async {
+            // Deliver published messages while waiting for the client's next command
+            loop {
+                tokio::select! {
+                    bytes_read = reader.read_buf(&mut partial_message) => break bytes_read,
+                    // On shutdown the connection is closed as if the client had hung up
+                    Ok(()) = shutdown.changed() => break Ok(0),
+                    Some(message) = push_receiver.recv() => {
+                        let mut stream_lock = writer.lock().await;
+                        if let Err(e) = stream_lock.write_all(&pubsub_message_frame(&message, protocol)).await {
+                            break Err(e);
+                        }
+                    }
+                }
+            }
+        })
+        .await else {
+            break;
+        };
 
-    while let Ok(bytes_read) = timeout(connection_timeout, async {
-        reader.read(&mut buffer).await
-    })
-    .await {
         match bytes_read {
             Ok(bytes_read) => {
                 if bytes_read == 0 {
-                    let db_lock = db.lock().await;
+                    let db_lock = db.read().await;
                     if let Some(ReplicationInfoValue::StringValue(value)) = db_lock.get_replication_info("role") {
                         println!("Closing connection for role: {}", value);
                     }
                     println!("Connection closed by client.");
                     return Ok(());
                 } else {
-                    // Append the newly read data to the partial message buffer
-                    partial_message.push_str(&String::from_utf8_lossy(&buffer[..bytes_read]));
-
                     // Process all complete Redis messages
                     while let Some(message_end) = get_end_of_redis_message(&partial_message) {
-                        let current_message = partial_message[..message_end].to_string();
-                        println!("Received Redis message in handle client: {}", current_message);
+                        let current_message = partial_message.split_to(message_end);
+                        println!("Received Redis message in handle client: {}", String::from_utf8_lossy(&current_message));
 
                         let parsed_results = {
                             parse_redis_message(&current_message, &db, config_map, &mut client_state).await
                         };
 
                         for (command, args, response, _) in parsed_results {
-                            let replconf_getack_message = "*3\r\n$8\r\nREPLCONF\r\n$6\r\nGETACK\r\n$1\r\n*\r\n";
-                            let replconf_getack_byte_len = replconf_getack_message.as_bytes().len();
-                            
-                            if command == Some("WAIT".to_string()) {
-                                println!("Got WAIT command");
-                                if args.len() != 2 {
-                                    let error_response = "-ERR wrong number of arguments for WAIT\r\n";
-                                    let mut stream_lock = writer.lock().await;
-                                    stream_lock.write_all(error_response.as_bytes()).await?;
-                                    stream_lock.flush().await?;
-                                } else {
-                                    let num_slaves = args[0].parse::<usize>().unwrap_or(0);
-                                    
-                                    let timeout_ms = args[1].parse::<u64>().unwrap_or(0);
-                                    if has_sent_commands_to_slaves == false{
-                                        println!("hasnt sent command to slaves, waiting on db lock");
-                                        let db_lock = db.lock().await;
-                                        println!("locked db to get num slaves");
-                                        
-                                        let slaves = {
-                                            db_lock.slave_connections.read().await.clone() // Clone slave connections list
-                                        };
-                                        let response = format!(":{}\r\n", slaves.len());
-
-                                        writer.lock().await.write_all(response.as_bytes()).await?;
-                                        writer.lock().await.flush().await?;
-
-                                    } 
-                                    else{
-                                        // Send REPLCONF GETACK * to all connected slaves
-                                        {   
-                                            println!("waiting to lock db to write getack to slave streams");
-                                            let mut db_lock = db.lock().await;
-                                            println!("locked db to write getack to slave streams");
-                                            
-                                            let slaves = {
-                                                db_lock.slave_connections.read().await.clone() // Clone slave connections list
-                                            };
-                                            
-                                            if slaves.len() > 0{
-                                                if let Some(ReplicationInfoValue::ByteValue(current_offset)) = db_lock.replication_info.get("master_repl_offset") {
-                                                    let new_offset = current_offset + replconf_getack_byte_len;
-                                                    db_lock.replication_info.insert(
-                                                        "master_repl_offset".to_string(),
-                                                        ReplicationInfoValue::ByteValue(new_offset),
-                                                    );
-                                                } else {
-                                                    db_lock.replication_info.insert(
-                                                        "master_repl_offset".to_string(),
-                                                        ReplicationInfoValue::ByteValue(replconf_getack_byte_len),
-                                                    );
-                                                }
-                                            }
-                                            for slave_stream in &slaves {
-                                                let mut slave_stream = slave_stream.lock().await;
-                                                slave_stream.write_all(replconf_getack_message.as_bytes()).await?;
-                                                slave_stream.flush().await?;
-                                            }
-                                            println!("releasing db lock after getack write");
-                                        }
-                                        // Wait for REPLCONF ACKs or timeout
-                                        let timeout_duration = tokio::time::Duration::from_millis(timeout_ms);
-                                        let result = tokio::time::timeout(timeout_duration, async {
-                                            loop {
-                                                //println!("locking ack counter");
-                                                let ack_counter_value = {
-                                                    // Only lock the ack_counter, not the whole db
-                                                    let db_lock = db.lock().await;
-                                                    let ack_counter = db_lock.ack_counter.lock().await;
-                                                    *ack_counter
-                                                };
-                                        
-                                                //println!("current ack counter: {}", ack_counter_value);
-                                                
-                                                if ack_counter_value >= num_slaves {
-                                                    break; // All required slaves have sent REPLCONF ACKs
-                                                }
-                                        
-                                                // Sleep briefly before checking again
-                                                tokio::time::sleep(Duration::from_millis(1)).await;
-                                            }
-                                            println!("finished waiting for ACKs");
-                                            Ok::<(), ()>(())
-                                        }).await;
-                                    
-                                        let response = {
-                                            let db_lock = db.lock().await;
-                                            let final_ack_count = *db_lock.ack_counter.lock().await;
-                                            println!("final ack count: {}", final_ack_count);
-                                            match result {
-                                                Ok(_) => format!(":{}\r\n", final_ack_count),
-                                                Err(_) => format!(":{}\r\n", final_ack_count), // Timeout
-                                            }
-                                        };
-
-                                        writer.lock().await.write_all(response.as_bytes()).await?;
-                                        writer.lock().await.flush().await?;
-                                        let db_lock = db.lock().await;
-                                        let mut ack_counter_lock = db_lock.ack_counter.lock().await;
-                                        *ack_counter_lock = 0;
-                                        println!("reset Ack counter to {}", *ack_counter_lock);
-                                    }
+                            // A replica acknowledging how much of the replication stream it has processed
+                            if command.as_deref() == Some("REPLCONF") && args.first().is_some_and(|arg| arg.eq_ignore_ascii_case("ACK")) {
+                                match args.get(1).and_then(|offset| offset.parse::<usize>().ok()) {
+                                    Some(offset) => db.write().await.record_replica_ack(peer_addr, offset),
+                                    None => eprintln!("Invalid REPLCONF ACK from {}: {:?}", peer_addr, args),
                                 }
-                            } // Detect and handle "REPLCONF ACK" message
-                            else if command == Some("REPLCONF".to_string())&& args[0].to_string() == "ACK" {
-                                // Increment the ack_counter inside the db
-                                println!("got replconf ack, waiting for db lock");
-                                let db_lock = db.lock().await;
-                                println!("got db lock, waiting for arc counter lock");
-                                let mut ack_counter_lock = db_lock.ack_counter.lock().await;
-                                *ack_counter_lock += 1;
-                                println!("Received REPLCONF ACK. Ack counter incremented to {}", *ack_counter_lock);
-                            } else if response.starts_with("+FULLRESYNC") {
+                            } else if response.starts_with(b"+FULLRESYNC") {
                                 // Send the FULLRESYNC response
                                 {
                                     let mut stream_lock = writer.lock().await;
-                                    stream_lock.write_all(response.as_bytes()).await?;
+                                    stream_lock.write_all(&response).await?;
                                     stream_lock.flush().await?;
                                 }
                                 // Send the RDB file to the client (slave)
                                 {
                                     let mut stream_lock = writer.lock().await;
-                                    send_rdb_file(&mut *stream_lock).await?;
+                                    send_rdb_file(&mut stream_lock).await?;
                                     println!("Sent RDB file after FULLRESYNC");
                                 }
 
                                 // Add the slave to the slave connections list
-                                {
-                                    let db_lock = db.lock().await;
-                                    db_lock.slave_connections.write().await.push(Arc::clone(&writer));
-                                }
+                                db.write().await.add_replica(peer_addr, Arc::clone(&writer)).await;
+                                serves_replica = true;
                                 println!("Added new slave after FULLRESYNC");
 
                             } else {
                                 // Write the response to the client
-                                println!("Sending response: {}", response);
+                                println!("Sending response: {}", String::from_utf8_lossy(&response));
                                 {
                                     let mut stream_lock = writer.lock().await;
-                                    stream_lock.write_all(response.as_bytes()).await?;
+                                    stream_lock.write_all(&response).await?;
                                     stream_lock.flush().await?;
                                 }
-
-                                // Forward the command to all connected slaves if applicable
-                                if let Some(cmd) = command {
-                                    if should_forward_to_slaves(&cmd) {
-                                        println!("Forwarding to slaves: {}", cmd);
-                                        let mut db_lock = db.lock().await;
-                                        println!("got db lock to forward to slaves");
-                                        // Forward the message to all connected slaves
-                                        let slaves = {
-                                            
-                                            db_lock.slave_connections.read().await.clone()
-                                        };
-
-                                        for slave_stream in slaves {
-                                            let mut slave_stream = slave_stream.lock().await;
-                                            println!("got slave stream lock");
-                                            if let Err(e) = slave_stream.write_all(current_message.as_bytes()).await {
-                                                eprintln!("Error sending message to slave: {:?}", e);
-                                            }
-                                            has_sent_commands_to_slaves = true;
-                                        }
-
-                                        // Increment the master_repl_offset only once for the total bytes sent
-                                        let bytes_sent = current_message.as_bytes().len();
-                                        if let Some(ReplicationInfoValue::ByteValue(current_offset)) = db_lock.replication_info.get("master_repl_offset") {
-                                            let new_offset = current_offset + bytes_sent;
-                                            db_lock.replication_info.insert(
-                                                "master_repl_offset".to_string(),
-                                                ReplicationInfoValue::ByteValue(new_offset),
-                                            );
-                                        } else {
-                                            db_lock.replication_info.insert(
-                                                "master_repl_offset".to_string(),
-                                                ReplicationInfoValue::ByteValue(bytes_sent),
-                                            );
-                                        }
-                                    }
-                                }
                             }
                         }
-
-                        // Remove the processed message from the partial buffer
-                        partial_message.drain(..message_end);
                     }
                 }
             }
             Err(e) => {
                 // Handle errors from the read operation, if any
                 println!("Error reading from stream: {:?}", e);
-                return Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "Read operation timed out"));
+                return Err(e);
             }
         }
     }
 
-    println!("Connection timeout reached. Closing connection");
+    println!("Client idle for longer than the timeout. Closing connection");
     Ok(())
 }
 
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queued(command: &str) -> QueuedCommand {
+        (command.to_string(), vec![command.as_bytes().to_vec()])
+    }
+
+    #[test]
+    fn test_deactivate_multiqueue_hands_back_the_queue() {
+        let mut client_state = ClientState::new();
+        client_state.initialiaze_multiqueue();
+        client_state.queue_command(queued("SET"));
+        client_state.queue_command(queued("GET"));
+        assert!(client_state.in_transaction());
+
+        let queue = client_state.deactivate_multiqueue();
+        assert_eq!(queue, vec![queued("SET"), queued("GET")]);
+        assert!(!client_state.in_transaction());
+        // Outside a transaction nothing is queued, and there is nothing to hand back
+        client_state.queue_command(queued("INCR"));
+        assert!(client_state.deactivate_multiqueue().is_empty());
+    }
+
+    #[test]
+    fn test_discarded_commands_stay_out_of_the_next_transaction() {
+        let mut client_state = ClientState::new();
+        client_state.initialiaze_multiqueue();
+        client_state.queue_command(queued("SET"));
+        client_state.flag_multi_error();
+        client_state.deactivate_multiqueue();
+
+        client_state.initialiaze_multiqueue();
+        assert!(!client_state.has_multi_error());
+        client_state.queue_command(queued("GET"));
+        assert_eq!(client_state.deactivate_multiqueue(), vec![queued("GET")]);
+    }
+}
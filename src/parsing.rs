@@ -1,32 +1,36 @@
-use tokio::sync::Mutex;
-use crate::database::{RedisDatabase, ReplicationInfoValue};
-use crate::commands::{handle_config, handle_echo, handle_get, handle_incr, handle_info, handle_keys, handle_ping, handle_psync, handle_replconf, handle_set, handle_type, handle_xadd, handle_xrange, handle_xread};
-use crate::network::ClientState;
-use std::collections::HashMap;
+use tokio::sync::{OwnedMutexGuard, RwLock};
+use crate::database::{RedisDatabase, SELECTED_DB};
+use crate::commands::{DENY_BLOCKING, RESP_VERSION, handle_acl, handle_append, handle_auth, handle_bgsave, handle_client_cmd, handle_command, handle_config, handle_copy, handle_dbsize, handle_debug, handle_decr, handle_decrby, handle_del, handle_echo, handle_exists, handle_expire, handle_flushall, handle_flushdb, handle_get, handle_getdel, handle_getrange, handle_hdel, handle_hello, handle_hexists, handle_hget, handle_hgetall, handle_hincrby, handle_hkeys, handle_hlen, handle_hset, handle_hvals, handle_incr, handle_incrby, handle_incrbyfloat, handle_info, handle_keys, handle_lindex, handle_llen, handle_lpop, handle_lpush, handle_lpushx, handle_lrange, handle_lset, handle_mget, handle_move, handle_mset, handle_object, handle_persist, handle_pexpire, handle_ping, handle_psetex, handle_psubscribe, handle_psync, handle_pttl, handle_publish, handle_punsubscribe, handle_randomkey, handle_rename, handle_renamenx, handle_replconf, handle_reset, handle_rpop, handle_rpush, handle_rpushx, handle_sadd, handle_save, handle_scan, handle_scard, handle_sdiff, handle_select, handle_set, handle_setex, handle_setnx, handle_setrange, handle_shutdown, handle_sinter, handle_sismember, handle_smembers, handle_srem, handle_strlen, handle_subscribe, handle_sunion, handle_swapdb, handle_ttl, handle_type, handle_unsubscribe, handle_unwatch, handle_wait, handle_watch, handle_xadd, handle_xdel, handle_xinfo, handle_xlen, handle_xrange, handle_xread, handle_xrevrange, handle_xtrim, handle_zadd, handle_zrange, handle_zrangebyscore, handle_zscore, password_required, subscribe_mode_reply};
+use crate::network::{ClientState, QueuedCommand, SharedConfig};
+use crate::command_table::{command_keys, command_spec, has_flag};
+use crate::utils::{encode_command, is_denyoom_command, is_write_command};
 use std::sync::Arc;
 
+// Run every command in `message`, returning for each its name, arguments, reply and length in
+// bytes. The name is None when the command was refused without running (NOAUTH, OOM, a bad
+// argument count or a command not allowed while subscribed), so it must not be forwarded.
 pub async fn parse_redis_message(
-    message: &str,
-    db: &Arc<Mutex<RedisDatabase>>,
-    config_map: &HashMap<String, String>,
+    message: &[u8],
+    db: &Arc<RwLock<RedisDatabase>>,
+    config_map: &SharedConfig,
     client_state: &mut ClientState,
-) -> Vec<(Option<String>, Vec<String>, String, usize)> {
+) -> Vec<(Option<String>, Vec<String>, Vec<u8>, usize)> {
     let mut results = Vec::new();
     let mut cursor = 0;
-    let bytes = message.as_bytes();
+    let bytes = message;
 
     while cursor < bytes.len() {
         let initial_cursor = cursor; // Track where this message started
 
-        // Check for argument count prefix *
-        if bytes[cursor] == b'*' {
+        // Check for argument count prefix *, otherwise it is an inline command
+        let (command, raw_args) = if bytes[cursor] == b'*' {
             cursor += 1; // Move past '*'
 
             // Parse the number of arguments
             let end = match find_crlf(&bytes[cursor..]) {
                 Some(e) => e,
                 None => {
-                    results.push((None, vec![], "-ERR incomplete message\r\n".to_string(), 0));
+                    results.push((None, vec![], "-ERR incomplete message\r\n".into(), 0));
                     break;
                 }
             };
@@ -37,14 +41,14 @@ pub async fn parse_redis_message(
             {
                 Some(count) => count,
                 None => {
-                    results.push((None, vec![], "-ERR invalid argument count\r\n".to_string(), 0));
+                    results.push((None, vec![], "-ERR invalid argument count\r\n".into(), 0));
                     break;
                 }
             };
             cursor += end + 2; // Move past \r\n
 
             let mut command = None;
-            let mut args = Vec::new();
+            let mut raw_args = Vec::new();
 
             // Parse each bulk string
             for _ in 0..arg_count {
@@ -56,7 +60,7 @@ pub async fn parse_redis_message(
                     let end = match find_crlf(&bytes[cursor..]) {
                         Some(e) => e,
                         None => {
-                            results.push((None, vec![], "-ERR incomplete message\r\n".to_string(), 0));
+                            results.push((None, vec![], "-ERR incomplete message\r\n".into(), 0));
                             break;
                         }
                     };
@@ -67,182 +71,386 @@ pub async fn parse_redis_message(
                     {
                         Some(len) => len,
                         None => {
-                            results.push((None, vec![], "-ERR invalid bulk string length\r\n".to_string(), 0));
+                            results.push((None, vec![], "-ERR invalid bulk string length\r\n".into(), 0));
                             break;
                         }
                     };
                     cursor += end + 2; // Move past \r\n
 
-                    // Extract bulk string
+                    // Extract bulk string as raw bytes so binary values are preserved
                     if cursor + bulk_len + 2 <= bytes.len() {
-                        let bulk_string = &message[cursor..cursor + bulk_len];
+                        let bulk_string = &bytes[cursor..cursor + bulk_len];
                         cursor += bulk_len + 2; // Move past the string and \r\n
 
                         if command.is_none() {
-                            command = Some(bulk_string.to_uppercase());
+                            command = Some(String::from_utf8_lossy(bulk_string).to_uppercase());
                         } else {
-                            args.push(bulk_string.to_string());
+                            raw_args.push(bulk_string.to_vec());
                         }
                     } else {
-                        results.push((None, vec![], "-ERR incomplete bulk string\r\n".to_string(), 0));
+                        results.push((None, vec![], "-ERR incomplete bulk string\r\n".into(), 0));
                         break;
                     }
                 } else {
-                    results.push((None, vec![], "-ERR expected bulk string\r\n".to_string(), 0));
+                    results.push((None, vec![], "-ERR expected bulk string\r\n".into(), 0));
                     break;
                 }
             }
+            (command, raw_args)
+        } else {
+            // An inline command, as typed into telnet: one line of space-separated words
+            let line_end = bytes[cursor..].iter().position(|&byte| byte == b'\n').map_or(bytes.len(), |newline| cursor + newline);
+            let line = bytes[cursor..line_end].strip_suffix(b"\r").unwrap_or(&bytes[cursor..line_end]);
+            let mut words = line.split(|byte| byte.is_ascii_whitespace()).filter(|word| !word.is_empty());
+            cursor = (line_end + 1).min(bytes.len()); // Move past \n
+            let Some(command) = words.next() else {
+                continue; // Blank lines are ignored
+            };
+            (Some(String::from_utf8_lossy(command).to_uppercase()), words.map(<[u8]>::to_vec).collect())
+        };
 
-            let command = command.as_deref();
-            match command {
-                Some("MULTI") => {
-                    if client_state.in_transaction() {
-                        results.push((Some("MULTI".to_string()), vec![], "-ERR MULTI already started\r\n".to_string(), 0));
-                    } else {
-                        client_state.initialiaze_multiqueue();
-                        results.push((Some("MULTI".to_string()), vec![], "+OK\r\n".to_string(),  0));
-                    }
+        // Keys and options are handled as text; value-carrying commands use raw_args
+        let args: Vec<String> = raw_args.iter().map(|arg| String::from_utf8_lossy(arg).into_owned()).collect();
+        let command = command.as_deref();
+        // Calculate the byte length of the entire command
+        let byte_length = cursor - initial_cursor;
+
+        // With requirepass set, only commands flagged no-auth run before the connection
+        // authenticates; what the master sends a replica is always trusted
+        if !client_state.is_authenticated()
+            && !client_state.is_replication_link()
+            && !command.is_some_and(|command| has_flag(command, "no-auth"))
+            && password_required(config_map).await
+        {
+            if client_state.in_transaction() {
+                client_state.flag_multi_error();
+            }
+            // Refused replies carry no command, so nothing downstream treats them as having run
+            results.push((None, args, "-NOAUTH Authentication required\r\n".into(), byte_length));
+            continue;
+        }
+
+        // What replicas are sent for the command, each write with the database it acted on
+        let mut writes = Vec::new();
+        let mut transaction = false;
+        let mut write_turn = None;
+        match command {
+            Some("MULTI") => {
+                if client_state.in_transaction() {
+                    results.push((Some("MULTI".to_string()), vec![], "-ERR MULTI already started\r\n".into(), byte_length));
+                } else {
+                    client_state.initialiaze_multiqueue();
+                    results.push((Some("MULTI".to_string()), vec![], "+OK\r\n".into(), byte_length));
                 }
-                Some("EXEC") => {
-                    if client_state.in_transaction() {
-                        let response = execute_queued_commands(client_state.get_multi_queue_ref(), db, config_map).await;
-                        client_state.deactivate_multiqueue();
-                         // RESP array of responses from queued commands
-                        results.push((
-                            Some("EXEC".to_string()),
-                            vec![],
-                            response,
-                            0,
-                        ));
+            }
+            Some("EXEC") => {
+                if client_state.in_transaction() {
+                    let queueing_failed = client_state.has_multi_error();
+                    let queue = client_state.deactivate_multiqueue();
+                    // No other write can run from the WATCH check until the transaction's writes
+                    // have been forwarded
+                    write_turn = Some(take_write_turn(db).await);
+                    // The transaction is aborted with a nil reply if a WATCHed key was written
+                    let watched_key_changed = {
+                        let db = db.read().await;
+                        client_state.watched_keys().iter().any(|(database, key, version)| db.key_version(*database, key) != *version)
+                    };
+                    client_state.unwatch();
+                    let response = if queueing_failed {
+                        "-EXECABORT Transaction discarded because of previous errors.\r\n".into()
+                    } else if watched_key_changed {
+                        "*-1\r\n".into()
                     } else {
-                        results.push((Some("EXEC".to_string()), vec![], "-ERR EXEC without MULTI\r\n".to_string(), 0));
-                    }
+                        let (response, queued_writes) =
+                            DENY_BLOCKING.scope(true, execute_queued_commands(&queue, db, config_map, client_state)).await;
+                        (writes, transaction) = (queued_writes, true);
+                        response
+                    };
+                     // RESP array of responses from queued commands
+                    results.push((
+                        Some("EXEC".to_string()),
+                        vec![],
+                        response,
+                        byte_length,
+                    ));
+                } else {
+                    results.push((Some("EXEC".to_string()), vec![], "-ERR EXEC without MULTI\r\n".into(), byte_length));
                 }
-                Some("DISCARD") => {
-                    if client_state.in_transaction() {
-                        client_state.deactivate_multiqueue();
-                        results.push((Some("DISCARD".to_string()), vec![], "+OK\r\n".to_string(), 0));
-                    } else {
-                        results.push((Some("DISCARD".to_string()), vec![], "-ERR DISCARD without MULTI\r\n".to_string(), 0));
-                    }
+            }
+            Some("DISCARD") => {
+                if client_state.in_transaction() {
+                    client_state.deactivate_multiqueue();
+                    client_state.unwatch();
+                    results.push((Some("DISCARD".to_string()), vec![], "+OK\r\n".into(), byte_length));
+                } else {
+                    results.push((Some("DISCARD".to_string()), vec![], "-ERR DISCARD without MULTI\r\n".into(), byte_length));
                 }
-                _ => {
+            }
+            Some("WATCH") if client_state.in_transaction() => {
+                results.push((Some("WATCH".to_string()), args.clone(), "-ERR WATCH inside MULTI is not allowed\r\n".into(), byte_length));
+            }
+            // RESET runs immediately even inside MULTI, discarding the transaction
+            Some("RESET") => {
+                let response = handle_reset(db, &args, client_state).await;
+                results.push((Some("RESET".to_string()), args.clone(), response.into(), byte_length));
+            }
+            _ => {
+                if let Some(error) = command_error(command, &args) {
+                    // Inside a transaction the error also makes EXEC discard it
                     if client_state.in_transaction() {
-                        // Add the command and its arguments to the multi_queue instead of executing
-                        if let Some(queue) = client_state.get_mut_multi_queue_ref(){
-                            queue.push((command.unwrap_or_default().to_string(), args.clone()));
-                            results.push((command.map(|cmd| cmd.to_string()), args.clone(), "+QUEUED\r\n".to_string(), 0));
-                        }
+                        client_state.flag_multi_error();
+                    }
+                    results.push((None, args, error.into(), byte_length));
+                } else if client_state.in_transaction() {
+                    // Add the command and its arguments to the multi_queue instead of executing
+                    client_state.queue_command((command.unwrap_or_default().to_string(), raw_args));
+                    results.push((command.map(|cmd| cmd.to_string()), args.clone(), "+QUEUED\r\n".into(), byte_length));
+                } else {
+                    // Execute the command normally if not in transaction mode
+                    // Handle the command once all args are collected
+                    let (selected_db, protocol) = (client_state.selected_db(), client_state.protocol());
+                    if command.is_some_and(is_write_command) {
+                        write_turn = Some(take_write_turn(db).await);
+                    }
+                    let subscribed_reply = match command {
+                        Some(command) if protocol == 2 && client_state.subscription_count() > 0 => subscribe_mode_reply(command, &args),
+                        _ => None,
+                    };
+                    // Make room under maxmemory before a write that may need it; replicas leave
+                    // eviction to their master
+                    let out_of_memory = command.is_some_and(is_denyoom_command)
+                        && !client_state.is_replication_link()
+                        && !db.write().await.evict_if_needed();
+                    if let Some(reply) = subscribed_reply {
+                        results.push((None, args, reply.into(), byte_length));
+                    } else if out_of_memory {
+                        results.push((None, args, "-OOM command not allowed when used memory > 'maxmemory'.\r\n".into(), byte_length));
                     } else {
-                        // Execute the command normally if not in transaction mode
-                        // Handle the command once all args are collected
-                        let response = match command {
-                            Some("SET") => handle_set(db, &args).await,
-                            Some("GET") => handle_get(db, &args).await,
-                            Some("CONFIG") => handle_config(config_map, &args),
-                            Some("KEYS") => handle_keys(db).await,
-                            Some("ECHO") => handle_echo(&args),
-                            Some("PING") => handle_ping(&args),
-                            Some("INFO") => handle_info(db, &args).await,
-                            Some("REPLCONF") => handle_replconf(db,&args).await,
-                            Some("PSYNC") => handle_psync(db, &args).await,
-                            Some("WAIT") => "".to_string(),
-                            Some("TYPE") => handle_type(db, &args).await,
-                            Some("XADD") => handle_xadd(db, &args).await,
-                            Some("XRANGE") => handle_xrange(db, &args).await,
-                            Some("XREAD") => handle_xread(db, &args).await,
-                            Some("INCR") => handle_incr(db, &args).await,
-                            _ => "-ERR unknown command\r\n".to_string(),
-                        };
-
-                        // Calculate the byte length of the entire command
-                        let byte_length = cursor - initial_cursor;
-                        // Incrementally update the bytes_processed
-                        let mut db_lock = db.lock().await;
-                        if let Some(ReplicationInfoValue::StringValue(role)) = db_lock.get_replication_info("role") {
-                            if role == "slave" {
-                                println!("updating slave repl offset");
-                                let cmd_bytes = match db_lock.get_replication_info("slave_repl_offset") {
-                                    Some(ReplicationInfoValue::ByteValue(current_bytes)) => {
-                                        current_bytes + byte_length
-                                    }
-                                    _ => byte_length,  // Initialize if not present
-                                };
-                        
-                                // Update the replication info in the database
-                                db_lock.update_replication_info(
-                                    "slave_repl_offset".to_string(),
-                                    ReplicationInfoValue::ByteValue(cmd_bytes),
-                                );
-                            }
+                        let response = run_command(command.unwrap_or_default(), &args, &raw_args, db, config_map, client_state).await;
+                        mark_keys_modified(db, command, &args, selected_db, &response).await;
+                        if command.is_some_and(is_write_command) && !response.starts_with(b"-") {
+                            writes.push((selected_db, bytes[initial_cursor..cursor].to_vec()));
                         }
-                        
-                        //println!("Updated bytes_processed to: {}", cmd_bytes);
 
                         // Push the result (command, args, response, cursor, byte_length)
                         results.push((command.map(|cmd| cmd.to_string()), args, response, byte_length));
                     }
                 }
             }
-            
-        } else {
-            results.push((None, vec![], "-ERR invalid format\r\n".to_string(), 0));
-            break;
+        }
+
+        // A replica's own replicas aren't fed from here
+        if !client_state.is_replication_link() {
+            propagate_writes(db, &writes, transaction).await;
+        }
+        drop(write_turn);
+
+        // Everything the master sends counts towards the offset, transaction commands included,
+        // after the command ran so a GETACK reports the offset from before itself
+        if client_state.is_replication_link() {
+            db.write().await.add_slave_repl_offset(byte_length);
         }
     }
 
     results
 }
 
+// Run a transaction's queued commands, returning the EXEC reply and the writes that succeeded
+// for the replicas
 async fn execute_queued_commands(
-    queue: &Option<Vec<(String, Vec<String>)>>,
-    db: &Arc<Mutex<RedisDatabase>>,
-    config_map: &HashMap<String, String>,
-) -> String {
+    queue: &[QueuedCommand],
+    db: &Arc<RwLock<RedisDatabase>>,
+    config_map: &SharedConfig,
+    client_state: &mut ClientState,
+) -> (Vec<u8>, Vec<(usize, Vec<u8>)>) {
     let mut responses = Vec::new();
+    let mut writes = Vec::new();
 
-    if let Some(commands) = queue {
-        for (command, args) in commands {
-            let response = match command.as_str() {
-                "SET" => handle_set(db, &args).await,
-                "GET" => handle_get(db, &args).await,
-                "INCR" => handle_incr(db, &args).await,
-                "CONFIG" => handle_config(config_map, &args),
-                "KEYS" => handle_keys(db).await,
-                "ECHO" => handle_echo(&args),
-                "PING" => handle_ping(&args),
-                "INFO" => handle_info(db, &args).await,
-                "REPLCONF" => handle_replconf(db,&args).await,
-                "PSYNC" => handle_psync(db, &args).await,
-                "WAIT" => "".to_string(),
-                "TYPE" => handle_type(db, &args).await,
-                "XADD" => handle_xadd(db, &args).await,
-                "XRANGE" => handle_xrange(db, &args).await,
-                "XREAD"=> handle_xread(db, &args).await,
-                // Add other supported commands here
-                _ => "-ERR unknown command\r\n".to_string(),
-            };
-
-            responses.push(response);
+    for (command, raw_args) in queue {
+        let args: Vec<String> = raw_args.iter().map(|arg| String::from_utf8_lossy(arg).into_owned()).collect();
+        let selected_db = client_state.selected_db();
+        let response = run_command(command, &args, raw_args, db, config_map, client_state).await;
+        mark_keys_modified(db, Some(command), &args, selected_db, &response).await;
+        if is_write_command(command) && !response.starts_with(b"-") {
+            writes.push((selected_db, encode_command(command, raw_args)));
         }
+
+        responses.push(response);
     }
      // Format the responses as a RESP array
-     let mut resp_array = format!("*{}\r\n", responses.len());
+     let mut resp_array = format!("*{}\r\n", responses.len()).into_bytes();
 
      for response in responses {
-            // Simple String (+OK) or Error (-ERR)
-        resp_array.push_str(&response);
+        // Each reply is already RESP-encoded, errors and integers included, so it goes in as is
+        resp_array.extend_from_slice(&response);
      }
-    resp_array
+    (resp_array, writes)
 }
 
+// Send replicas DELs for the keys expired or evicted so far, then a command's writes; those of a
+// transaction go as one MULTI/EXEC block. With neither there's no need for the write lock.
+async fn propagate_writes(db: &Arc<RwLock<RedisDatabase>>, writes: &[(usize, Vec<u8>)], transaction: bool) {
+    if writes.is_empty() && !db.read().await.has_dels_to_propagate() {
+        return;
+    }
+    let mut db = db.write().await;
+    db.propagate_dels().await;
+    match writes {
+        [] => {}
+        [(database, write)] if !transaction => db.propagate_command(*database, write).await,
+        writes => db.propagate_transaction(writes).await,
+    }
+}
 
-// Helper function to find CRLF (\r\n)
-fn find_crlf(bytes: &[u8]) -> Option<usize> {
-    for i in 0..bytes.len() - 1 {
-        if bytes[i] == b'\r' && bytes[i + 1] == b'\n' {
-            return Some(i);
+// Run a command against the database this connection has selected, replying in the protocol
+// version it negotiated. Both commands sent on their own and those queued for EXEC come here.
+async fn run_command(
+    command: &str,
+    args: &[String],
+    raw_args: &[Vec<u8>],
+    db: &Arc<RwLock<RedisDatabase>>,
+    config_map: &SharedConfig,
+    client_state: &mut ClientState,
+) -> Vec<u8> {
+    let (selected_db, protocol) = (client_state.selected_db(), client_state.protocol());
+    SELECTED_DB.scope(selected_db, RESP_VERSION.scope(protocol, async {
+        match command {
+            "SET" => handle_set(db, raw_args).await,
+            "SETNX" => handle_setnx(db, raw_args).await.into(),
+            "SETEX" => handle_setex(db, raw_args).await.into(),
+            "PSETEX" => handle_psetex(db, raw_args).await.into(),
+            "GET" => handle_get(db, args).await,
+            "GETDEL" => handle_getdel(db, args).await,
+            "MSET" => handle_mset(db, raw_args).await.into(),
+            "MGET" => handle_mget(db, args).await,
+            "APPEND" => handle_append(db, raw_args).await.into(),
+            "STRLEN" => handle_strlen(db, args).await.into(),
+            "GETRANGE" => handle_getrange(db, args).await,
+            "SETRANGE" => handle_setrange(db, raw_args).await.into(),
+            "DEL" => handle_del(db, args).await.into(),
+            "EXISTS" => handle_exists(db, args).await.into(),
+            "EXPIRE" => handle_expire(db, args).await.into(),
+            "PEXPIRE" => handle_pexpire(db, args).await.into(),
+            "PERSIST" => handle_persist(db, args).await.into(),
+            "TTL" => handle_ttl(db, args).await.into(),
+            "PTTL" => handle_pttl(db, args).await.into(),
+            "CONFIG" => handle_config(db, config_map, args).await.into(),
+            "SAVE" => handle_save(db, config_map).await.into(),
+            "BGSAVE" => handle_bgsave(db, config_map).await.into(),
+            "SHUTDOWN" => handle_shutdown(db, config_map, args).await.into(),
+            "FLUSHDB" => handle_flushdb(db, args).await.into(),
+            "DBSIZE" => handle_dbsize(db).await.into(),
+            "DEBUG" => handle_debug(db, args).await.into(),
+            "SCAN" => handle_scan(db, args).await.into(),
+            "RANDOMKEY" => handle_randomkey(db).await.into(),
+            "RENAME" => handle_rename(db, args).await.into(),
+            "RENAMENX" => handle_renamenx(db, args).await.into(),
+            "COPY" => handle_copy(db, args).await.into(),
+            "OBJECT" => handle_object(db, args).await.into(),
+            "FLUSHALL" => handle_flushall(db, args).await.into(),
+            "KEYS" => handle_keys(db, args).await.into(),
+            "SELECT" => handle_select(args, client_state).into(),
+            "ACL" => handle_acl(config_map, args, client_state).await.into(),
+            "AUTH" => handle_auth(config_map, args, client_state).await.into(),
+            "HELLO" => handle_hello(db, config_map, args, client_state).await.into(),
+            "SUBSCRIBE" => handle_subscribe(db, args, client_state).await.into(),
+            "UNSUBSCRIBE" => handle_unsubscribe(db, args, client_state).await.into(),
+            "PSUBSCRIBE" => handle_psubscribe(db, args, client_state).await.into(),
+            "PUNSUBSCRIBE" => handle_punsubscribe(db, args, client_state).await.into(),
+            "PUBLISH" => handle_publish(db, args).await.into(),
+            "SWAPDB" => handle_swapdb(db, args).await.into(),
+            "MOVE" => handle_move(db, args).await.into(),
+            "ECHO" => handle_echo(args).into(),
+            "PING" => handle_ping(args).into(),
+            "INFO" => handle_info(db, args).await.into(),
+            "REPLCONF" => handle_replconf(db, args).await.into(),
+            "PSYNC" => handle_psync(db, args).await.into(),
+            "WAIT" => handle_wait(db, args).await.into(),
+            "TYPE" => handle_type(db, args).await.into(),
+            "COMMAND" => handle_command(args).into(),
+            "CLIENT" => handle_client_cmd(args, client_state).into(),
+            "WATCH" => handle_watch(db, args, client_state).await.into(),
+            "UNWATCH" => handle_unwatch(args, client_state).into(),
+            "LPUSH" => handle_lpush(db, args).await.into(),
+            "RPUSH" => handle_rpush(db, args).await.into(),
+            "LPUSHX" => handle_lpushx(db, args).await.into(),
+            "RPUSHX" => handle_rpushx(db, args).await.into(),
+            "LRANGE" => handle_lrange(db, args).await.into(),
+            "LLEN" => handle_llen(db, args).await.into(),
+            "LPOP" => handle_lpop(db, args).await.into(),
+            "RPOP" => handle_rpop(db, args).await.into(),
+            "LINDEX" => handle_lindex(db, args).await.into(),
+            "LSET" => handle_lset(db, args).await.into(),
+            "HSET" => handle_hset(db, args).await.into(),
+            "HGET" => handle_hget(db, args).await.into(),
+            "HGETALL" => handle_hgetall(db, args).await.into(),
+            "HDEL" => handle_hdel(db, args).await.into(),
+            "HEXISTS" => handle_hexists(db, args).await.into(),
+            "HKEYS" => handle_hkeys(db, args).await.into(),
+            "HVALS" => handle_hvals(db, args).await.into(),
+            "HLEN" => handle_hlen(db, args).await.into(),
+            "HINCRBY" => handle_hincrby(db, args).await.into(),
+            "SADD" => handle_sadd(db, args).await.into(),
+            "SREM" => handle_srem(db, args).await.into(),
+            "SMEMBERS" => handle_smembers(db, args).await.into(),
+            "SISMEMBER" => handle_sismember(db, args).await.into(),
+            "SCARD" => handle_scard(db, args).await.into(),
+            "SINTER" => handle_sinter(db, args).await.into(),
+            "SUNION" => handle_sunion(db, args).await.into(),
+            "SDIFF" => handle_sdiff(db, args).await.into(),
+            "ZADD" => handle_zadd(db, args).await.into(),
+            "ZSCORE" => handle_zscore(db, args).await.into(),
+            "ZRANGE" => handle_zrange(db, args).await.into(),
+            "ZRANGEBYSCORE" => handle_zrangebyscore(db, args).await.into(),
+            "XADD" => handle_xadd(db, args).await.into(),
+            "XLEN" => handle_xlen(db, args).await.into(),
+            "XDEL" => handle_xdel(db, args).await.into(),
+            "XTRIM" => handle_xtrim(db, args).await.into(),
+            "XINFO" => handle_xinfo(db, args).await.into(),
+            "XRANGE" => handle_xrange(db, args).await.into(),
+            "XREVRANGE" => handle_xrevrange(db, args).await.into(),
+            "XREAD" => handle_xread(db, args).await.into(),
+            "INCR" => handle_incr(db, args).await.into(),
+            "INCRBY" => handle_incrby(db, args).await.into(),
+            "DECR" => handle_decr(db, args).await.into(),
+            "DECRBY" => handle_decrby(db, args).await.into(),
+            "INCRBYFLOAT" => handle_incrbyfloat(db, args).await,
+            _ => "-ERR unknown command\r\n".into(),
         }
+    })).await
+}
+
+// Why a command can't run, checked against the command table before it is dispatched: an
+// unknown name or the wrong number of arguments. After MULTI the error is given when queueing
+// and makes EXEC discard the whole transaction, as in Redis.
+fn command_error(command: Option<&str>, args: &[String]) -> Option<String> {
+    let Some(spec) = command.and_then(command_spec) else {
+        return Some("-ERR unknown command\r\n".to_string());
+    };
+    // Positive arities are exact, negative ones a minimum; both count the command name
+    let arg_count = args.len() as i32 + 1;
+    if (spec.arity > 0 && arg_count != spec.arity) || arg_count < -spec.arity {
+        return Some(format!("-ERR wrong number of arguments for '{}' command\r\n", spec.name));
     }
     None
 }
+
+// Give the keys a write command names new versions, which aborts the EXEC of clients WATCHing them.
+// A write that failed with an error reply changed nothing, so leaves them alone.
+async fn mark_keys_modified(db: &Arc<RwLock<RedisDatabase>>, command: Option<&str>, args: &[String], database: usize, response: &[u8]) {
+    if let Some(command) = command.filter(|command| has_flag(command, "write") && !response.starts_with(b"-")) {
+        db.write().await.mark_modified(database, &command_keys(command, args));
+    }
+}
+
+// Wait until no other write is running; see RedisDatabase::write_turn. The database lock is let
+// go first, as whoever has the turn may need it.
+async fn take_write_turn(db: &Arc<RwLock<RedisDatabase>>) -> OwnedMutexGuard<()> {
+    let turn = db.read().await.write_turn();
+    turn.lock_owned().await
+}
+
+// Helper function to find CRLF (\r\n)
+fn find_crlf(bytes: &[u8]) -> Option<usize> {
+    bytes.windows(2).position(|w| w == b"\r\n")
+}
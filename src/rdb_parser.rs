@@ -2,7 +2,9 @@
 use std::fs;
 use std::io::{self, Read};
 use std::time::{Duration, SystemTime};
-use crate::database::{RedisDatabase, RedisValue};
+use std::collections::HashMap;
+use crate::database::{RedisDatabase, RedisValue, RedisValueType, SortedSet, Stream, StreamID, DATABASE_COUNT};
+use crate::utils::crc64;
 
 fn read_u8(buffer: &[u8], cursor: &mut usize) -> io::Result<u8> {
     if *cursor < buffer.len() {
@@ -27,6 +29,21 @@ fn read_uint_le(buffer: &[u8], cursor: &mut usize, n: usize) -> io::Result<u64>
     }
 }
 
+fn read_uint_be(buffer: &[u8], cursor: &mut usize, n: usize) -> io::Result<u64> {
+    let bytes = read_raw(buffer, cursor, n)?;
+    Ok(bytes.iter().fold(0u64, |value, byte| (value << 8) | u64::from(*byte)))
+}
+
+fn read_raw<'a>(buffer: &'a [u8], cursor: &mut usize, n: usize) -> io::Result<&'a [u8]> {
+    match buffer.get(*cursor..*cursor + n) {
+        Some(bytes) => {
+            *cursor += n;
+            Ok(bytes)
+        }
+        None => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Reached end of buffer")),
+    }
+}
+
 fn decode_size(buffer: &[u8], cursor: &mut usize) -> io::Result<u64> {
     let first_byte = read_u8(buffer, cursor)?;
     let size = match first_byte >> 6 {
@@ -35,51 +52,385 @@ fn decode_size(buffer: &[u8], cursor: &mut usize) -> io::Result<u64> {
             let second_byte = read_u8(buffer, cursor)?;
             u64::from(first_byte & 0x3F) << 8 | u64::from(second_byte)
         },
-        0b10 => read_uint_le(buffer, cursor, 4)?,
+        // 32 and 64 bit lengths are stored big-endian
+        0b10 if first_byte == 0x80 => read_uint_be(buffer, cursor, 4)?,
+        0b10 if first_byte == 0x81 => read_uint_be(buffer, cursor, 8)?,
         _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "Unexpected string encoding type")),
     };
     Ok(size)
 }
 
 fn read_string(buffer: &[u8], cursor: &mut usize) -> io::Result<String> {
+    let bytes = read_bytes(buffer, cursor)?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+// Read an RDB string without assuming it is valid UTF-8 (listpacks are binary)
+fn read_bytes(buffer: &[u8], cursor: &mut usize) -> io::Result<Vec<u8>> {
     let first_byte = read_u8(buffer, cursor)?;
 
     if (first_byte & 0xC0) == 0xC0 {
         match first_byte {
             0xC0 => {
-                let value = read_u8(buffer, cursor)?;
-                Ok(value.to_string())
+                let value = read_u8(buffer, cursor)? as i8;
+                Ok(value.to_string().into_bytes())
             },
             0xC1 => {
-                let value = read_uint_le(buffer, cursor, 2)?;
-                Ok(value.to_string())
+                let value = read_uint_le(buffer, cursor, 2)? as i16;
+                Ok(value.to_string().into_bytes())
             },
             0xC2 => {
-                let value = read_uint_le(buffer, cursor, 4)?;
-                Ok(value.to_string())
+                let value = read_uint_le(buffer, cursor, 4)? as i32;
+                Ok(value.to_string().into_bytes())
+            },
+            0xC3 => {
+                let compressed_len = decode_size(buffer, cursor)? as usize;
+                let uncompressed_len = decode_size(buffer, cursor)? as usize;
+                let compressed = read_raw(buffer, cursor, compressed_len)?;
+                lzf_decompress(compressed, uncompressed_len)
             },
-            0xC3 => Err(io::Error::new(io::ErrorKind::InvalidData, "LZF compressed strings are not supported")),
             _ => Err(io::Error::new(io::ErrorKind::InvalidData, "Unknown string encoding type")),
         }
     } else {
-        let size = match first_byte >> 6 {
-            0b00 => u64::from(first_byte & 0x3F),
-            0b01 => {
-                let second_byte = read_u8(buffer, cursor)?;
-                u64::from(first_byte & 0x3F) << 8 | u64::from(second_byte)
-            },
-            0b10 => read_uint_le(buffer, cursor, 4)?,
-            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "Unexpected string encoding type")),
+        *cursor -= 1;
+        let size = decode_size(buffer, cursor)?;
+        Ok(read_raw(buffer, cursor, size as usize)?.to_vec())
+    }
+}
+
+// Decompress an LZF block. Each control byte either starts a run of up to 32 literal bytes
+// (values below 32) or is a back-reference copying bytes already written to the output.
+fn lzf_decompress(input: &[u8], uncompressed_len: usize) -> io::Result<Vec<u8>> {
+    let corrupt = || io::Error::new(io::ErrorKind::InvalidData, "Corrupt LZF compressed string");
+    let mut output = Vec::with_capacity(uncompressed_len);
+    let mut cursor = 0;
+
+    while cursor < input.len() {
+        let control = usize::from(read_u8(input, &mut cursor)?);
+        if control < 32 {
+            // Literal run of control + 1 bytes
+            output.extend_from_slice(read_raw(input, &mut cursor, control + 1)?);
+        } else {
+            // Back-reference: 3 bits of length (7 means an extra length byte follows)
+            // and 13 bits of distance back from the end of the output
+            let mut length = control >> 5;
+            if length == 7 {
+                length += usize::from(read_u8(input, &mut cursor)?);
+            }
+            let distance = ((control & 0x1F) << 8) + usize::from(read_u8(input, &mut cursor)?) + 1;
+            let start = output.len().checked_sub(distance).ok_or_else(corrupt)?;
+            // The copy may overlap the bytes it produces, so go one byte at a time
+            for i in start..start + length + 2 {
+                output.push(output[i]);
+            }
+        }
+    }
+
+    if output.len() != uncompressed_len {
+        return Err(corrupt());
+    }
+    Ok(output)
+}
+
+// RDB value types for values stored element by element, as the writer saves them
+pub(crate) const RDB_TYPE_STRING: u8 = 0x00;
+pub(crate) const RDB_TYPE_LIST: u8 = 0x01;
+pub(crate) const RDB_TYPE_SET: u8 = 0x02;
+const RDB_TYPE_ZSET: u8 = 0x03; // Scores as strings; only older versions write it
+pub(crate) const RDB_TYPE_HASH: u8 = 0x04;
+pub(crate) const RDB_TYPE_ZSET_2: u8 = 0x05; // Scores as little-endian doubles
+
+// RDB value types for streams, one per generation of the stream encoding
+pub(crate) const RDB_TYPE_STREAM_LISTPACKS: u8 = 0x0F;
+const RDB_TYPE_STREAM_LISTPACKS_2: u8 = 0x13;
+const RDB_TYPE_STREAM_LISTPACKS_3: u8 = 0x15;
+
+// Flags stored with each entry of a stream listpack
+const STREAM_ITEM_FLAG_DELETED: i64 = 1;
+pub(crate) const STREAM_ITEM_FLAG_SAMEFIELDS: i64 = 2;
+
+// Read `count` elements, e.g. the members of a list or set
+fn read_strings(buffer: &[u8], cursor: &mut usize, count: u64) -> io::Result<Vec<String>> {
+    (0..count).map(|_| read_string(buffer, cursor)).collect()
+}
+
+// A sorted set member's score: a double in RDB_TYPE_ZSET_2, otherwise a length-prefixed string
+// with 253-255 standing for NaN, +inf and -inf
+fn read_zset_score(buffer: &[u8], cursor: &mut usize, rdb_type: u8) -> io::Result<f64> {
+    if rdb_type == RDB_TYPE_ZSET_2 {
+        return Ok(f64::from_bits(read_uint_le(buffer, cursor, 8)?));
+    }
+    match read_u8(buffer, cursor)? {
+        253 => Ok(f64::NAN),
+        254 => Ok(f64::INFINITY),
+        255 => Ok(f64::NEG_INFINITY),
+        len => std::str::from_utf8(read_raw(buffer, cursor, usize::from(len))?)
+            .ok()
+            .and_then(|score| score.parse().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid sorted set score in RDB file")),
+    }
+}
+
+// Decode a list, set, hash or sorted set stored element by element
+fn read_collection(buffer: &[u8], cursor: &mut usize, rdb_type: u8) -> io::Result<RedisValueType> {
+    let size = decode_size(buffer, cursor)?;
+    Ok(match rdb_type {
+        RDB_TYPE_LIST => RedisValueType::ListValue(read_strings(buffer, cursor, size)?.into()),
+        RDB_TYPE_SET => RedisValueType::SetValue(read_strings(buffer, cursor, size)?.into_iter().collect()),
+        RDB_TYPE_HASH => {
+            let mut hash = HashMap::new();
+            for _ in 0..size {
+                let field = read_string(buffer, cursor)?;
+                hash.insert(field, read_string(buffer, cursor)?);
+            }
+            RedisValueType::HashValue(hash)
+        }
+        _ => {
+            let mut sorted_set = SortedSet::new();
+            for _ in 0..size {
+                let member = read_string(buffer, cursor)?;
+                sorted_set.insert(member, read_zset_score(buffer, cursor, rdb_type)?);
+            }
+            RedisValueType::ZSetValue(sorted_set)
+        }
+    })
+}
+
+fn invalid_stream(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Invalid stream in RDB file: {}", message))
+}
+
+// Decode a stream value. Consumer groups aren't supported, so they are read only to skip past them.
+fn read_stream(buffer: &[u8], cursor: &mut usize, rdb_type: u8) -> io::Result<Stream> {
+    let mut stream = Stream::new();
+
+    let listpack_count = decode_size(buffer, cursor)?;
+    for _ in 0..listpack_count {
+        // Each listpack is keyed on its master ID, stored as two big-endian u64s
+        let node_key = read_bytes(buffer, cursor)?;
+        if node_key.len() != 16 {
+            return Err(invalid_stream("node key is not a stream ID"));
+        }
+        let master_id = StreamID {
+            milliseconds_time: read_uint_be(&node_key, &mut 0, 8)?,
+            sequence_number: read_uint_be(&node_key, &mut 8, 8)?,
+        };
+        let listpack = read_bytes(buffer, cursor)?;
+        read_stream_listpack(&listpack, master_id, &mut stream)?;
+    }
+
+    // Length and last ID, then first ID, max deleted ID and entries added in newer encodings.
+    // The last ID may be past the last entry if newer ones were deleted.
+    decode_size(buffer, cursor)?;
+    stream.set_last_id(StreamID {
+        milliseconds_time: decode_size(buffer, cursor)? as u64,
+        sequence_number: decode_size(buffer, cursor)? as u64,
+    });
+    let extra_metadata_fields = if rdb_type == RDB_TYPE_STREAM_LISTPACKS { 0 } else { 5 };
+    for _ in 0..extra_metadata_fields {
+        decode_size(buffer, cursor)?;
+    }
+
+    let group_count = decode_size(buffer, cursor)?;
+    for _ in 0..group_count {
+        read_bytes(buffer, cursor)?; // Group name
+        decode_size(buffer, cursor)?; // Last delivered ID
+        decode_size(buffer, cursor)?;
+        if rdb_type != RDB_TYPE_STREAM_LISTPACKS {
+            decode_size(buffer, cursor)?; // Entries read
+        }
+        let pending_count = decode_size(buffer, cursor)?;
+        for _ in 0..pending_count {
+            read_raw(buffer, cursor, 16)?; // Entry ID
+            read_raw(buffer, cursor, 8)?; // Delivery time
+            decode_size(buffer, cursor)?; // Delivery count
+        }
+        let consumer_count = decode_size(buffer, cursor)?;
+        for _ in 0..consumer_count {
+            read_bytes(buffer, cursor)?; // Consumer name
+            read_raw(buffer, cursor, 8)?; // Seen time
+            if rdb_type == RDB_TYPE_STREAM_LISTPACKS_3 {
+                read_raw(buffer, cursor, 8)?; // Active time
+            }
+            let consumer_pending_count = decode_size(buffer, cursor)?;
+            for _ in 0..consumer_pending_count {
+                read_raw(buffer, cursor, 16)?; // Entry ID
+            }
+        }
+    }
+
+    Ok(stream)
+}
+
+// Add the live entries of one stream listpack to `stream`. The listpack starts with a master
+// entry (count, deleted, field count, fields..., 0) and IDs are stored as deltas from `master_id`.
+fn read_stream_listpack(
+    listpack: &[u8],
+    master_id: StreamID,
+    stream: &mut Stream,
+) -> io::Result<()> {
+    let mut elements = parse_listpack(listpack)?.into_iter().peekable();
+    let mut next_element = || elements.next().ok_or_else(|| invalid_stream("listpack ended early"));
+
+    next_element()?.as_integer()?; // Valid entry count
+    next_element()?.as_integer()?; // Deleted entry count
+    let master_field_count = next_element()?.as_integer()?;
+    let mut master_fields = Vec::new();
+    for _ in 0..master_field_count {
+        master_fields.push(next_element()?.into_string());
+    }
+    next_element()?; // Master entry terminator
+
+    while elements.peek().is_some() {
+        let mut next_element = || elements.next().ok_or_else(|| invalid_stream("listpack ended early"));
+        let flags = next_element()?.as_integer()?;
+        let stream_id = StreamID {
+            milliseconds_time: master_id.milliseconds_time.wrapping_add(next_element()?.as_integer()? as u64),
+            sequence_number: master_id.sequence_number.wrapping_add(next_element()?.as_integer()? as u64),
+        };
+
+        let mut entry = HashMap::new();
+        if flags & STREAM_ITEM_FLAG_SAMEFIELDS != 0 {
+            for field in &master_fields {
+                entry.insert(field.clone(), next_element()?.into_string());
+            }
+        } else {
+            let field_count = next_element()?.as_integer()?;
+            for _ in 0..field_count {
+                let field = next_element()?.into_string();
+                entry.insert(field, next_element()?.into_string());
+            }
+        }
+        next_element()?; // Number of listpack elements in this entry
+
+        if flags & STREAM_ITEM_FLAG_DELETED == 0 {
+            stream.insert(stream_id, entry);
+        }
+    }
+    Ok(())
+}
+
+enum ListpackElement {
+    Integer(i64),
+    String(Vec<u8>),
+}
+
+impl ListpackElement {
+    fn as_integer(&self) -> io::Result<i64> {
+        match self {
+            ListpackElement::Integer(value) => Ok(*value),
+            ListpackElement::String(bytes) => std::str::from_utf8(bytes)
+                .ok()
+                .and_then(|s| s.parse::<i64>().ok())
+                .ok_or_else(|| invalid_stream("expected an integer")),
+        }
+    }
+
+    fn into_string(self) -> String {
+        match self {
+            ListpackElement::Integer(value) => value.to_string(),
+            ListpackElement::String(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+        }
+    }
+}
+
+// Interpret the low `bits` of `value` as a two's complement integer
+fn sign_extend(value: u64, bits: u32) -> i64 {
+    let shift = 64 - bits;
+    ((value << shift) as i64) >> shift
+}
+
+// Split a listpack into its elements. Each element is an encoding byte, its data,
+// and a back-length used for reverse traversal that we skip over.
+fn parse_listpack(listpack: &[u8]) -> io::Result<Vec<ListpackElement>> {
+    let mut cursor = 6; // Total byte count (u32) and element count (u16)
+    let mut elements = Vec::new();
+
+    loop {
+        let start = cursor;
+        let encoding = read_u8(listpack, &mut cursor)?;
+        let element = match encoding {
+            0xFF => break,
+            _ if encoding & 0x80 == 0 => ListpackElement::Integer(i64::from(encoding & 0x7F)),
+            _ if encoding & 0xC0 == 0x80 => {
+                let length = usize::from(encoding & 0x3F);
+                ListpackElement::String(read_raw(listpack, &mut cursor, length)?.to_vec())
+            }
+            _ if encoding & 0xE0 == 0xC0 => {
+                let value = u64::from(encoding & 0x1F) << 8 | u64::from(read_u8(listpack, &mut cursor)?);
+                ListpackElement::Integer(sign_extend(value, 13))
+            }
+            _ if encoding & 0xF0 == 0xE0 => {
+                let length = usize::from(encoding & 0x0F) << 8 | usize::from(read_u8(listpack, &mut cursor)?);
+                ListpackElement::String(read_raw(listpack, &mut cursor, length)?.to_vec())
+            }
+            0xF0 => {
+                let length = read_uint_le(listpack, &mut cursor, 4)? as usize;
+                ListpackElement::String(read_raw(listpack, &mut cursor, length)?.to_vec())
+            }
+            0xF1 => ListpackElement::Integer(sign_extend(read_uint_le(listpack, &mut cursor, 2)?, 16)),
+            0xF2 => ListpackElement::Integer(sign_extend(read_uint_le(listpack, &mut cursor, 3)?, 24)),
+            0xF3 => ListpackElement::Integer(sign_extend(read_uint_le(listpack, &mut cursor, 4)?, 32)),
+            0xF4 => ListpackElement::Integer(read_uint_le(listpack, &mut cursor, 8)? as i64),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "Unknown listpack encoding")),
+        };
+
+        // The back-length grows by a byte for about every 7 bits of the element's size, with
+        // Redis's thresholds (lpEncodeBacklen)
+        let element_len = cursor - start;
+        let backlen_size = match element_len {
+            0..=127 => 1,
+            128..=16382 => 2,
+            16383..=2097150 => 3,
+            2097151..=268435454 => 4,
+            _ => 5,
         };
+        read_raw(listpack, &mut cursor, backlen_size)?;
+        elements.push(element);
+    }
 
-        let string_bytes = &buffer[*cursor..*cursor + size as usize];
-        *cursor += size as usize;
-        Ok(String::from_utf8_lossy(string_bytes).into_owned())
+    Ok(elements)
+}
+
+// First RDB version that ends with a CRC64 checksum
+const RDB_CHECKSUM_VERSION: u32 = 5;
+
+// Check the CRC64 trailer, the file's last 8 bytes, against everything before it. A stored
+// checksum of 0 means the writer didn't compute one, so like Redis it isn't checked.
+fn verify_rdb_checksum(buffer: &[u8], version: u32, verify_checksum: bool) -> io::Result<()> {
+    if !verify_checksum || version < RDB_CHECKSUM_VERSION {
+        return Ok(());
+    }
+    let mut cursor = buffer.len().saturating_sub(8);
+    let end = cursor;
+    let expected = read_uint_le(buffer, &mut cursor, 8)?;
+    if expected != 0 && crc64(&buffer[..end]) != expected {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Wrong RDB checksum"));
     }
+    Ok(())
 }
 
+// Oldest and newest RDB format versions this parser understands
+const MIN_RDB_VERSION: u32 = 3;
+const MAX_RDB_VERSION: u32 = 11;
+
+fn parse_rdb_version(buffer: &[u8]) -> io::Result<u32> {
+    let invalid_header = || io::Error::new(io::ErrorKind::InvalidData, "Invalid RDB file header");
+    let header = buffer.get(0..9).ok_or_else(invalid_header)?;
+    if &header[0..5] != b"REDIS" || !header[5..].iter().all(u8::is_ascii_digit) {
+        return Err(invalid_header());
+    }
+    let version = header[5..].iter().fold(0, |version, digit| version * 10 + u32::from(digit - b'0'));
+    if !(MIN_RDB_VERSION..=MAX_RDB_VERSION).contains(&version) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unsupported RDB version {} (supported: {}-{})", version, MIN_RDB_VERSION, MAX_RDB_VERSION),
+        ));
+    }
+    Ok(version)
+}
 
-pub fn parse_rdb_file(file_path: &str, db: &mut RedisDatabase) -> io::Result<()> {
+pub fn parse_rdb_file(file_path: &str, db: &mut RedisDatabase, verify_checksum: bool) -> io::Result<()> {
     let mut file = fs::File::open(file_path)?;
     let mut buffer = Vec::new();
     file.read_to_end(&mut buffer)?;
@@ -94,23 +445,27 @@ pub fn parse_rdb_file(file_path: &str, db: &mut RedisDatabase) -> io::Result<()>
     }
     println!(); // Final newline after the last line
 
+    parse_rdb_bytes(&buffer, db, verify_checksum)
+}
 
+// Load an RDB image into the database; used both for the file on disk and
+// for the snapshot a replica receives from its master
+pub fn parse_rdb_bytes(buffer: &[u8], db: &mut RedisDatabase, verify_checksum: bool) -> io::Result<()> {
     let mut cursor = 0;
     let mut current_ttl: Option<u64> = None;
+    let mut database_index = 0;
 
-    // Validate header
-    let header = &buffer[0..5]; // Only check the first 5 bytes ("REDIS")
-    let header_str = String::from_utf8_lossy(header);
-    if header_str != "REDIS" {
-        return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid RDB file header"));
-    }
+    // Validate header: "REDIS" followed by a 4 digit version number
+    let version = parse_rdb_version(buffer)?;
+    // Before loading anything, so a corrupt file leaves the database empty
+    verify_rdb_checksum(buffer, version, verify_checksum)?;
     cursor += 9;
 
     // Handle metadata sections
-    while let Ok(byte) = read_u8(&buffer, &mut cursor) {
+    while let Ok(byte) = read_u8(buffer, &mut cursor) {
         if byte == 0xFA {
-            let _meta_key = read_string(&buffer, &mut cursor)?;
-            let _meta_value = read_string(&buffer, &mut cursor)?;
+            let _meta_key = read_string(buffer, &mut cursor)?;
+            let _meta_value = read_string(buffer, &mut cursor)?;
         } else {
             cursor -= 1;
             break;
@@ -118,11 +473,11 @@ pub fn parse_rdb_file(file_path: &str, db: &mut RedisDatabase) -> io::Result<()>
     }
 
     // Parse key-value pairs with TTL
-    while let Ok(byte) = read_u8(&buffer, &mut cursor) {
+    while let Ok(byte) = read_u8(buffer, &mut cursor) {
         match byte {
             0xFD => { // Expiration timestamp in seconds
                 println!("Debug: Found key with expiration timestamp in seconds.");
-                let expire_seconds = read_uint_le(&buffer, &mut cursor, 4)?;
+                let expire_seconds = read_uint_le(buffer, &mut cursor, 4)?;
                 let now_seconds = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or(Duration::ZERO).as_secs();
                 println!("Debug: Current time in seconds: {}", now_seconds);
                 println!("Debug: Expiration time in seconds: {}", expire_seconds);
@@ -134,7 +489,7 @@ pub fn parse_rdb_file(file_path: &str, db: &mut RedisDatabase) -> io::Result<()>
             },
             0xFC => { // Expiration timestamp in milliseconds
                 println!("Debug: Found key with expiration timestamp in milliseconds.");
-                let expire_milliseconds = read_uint_le(&buffer, &mut cursor, 8)?;
+                let expire_milliseconds = read_uint_le(buffer, &mut cursor, 8)?;
                 let now_millis = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or(Duration::ZERO).as_millis() as u64;
                 println!("Debug: Current time in milliseconds: {}", now_millis);
                 current_ttl = if expire_milliseconds > now_millis {
@@ -143,16 +498,34 @@ pub fn parse_rdb_file(file_path: &str, db: &mut RedisDatabase) -> io::Result<()>
                     Some(0)  // Already expired
                 };
             },
-            0xFE => { decode_size(&buffer, &mut cursor)?; }, // Start of database subsection
+            0xFE => { // Start of database subsection
+                database_index = decode_size(buffer, &mut cursor)? as usize;
+                if database_index >= DATABASE_COUNT {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "RDB database index out of range"));
+                }
+            },
             0xFB => {
-                decode_size(&buffer, &mut cursor)?; // Key hash table size
-                decode_size(&buffer, &mut cursor)?; // Expire hash table size
+                decode_size(buffer, &mut cursor)?; // Key hash table size
+                decode_size(buffer, &mut cursor)?; // Expire hash table size
             },
-            0x00 | 0x01 | 0x02 | 0x03 => {
-                let key = read_string(&buffer, &mut cursor)?;
-                let value = read_string(&buffer, &mut cursor)?;
-                println!("Debug: Inserting key-value pair. Key: {}, Value: {}, TTL: {:?}", key, value, current_ttl);
-                db.insert(key, RedisValue::new(value, current_ttl)); // Insert with TTL in milliseconds
+            RDB_TYPE_STRING => {
+                let key = read_string(buffer, &mut cursor)?;
+                // Values are binary-safe, so they are kept as the bytes stored
+                let value = read_bytes(buffer, &mut cursor)?;
+                println!("Debug: Inserting key-value pair. Key: {}, Value: {}, TTL: {:?}", key, String::from_utf8_lossy(&value), current_ttl);
+                db.database_mut(database_index).insert(key, RedisValue::new(value, current_ttl)); // Insert with TTL in milliseconds
+                current_ttl = None; // Reset TTL after insertion
+            },
+            RDB_TYPE_LIST | RDB_TYPE_SET | RDB_TYPE_ZSET | RDB_TYPE_HASH | RDB_TYPE_ZSET_2 => {
+                let key = read_string(buffer, &mut cursor)?;
+                let value = read_collection(buffer, &mut cursor, byte)?;
+                db.database_mut(database_index).insert(key, RedisValue::new(value, current_ttl));
+                current_ttl = None; // Reset TTL after insertion
+            },
+            RDB_TYPE_STREAM_LISTPACKS | RDB_TYPE_STREAM_LISTPACKS_2 | RDB_TYPE_STREAM_LISTPACKS_3 => {
+                let key = read_string(buffer, &mut cursor)?;
+                let stream = read_stream(buffer, &mut cursor, byte)?;
+                db.database_mut(database_index).insert(key, RedisValue::new(stream, current_ttl));
                 current_ttl = None; // Reset TTL after insertion
             },
             0xFF => { break; }, // End of file section
@@ -163,4 +536,102 @@ pub fn parse_rdb_file(file_path: &str, db: &mut RedisDatabase) -> io::Result<()>
     }
     
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // "abc" as a literal run, then a back-reference copying 9 bytes from 3 back, which
+    // overlaps the bytes it writes
+    const COMPRESSED: [u8; 7] = [0x02, b'a', b'b', b'c', 0xE0, 0x00, 0x02];
+
+    #[test]
+    fn test_lzf_decompress_expands_back_references() {
+        assert_eq!(lzf_decompress(&COMPRESSED, 12).unwrap(), b"abcabcabcabc");
+    }
+
+    #[test]
+    fn test_lzf_decompress_rejects_corrupt_input() {
+        // The wrong uncompressed length
+        assert!(lzf_decompress(&COMPRESSED, 11).is_err());
+        // A back-reference to before the start of the output
+        assert!(lzf_decompress(&[0x00, b'a', 0x20, 0x05], 4).is_err());
+        // A literal run cut short
+        assert!(lzf_decompress(&[0x04, b'a', b'b'], 5).is_err());
+    }
+
+    #[test]
+    fn test_read_bytes_decodes_lzf_strings() {
+        let mut buffer = vec![0xC3, COMPRESSED.len() as u8, 12];
+        buffer.extend_from_slice(&COMPRESSED);
+        let mut cursor = 0;
+        assert_eq!(read_bytes(&buffer, &mut cursor).unwrap(), b"abcabcabcabc");
+        assert_eq!(cursor, buffer.len());
+    }
+
+    // An RDB file holding one string key in database 0, with `header` and no checksum
+    fn rdb_with_header(header: &[u8]) -> Vec<u8> {
+        let mut buffer = header.to_vec();
+        buffer.extend_from_slice(&[0xFE, 0x00, 0xFB, 0x01, 0x00, 0x00]);
+        buffer.extend_from_slice(&[0x03, b'k', b'e', b'y', 0x05, b'v', b'a', b'l', b'u', b'e']);
+        buffer.push(0xFF);
+        buffer.extend_from_slice(&[0; 8]);
+        buffer
+    }
+
+    #[test]
+    fn test_parse_rdb_bytes_loads_supported_versions() {
+        for header in [b"REDIS0003", b"REDIS0009", b"REDIS0011"] {
+            let mut db = RedisDatabase::new();
+            parse_rdb_bytes(&rdb_with_header(header), &mut db, true).unwrap();
+            assert_eq!(db.get("key").unwrap().get_value().string_bytes(), Some(b"value".to_vec()));
+        }
+    }
+
+    #[test]
+    fn test_parse_rdb_version_rejects_unknown_versions() {
+        assert_eq!(parse_rdb_version(b"REDIS0011").unwrap(), 11);
+        let error = parse_rdb_version(b"REDIS0012").unwrap_err();
+        assert_eq!(error.to_string(), "Unsupported RDB version 12 (supported: 3-11)");
+        assert!(parse_rdb_version(b"REDIS0002").is_err());
+        for header in [&b"REDIS00x1"[..], b"RADIS0011", b"REDIS001"] {
+            assert_eq!(parse_rdb_version(header).unwrap_err().to_string(), "Invalid RDB file header");
+        }
+
+        let mut db = RedisDatabase::new();
+        assert!(parse_rdb_bytes(&rdb_with_header(b"REDIS0012"), &mut db, true).is_err());
+        assert!(db.data().is_empty());
+    }
+
+    #[test]
+    fn test_parse_rdb_bytes_verifies_the_checksum() {
+        let mut buffer = rdb_with_header(b"REDIS0011");
+        let end = buffer.len() - 8;
+        let checksum = crc64(&buffer[..end]);
+        buffer[end..].copy_from_slice(&checksum.to_le_bytes());
+        parse_rdb_bytes(&buffer, &mut RedisDatabase::new(), true).unwrap();
+
+        // Corrupt the value: "valUe"
+        buffer[end - 3] = b'U';
+        let mut db = RedisDatabase::new();
+        assert_eq!(parse_rdb_bytes(&buffer, &mut db, true).unwrap_err().to_string(), "Wrong RDB checksum");
+        assert!(db.data().is_empty());
+        // With checking turned off the file loads as it is
+        parse_rdb_bytes(&buffer, &mut db, false).unwrap();
+        assert_eq!(db.get("key").unwrap().get_value().string_bytes(), Some(b"valUe".to_vec()));
+    }
+
+    #[test]
+    fn test_read_collection_decodes_string_scores() {
+        // Two members, "a" scored "2.5" and "b" scored -inf
+        let buffer = [0x02, 0x01, b'a', 0x03, b'2', b'.', b'5', 0x01, b'b', 255];
+        let mut cursor = 0;
+        let RedisValueType::ZSetValue(sorted_set) = read_collection(&buffer, &mut cursor, RDB_TYPE_ZSET).unwrap() else {
+            panic!("Not a sorted set");
+        };
+        assert_eq!(cursor, buffer.len());
+        assert_eq!(sorted_set.score("a"), Some(2.5));
+        assert_eq!(sorted_set.score("b"), Some(f64::NEG_INFINITY));
+    }
+}
@@ -0,0 +1,327 @@
+// src/rdb_writer.rs
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::database::{RedisDatabase, RedisValueType, Stream, StreamID};
+use crate::rdb_parser::{
+    RDB_TYPE_HASH, RDB_TYPE_LIST, RDB_TYPE_SET, RDB_TYPE_STREAM_LISTPACKS, RDB_TYPE_STRING, RDB_TYPE_ZSET_2, STREAM_ITEM_FLAG_SAMEFIELDS,
+};
+use crate::utils::crc64;
+
+// Where the RDB file lives, from the `dir` and `dbfilename` config like Redis (default ./dump.rdb)
+pub fn rdb_file_path(config_map: &HashMap<String, String>) -> PathBuf {
+    let dir = config_map.get("dir").map_or(".", String::as_str);
+    let dbfilename = config_map.get("dbfilename").map_or("dump.rdb", String::as_str);
+    Path::new(dir).join(dbfilename)
+}
+
+// Whether RDB files get a CRC64 trailer and have it checked on load, from the `rdbchecksum`
+// config like Redis (default yes). Without it the checksum is written as 0, meaning "not computed".
+pub fn rdb_checksum_enabled(config_map: &HashMap<String, String>) -> bool {
+    !config_map.get("rdbchecksum").is_some_and(|value| value.eq_ignore_ascii_case("no"))
+}
+
+// A key/value as it will be written to the RDB file
+pub struct RdbEntry {
+    key: String,
+    value: RedisValueType,
+    expire_at_millis: Option<u64>, // Absolute Unix time in milliseconds
+}
+
+// Copy out every live value of each database (outer index = database index),
+// turning relative TTLs into absolute expiry times
+pub fn snapshot(db: &RedisDatabase) -> Vec<Vec<RdbEntry>> {
+    let now_millis = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+    db.databases()
+        .iter()
+        .map(|data| {
+            data.iter()
+                .filter(|(_, redis_value)| !redis_value.is_expired())
+                .map(|(key, redis_value)| {
+                    let expire_at_millis = redis_value.remaining_ttl().map(|ttl| now_millis + ttl.as_millis() as u64);
+                    RdbEntry { key: key.clone(), value: redis_value.get_value().clone(), expire_at_millis }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// Serialize the entries into an RDB file: header, a subsection per non-empty database with
+// its key/values, and the CRC64 trailer (0 when `checksum` is off)
+pub fn encode_rdb(databases: &[Vec<RdbEntry>], checksum: bool) -> Vec<u8> {
+    let mut buffer = b"REDIS0011".to_vec();
+
+    for (index, entries) in databases.iter().enumerate().filter(|(_, entries)| !entries.is_empty()) {
+        buffer.push(0xFE); // Start of database subsection
+        encode_size(&mut buffer, index as u64);
+        buffer.push(0xFB); // Hash table size hints
+        encode_size(&mut buffer, entries.len() as u64);
+        encode_size(&mut buffer, entries.iter().filter(|entry| entry.expire_at_millis.is_some()).count() as u64);
+
+        for entry in entries {
+            if let Some(expire_at_millis) = entry.expire_at_millis {
+                buffer.push(0xFC); // Expiration timestamp in milliseconds
+                buffer.extend_from_slice(&expire_at_millis.to_le_bytes());
+            }
+            encode_value(&mut buffer, &entry.key, &entry.value);
+        }
+    }
+
+    buffer.push(0xFF); // End of file
+    let checksum = if checksum { crc64(&buffer) } else { 0 };
+    buffer.extend_from_slice(&checksum.to_le_bytes());
+    buffer
+}
+
+// The value type byte, key and value. Lists, sets, hashes and sorted sets are written element by
+// element rather than in the compact encodings Redis uses for small ones, which every Redis
+// version still loads; streams only have a listpack encoding.
+fn encode_value(buffer: &mut Vec<u8>, key: &str, value: &RedisValueType) {
+    let type_byte = match value {
+        RedisValueType::IntegerValue(_) | RedisValueType::StringValue(_) => RDB_TYPE_STRING,
+        RedisValueType::ListValue(_) => RDB_TYPE_LIST,
+        RedisValueType::SetValue(_) => RDB_TYPE_SET,
+        RedisValueType::HashValue(_) => RDB_TYPE_HASH,
+        RedisValueType::ZSetValue(_) => RDB_TYPE_ZSET_2,
+        RedisValueType::StreamValue(_) => RDB_TYPE_STREAM_LISTPACKS,
+    };
+    buffer.push(type_byte);
+    encode_string(buffer, key.as_bytes());
+
+    match value {
+        RedisValueType::IntegerValue(int_value) => encode_string(buffer, int_value.to_string().as_bytes()),
+        RedisValueType::StringValue(bytes) => encode_string(buffer, bytes),
+        RedisValueType::ListValue(list) => {
+            encode_size(buffer, list.len() as u64);
+            list.iter().for_each(|element| encode_string(buffer, element.as_bytes()));
+        }
+        RedisValueType::SetValue(set) => {
+            encode_size(buffer, set.len() as u64);
+            set.iter().for_each(|member| encode_string(buffer, member.as_bytes()));
+        }
+        RedisValueType::HashValue(hash) => {
+            encode_size(buffer, hash.len() as u64);
+            for (field, value) in hash {
+                encode_string(buffer, field.as_bytes());
+                encode_string(buffer, value.as_bytes());
+            }
+        }
+        RedisValueType::ZSetValue(sorted_set) => {
+            encode_size(buffer, sorted_set.len() as u64);
+            for (member, score) in sorted_set.iter() {
+                encode_string(buffer, member.as_bytes());
+                buffer.extend_from_slice(&score.to_le_bytes());
+            }
+        }
+        RedisValueType::StreamValue(stream) => encode_stream(buffer, stream),
+    }
+}
+
+// A stream as RDB_TYPE_STREAM_LISTPACKS: its entries in a single listpack keyed on the first ID,
+// then the length and the last ID, which stays past the last entry if newer ones were deleted.
+// Consumer groups aren't supported, so there are none to write.
+fn encode_stream(buffer: &mut Vec<u8>, stream: &Stream) {
+    match stream.entries().keys().next() {
+        Some(master_id) => {
+            encode_size(buffer, 1);
+            let mut node_key = master_id.milliseconds_time.to_be_bytes().to_vec();
+            node_key.extend_from_slice(&master_id.sequence_number.to_be_bytes());
+            encode_string(buffer, &node_key);
+            encode_string(buffer, &stream_listpack(stream, *master_id));
+        }
+        None => encode_size(buffer, 0),
+    }
+    encode_size(buffer, stream.len() as u64);
+    encode_size(buffer, stream.last_id().milliseconds_time);
+    encode_size(buffer, stream.last_id().sequence_number);
+    encode_size(buffer, 0); // Consumer groups
+}
+
+// The listpack holding a stream's entries, laid out as read_stream_listpack expects: a master
+// entry naming the first entry's fields, then each entry with its ID as deltas from `master_id`.
+// Entries with exactly the master fields store only their values.
+fn stream_listpack(stream: &Stream, master_id: StreamID) -> Vec<u8> {
+    let mut master_fields: Vec<&String> = stream.entries().values().next().map_or(Vec::new(), |entry| entry.keys().collect());
+    master_fields.sort();
+
+    let mut listpack = Listpack::new();
+    listpack.push_integer(stream.len() as i64);
+    listpack.push_integer(0); // Deleted entries
+    listpack.push_integer(master_fields.len() as i64);
+    for field in &master_fields {
+        listpack.push_string(field.as_bytes());
+    }
+    listpack.push_integer(0); // End of the master entry
+
+    for (id, entry) in stream.entries() {
+        let same_fields = entry.len() == master_fields.len() && master_fields.iter().all(|field| entry.contains_key(*field));
+        listpack.push_integer(if same_fields { STREAM_ITEM_FLAG_SAMEFIELDS } else { 0 });
+        listpack.push_integer(id.milliseconds_time.wrapping_sub(master_id.milliseconds_time) as i64);
+        listpack.push_integer(id.sequence_number.wrapping_sub(master_id.sequence_number) as i64);
+        if same_fields {
+            for field in &master_fields {
+                listpack.push_string(entry[*field].as_bytes());
+            }
+            listpack.push_integer(entry.len() as i64 + 3);
+        } else {
+            listpack.push_integer(entry.len() as i64);
+            for (field, value) in entry {
+                listpack.push_string(field.as_bytes());
+                listpack.push_string(value.as_bytes());
+            }
+            listpack.push_integer(entry.len() as i64 * 2 + 4);
+        }
+    }
+    listpack.finish()
+}
+
+// Builds a listpack, the inverse of parse_listpack: a header with the total size and element
+// count, each element's encoding and data followed by its length for walking backwards, and 0xFF
+struct Listpack {
+    bytes: Vec<u8>,
+    count: usize,
+}
+
+impl Listpack {
+    fn new() -> Self {
+        Self { bytes: vec![0; 6], count: 0 }
+    }
+
+    fn push_integer(&mut self, value: i64) {
+        let mut element = Vec::new();
+        match value {
+            0..=127 => element.push(value as u8),
+            -4096..=4095 => element.extend_from_slice(&[0xC0 | ((value >> 8) as u8 & 0x1F), value as u8]),
+            -32768..=32767 => {
+                element.push(0xF1);
+                element.extend_from_slice(&(value as i16).to_le_bytes());
+            }
+            -8388608..=8388607 => {
+                element.push(0xF2);
+                element.extend_from_slice(&(value as i32).to_le_bytes()[..3]);
+            }
+            _ if i32::try_from(value).is_ok() => {
+                element.push(0xF3);
+                element.extend_from_slice(&(value as i32).to_le_bytes());
+            }
+            _ => {
+                element.push(0xF4);
+                element.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        self.push_element(element);
+    }
+
+    fn push_string(&mut self, bytes: &[u8]) {
+        let mut element = match bytes.len() {
+            len @ 0..64 => vec![0x80 | len as u8],
+            len @ 64..4096 => vec![0xE0 | (len >> 8) as u8, len as u8],
+            len => {
+                let mut header = vec![0xF0];
+                header.extend_from_slice(&(len as u32).to_le_bytes());
+                header
+            }
+        };
+        element.extend_from_slice(bytes);
+        self.push_element(element);
+    }
+
+    fn push_element(&mut self, element: Vec<u8>) {
+        let len = element.len();
+        self.bytes.extend_from_slice(&element);
+        // Seven bits per byte, high bits first, with the top bit set on all but the first byte
+        let backlen_size = match len {
+            0..=127 => 1,
+            128..=16382 => 2,
+            16383..=2097150 => 3,
+            2097151..=268435454 => 4,
+            _ => 5,
+        };
+        for i in (0..backlen_size).rev() {
+            let bits = (len >> (7 * i)) as u8 & 0x7F;
+            self.bytes.push(if i == backlen_size - 1 { bits } else { bits | 0x80 });
+        }
+        self.count += 1;
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.bytes.push(0xFF);
+        let total_bytes = self.bytes.len() as u32;
+        self.bytes[..4].copy_from_slice(&total_bytes.to_le_bytes());
+        // Past u16::MAX elements the count is left as "unknown"
+        let count = u16::try_from(self.count).unwrap_or(u16::MAX);
+        self.bytes[4..6].copy_from_slice(&count.to_le_bytes());
+        self.bytes
+    }
+}
+
+// Write the file under a temporary name and rename it into place once complete, so a crash or
+// a failed write never leaves a truncated RDB file where the old one was
+pub fn write_rdb_file(file_path: &Path, databases: &[Vec<RdbEntry>], checksum: bool) -> io::Result<()> {
+    static TEMP_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
+    let rdb = encode_rdb(databases, checksum);
+    // SAVE may run while a BGSAVE is still writing, so each save gets a name of its own
+    let temp_name = format!("temp-{}-{}.rdb", std::process::id(), TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed));
+    let temp_path = file_path.with_file_name(temp_name);
+    let result = fs::write(&temp_path, rdb).and_then(|()| fs::rename(&temp_path, file_path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+// Length encoding, the inverse of decode_size in the parser
+fn encode_size(buffer: &mut Vec<u8>, size: u64) {
+    if size < 1 << 6 {
+        buffer.push(size as u8);
+    } else if size < 1 << 14 {
+        buffer.push(0x40 | (size >> 8) as u8);
+        buffer.push(size as u8);
+    } else if size <= u64::from(u32::MAX) {
+        buffer.push(0x80);
+        buffer.extend_from_slice(&(size as u32).to_be_bytes());
+    } else {
+        buffer.push(0x81);
+        buffer.extend_from_slice(&size.to_be_bytes());
+    }
+}
+
+fn encode_string(buffer: &mut Vec<u8>, bytes: &[u8]) {
+    encode_size(buffer, bytes.len() as u64);
+    buffer.extend_from_slice(bytes);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rdb_parser::parse_rdb_bytes;
+
+    #[test]
+    fn test_streams_round_trip_through_the_listpack() {
+        let mut stream = Stream::new();
+        let first = StreamID { milliseconds_time: 5, sequence_number: 0 };
+        stream.insert(first, HashMap::from([("field".to_string(), "value".to_string())]));
+        // IDs far enough from the first to need each integer encoding, and an entry with
+        // fields of its own whose value needs a 12 bit length
+        for (delta, fields) in [(100, "field"), (4000, "other"), (30000, "field"), (8_000_000, "field"), (1 << 40, "field")] {
+            let id = StreamID { milliseconds_time: 5 + delta, sequence_number: delta };
+            stream.insert(id, HashMap::from([(fields.to_string(), "x".repeat(delta as usize % 200))]));
+        }
+        // The last ID stays past the entries once the newest is deleted
+        let last_id = StreamID { milliseconds_time: 1 << 50, sequence_number: 7 };
+        stream.insert(last_id, HashMap::new());
+        stream.remove(&last_id);
+
+        let entry = RdbEntry { key: "stream".to_string(), value: RedisValueType::StreamValue(stream.clone()), expire_at_millis: None };
+        let mut db = RedisDatabase::new();
+        parse_rdb_bytes(&encode_rdb(&[vec![entry]], true), &mut db, true).unwrap();
+        let Some(RedisValueType::StreamValue(loaded)) = db.get("stream").map(|value| value.get_value()) else {
+            panic!("No stream loaded");
+        };
+        assert_eq!(loaded.entries(), stream.entries());
+        assert_eq!(loaded.last_id(), last_id);
+    }
+}
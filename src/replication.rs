@@ -1,18 +1,24 @@
-use std::collections::HashMap;
 use std::sync::Arc;
+use bytes::{Buf, BytesMut};
 use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::OwnedWriteHalf;
 use tokio::net::TcpStream;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, RwLock};
+use tokio::time::Duration;
 use crate::database::RedisDatabase;
+use crate::network::{ClientState, SharedConfig};
 use crate::commands::process_commands_after_rdb;
 use crate::database::ReplicationInfoValue;
+use crate::rdb_parser::parse_rdb_bytes;
+use crate::rdb_writer::rdb_checksum_enabled;
+use crate::utils::{get_end_of_redis_message, READ_CHUNK_SIZE};
 
 // Sends REPLCONF commands to the master after receiving the PING response
 pub async fn send_replconf(
-    stream: &mut TcpStream,
+    mut stream: TcpStream,
     port: &str,
-    db: Arc<Mutex<RedisDatabase>>,
-    config_map: &HashMap<String, String>,
+    db: Arc<RwLock<RedisDatabase>>,
+    config_map: &SharedConfig,
 ) -> io::Result<()> {
     let replconf_listening_port = format!(
         "*3\r\n$8\r\nREPLCONF\r\n$14\r\nlistening-port\r\n${}\r\n{}\r\n",
@@ -22,15 +28,16 @@ pub async fn send_replconf(
     stream.write_all(replconf_listening_port.as_bytes()).await?;
     println!("Sent REPLCONF listening-port with port: {}", port);
 
-    stream.write_all(b"*5\r\n$8\r\nREPLCONF\r\n$4\r\ncapa\r\n$3\r\neof\r\n$4\r\ncapa\r\n$6\r\npsync2\r\n").await?;
-    println!("Sent REPLCONF capa eof capa psync2");
-
+    // Wait for this reply before sending the next REPLCONF, so the two +OKs never arrive in the
+    // same read and the one for capa is left for listen_for_master_commands to answer with PSYNC
     let mut buffer = vec![0; 512];
     let bytes_read = stream.read(&mut buffer).await?;
     let response = String::from_utf8_lossy(&buffer[..bytes_read]);
 
     if response.contains("+OK") {
         println!("Received +OK from master. Waiting for more commands...");
+        stream.write_all(b"*5\r\n$8\r\nREPLCONF\r\n$4\r\ncapa\r\n$3\r\neof\r\n$4\r\ncapa\r\n$6\r\npsync2\r\n").await?;
+        println!("Sent REPLCONF capa eof capa psync2");
         listen_for_master_commands(stream, db, config_map).await?;
     } else {
         println!("Unexpected response from master: {}", response);
@@ -39,31 +46,56 @@ pub async fn send_replconf(
     Ok(())
 }
 
+// How often the replica reports its offset to the master unprompted
+const REPLCONF_ACK_INTERVAL: Duration = Duration::from_secs(1);
+
+// Periodically send the master `REPLCONF ACK <offset>`, so WAIT sees progress without a GETACK,
+// until the connection to the master fails
+async fn send_periodic_acks(writer: Arc<Mutex<OwnedWriteHalf>>, db: Arc<RwLock<RedisDatabase>>) {
+    let mut interval = tokio::time::interval(REPLCONF_ACK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let offset = db.read().await.slave_repl_offset().to_string();
+        let ack_message = format!("*3\r\n$8\r\nREPLCONF\r\n$3\r\nACK\r\n${}\r\n{}\r\n", offset.len(), offset);
+        if let Err(e) = writer.lock().await.write_all(ack_message.as_bytes()).await {
+            eprintln!("Failed to send REPLCONF ACK to master: {}", e);
+            break;
+        }
+    }
+}
+
 // Listens for further commands from the master after REPLCONF
 pub async fn listen_for_master_commands(
-    stream: &mut TcpStream,
-    db: Arc<Mutex<RedisDatabase>>,
-    config_map: &HashMap<String, String>,
+    stream: TcpStream,
+    db: Arc<RwLock<RedisDatabase>>,
+    config_map: &SharedConfig,
 ) -> io::Result<()> {
-    let mut buffer = vec![0; 512];
-    let mut partial_message = Vec::new();
+    // The write half is shared with the task sending periodic ACKs
+    let (mut stream, writer) = stream.into_split();
+    let writer = Arc::new(Mutex::new(writer));
+    let mut ack_task = None;
+    let mut partial_message = BytesMut::with_capacity(READ_CHUNK_SIZE);
     let mut received_rdb = false;
     #[allow(unused_assignments)]
     let mut remaining_bulk_bytes = 0;
+    let mut client_state = ClientState::new();
+    client_state.mark_replication_link();
 
-    while let Ok(bytes_read) = stream.read(&mut buffer).await {
+    loop {
+        partial_message.reserve(READ_CHUNK_SIZE);
+        let Ok(bytes_read) = stream.read_buf(&mut partial_message).await else {
+            break;
+        };
         if bytes_read == 0 && received_rdb {
             println!("Connection closed by master.");
             break;
         }
 
-        partial_message.extend_from_slice(&buffer[..bytes_read]);
-
         // Handle "+OK\r\n" as text
         if let Ok(message_str) = std::str::from_utf8(&partial_message) {
             if message_str == "+OK\r\n" {
                 println!("Received +OK from master. Sending PSYNC command...");
-                stream.write_all(b"*3\r\n$5\r\nPSYNC\r\n$1\r\n?\r\n$2\r\n-1\r\n").await?;
+                writer.lock().await.write_all(b"*3\r\n$5\r\nPSYNC\r\n$1\r\n?\r\n$2\r\n-1\r\n").await?;
                 partial_message.clear();
                 continue;
             }
@@ -76,10 +108,12 @@ pub async fn listen_for_master_commands(
 
             if let Ok(fullresync_str) = std::str::from_utf8(fullresync_message) {
                 if let Some((replid, offset)) = parse_fullresync(fullresync_str) {
-                    let mut db_lock = db.lock().await;
+                    let mut db_lock = db.write().await;
                     db_lock.replication_info.insert("master_replid".to_string(), ReplicationInfoValue::StringValue(replid.clone()));
-                    db_lock.replication_info.insert("master_repl_offset".to_string(), ReplicationInfoValue::StringValue(offset.clone()));
-                    partial_message.drain(..fullresync_end + 2);
+                    db_lock.replication_info.insert("master_repl_offset".to_string(), ReplicationInfoValue::ByteValue(offset));
+                    // The stream picks up at the master's offset, so what we acknowledge counts from there
+                    db_lock.replication_info.insert("slave_repl_offset".to_string(), ReplicationInfoValue::ByteValue(offset));
+                    partial_message.advance(fullresync_end + 2);
                 }
             }
         }
@@ -90,48 +124,58 @@ pub async fn listen_for_master_commands(
                 let header_size = partial_message.windows(2).position(|w| w == b"\r\n").unwrap() + 2;
 
                 // Drain the header bytes
-                partial_message.drain(..header_size);
+                partial_message.advance(header_size);
                 remaining_bulk_bytes = bulk_length;
 
                 // Read the entire bulk string (RDB file)
+                partial_message.reserve(remaining_bulk_bytes.saturating_sub(partial_message.len()));
                 while partial_message.len() < remaining_bulk_bytes {
-                    let bytes_read = stream.read(&mut buffer).await?;
+                    let bytes_read = stream.read_buf(&mut partial_message).await?;
                     if bytes_read == 0 {
                         println!("No bytes read from master when waiting on RDB file. Breaking.");
                         return Ok(());
                     }
-                    partial_message.extend_from_slice(&buffer[..bytes_read]);
                 }
 
-                partial_message.drain(..remaining_bulk_bytes);
+                // Load the master's snapshot through the same parser used for the RDB file on disk
+                let rdb = partial_message.split_to(remaining_bulk_bytes);
+                let verify_checksum = rdb_checksum_enabled(&*config_map.read().await);
+                if let Err(e) = parse_rdb_bytes(&rdb, &mut *db.write().await, verify_checksum) {
+                    println!("Failed to parse RDB file from master: {}", e);
+                }
                 received_rdb = true;
                 println!("RDB file fully received and processed.");
+                ack_task = Some(tokio::spawn(send_periodic_acks(Arc::clone(&writer), db.clone())));
             }
         }
 
-        // Process Redis commands after RDB has been received
+        // Process Redis commands after RDB has been received. Only whole commands are processed;
+        // one split across reads stays in the buffer until the rest of it arrives.
         if received_rdb {
-            if let Ok(partial_str) = std::str::from_utf8(&partial_message) {
-                if !partial_str.is_empty() {
-                    println!("Processing command in replication: {}", partial_str);
-                    process_commands_after_rdb(&mut partial_str.to_string(), db.clone(), config_map, stream).await?;
-
-                    // Clear the processed part of the message
-                    partial_message.clear();
-                }
+            let mut complete_len = 0;
+            while let Some(message_end) = get_end_of_redis_message(&partial_message[complete_len..]) {
+                complete_len += message_end;
+            }
+            if complete_len > 0 {
+                let mut commands = partial_message.split_to(complete_len).to_vec();
+                println!("Processing command in replication: {}", String::from_utf8_lossy(&commands));
+                process_commands_after_rdb(&mut commands, db.clone(), config_map, &writer, &mut client_state).await?;
             }
         }
     }
 
+    if let Some(ack_task) = ack_task {
+        ack_task.abort();
+    }
     Ok(())
 }
 
 // Helper function to parse the FULLRESYNC command and extract replid and offset
-fn parse_fullresync(message: &str) -> Option<(String, String)> {
+fn parse_fullresync(message: &str) -> Option<(String, usize)> {
     let parts: Vec<&str> = message.split_whitespace().collect();
     if parts.len() >= 3 {
         let replid = parts[1].to_string();
-        let offset = parts[2].to_string();
+        let offset = parts[2].parse::<usize>().ok()?;
         Some((replid, offset))
     } else {
         None
@@ -151,18 +195,19 @@ fn parse_bulk_length(message: &[u8]) -> Option<usize> {
 
 // Initializes replication settings, determining whether this server is a master or slave
 pub async fn initialize_replication(
-    config_map: &HashMap<String, String>,
-    db: Arc<Mutex<RedisDatabase>>,
+    config_map: &SharedConfig,
+    db: Arc<RwLock<RedisDatabase>>,
     port: &str,
 ) {
-    if let Some(replicaof) = config_map.get("replicaof") {
+    let replicaof = config_map.read().await.get("replicaof").cloned();
+    if let Some(replicaof) = replicaof {
         let replicaof_parts: Vec<&str> = replicaof.split(' ').collect();
         let ip = replicaof_parts[0];
         let replica_port = replicaof_parts[1];
         let address = format!("{}:{}", ip, replica_port);
 
         {
-            let mut db_lock = db.lock().await;
+            let mut db_lock = db.write().await;
             db_lock.replication_info.insert("role".to_string(), ReplicationInfoValue::StringValue("slave".to_string()));
             println!("Replication info updated to 'slave'.");
         }
@@ -177,7 +222,7 @@ pub async fn initialize_replication(
                 match stream.read(&mut buffer).await {
                     Ok(_) => {
                         println!("Received PING response from master");
-                        let _ = send_replconf(&mut stream, port, db.clone(), config_map).await;
+                        let _ = send_replconf(stream, port, db.clone(), config_map).await;
                     }
                     Err(e) => eprintln!("Failed to receive PING response: {}", e),
                 }
@@ -185,7 +230,7 @@ pub async fn initialize_replication(
             Err(e) => eprintln!("Failed to connect to master at {}: {}", address, e),
         }
     } else {
-        let mut db_lock = db.lock().await;
+        let mut db_lock = db.write().await;
         db_lock.replication_info.insert("role".to_string(), ReplicationInfoValue::StringValue("master".to_string()));
         db_lock.replication_info.insert("master_replid".to_string(), ReplicationInfoValue::StringValue("8371b4fb1155b71f4a04d3e1bc3e18c4a990aeeb".to_string()));
         if !db_lock.replication_info.contains_key("master_repl_offset") {
@@ -1,33 +1,321 @@
-// Function to determine if the end of the Redis message is reached
-pub fn get_end_of_redis_message(message: &str) -> Option<usize> {
-    let mut lines = message.lines();
-    if let Some(line) = lines.next() {
-        if line.starts_with('*') {
-            if let Ok(arg_count) = line[1..].parse::<usize>() {
-                let mut total_len = line.len() + 2; // Include \r\n
-                for _ in 0..arg_count {
-                    if let Some(length_line) = lines.next() {
-                        if length_line.starts_with('$') {
-                            if let Ok(_bulk_length) = length_line[1..].parse::<usize>() {
-                                total_len += length_line.len() + 2; // $<len>\r\n
-                                if let Some(arg) = lines.next() {
-                                    total_len += arg.len() + 2; // Argument and \r\n
-                                }
-                            }
-                        }
-                    }
-                }
-                return Some(total_len);
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use crate::command_table::has_flag;
+
+// Room made in a connection's read buffer before each read. The buffer grows beyond it when a
+// frame is bigger, so large values arrive whole.
+pub const READ_CHUNK_SIZE: usize = 4096;
+
+// Function to determine if the end of the Redis message is reached. Bulk strings are framed by
+// their `$<len>` header, so payloads may contain CRLFs. Returns None while the message is still
+// incomplete; a malformed message is handed over whole so the parser can reply with an error.
+pub fn get_end_of_redis_message(message: &[u8]) -> Option<usize> {
+    // Anything not starting with '*' is an inline command, which ends at the first newline
+    let Some(rest) = message.strip_prefix(b"*") else {
+        return message.iter().position(|&byte| byte == b'\n').map(|newline| newline + 1);
+    };
+    let (arg_count, mut cursor) = read_length_line(rest)?;
+    cursor += 1; // Include the '*'
+    let Some(arg_count) = arg_count else {
+        return Some(message.len());
+    };
+
+    for _ in 0..arg_count {
+        let Some(rest) = message[cursor..].strip_prefix(b"$") else {
+            return if cursor < message.len() { Some(message.len()) } else { None };
+        };
+        let (bulk_length, header_len) = read_length_line(rest)?;
+        let Some(bulk_length) = bulk_length else {
+            return Some(message.len());
+        };
+        cursor += 1 + header_len; // $<len>\r\n
+        let payload_end = cursor + bulk_length;
+        if message.len() < payload_end + 2 {
+            return None;
+        }
+        if &message[payload_end..payload_end + 2] != b"\r\n" {
+            return Some(message.len());
+        }
+        cursor = payload_end + 2; // Payload and \r\n
+    }
+    Some(cursor)
+}
+
+// Parse a `<number>\r\n` line, returning the number (None if it isn't one) and the line's length
+// including the CRLF, or None if the line isn't complete yet
+fn read_length_line(bytes: &[u8]) -> Option<(Option<usize>, usize)> {
+    let line_end = bytes.windows(2).position(|window| window == b"\r\n")?;
+    let number = std::str::from_utf8(&bytes[..line_end]).ok().and_then(|line| line.parse::<usize>().ok());
+    Some((number, line_end + 2))
+}
+
+// Determines whether a command changes the dataset and so must be forwarded to slaves, from its
+// "write" flag in the command table. Reads and protocol-related commands like PING, REPLCONF,
+// PSYNC and SUBSCRIBE are not.
+pub fn is_write_command(command: &str) -> bool {
+    // Replicas deliver published messages to their own subscribers too. Transactions aren't
+    // forwarded command by command; a successful EXEC sends the writes it ran as one block.
+    has_flag(command, "write") || command == "PUBLISH"
+}
+
+// A command as a RESP array of bulk strings, the form commands take on the replication stream
+pub fn encode_command(command: &str, args: &[Vec<u8>]) -> Vec<u8> {
+    let mut frame = format!("*{}\r\n${}\r\n{}\r\n", args.len() + 1, command.len(), command).into_bytes();
+    for arg in args {
+        frame.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        frame.extend_from_slice(arg);
+        frame.extend_from_slice(b"\r\n");
+    }
+    frame
+}
+
+// Whether a command can grow the dataset, so is refused when over maxmemory with nothing left
+// to evict (its "denyoom" flag). Deletions stay allowed so memory can still be freed.
+pub fn is_denyoom_command(command: &str) -> bool {
+    has_flag(command, "denyoom")
+}
+
+// A random u64 from the std hasher's per-instance random keys; good enough for sampling keys
+pub fn random_u64() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+// Redis-style glob matching: `*` matches any run, `?` any single byte, `[abc]`, `[^a-z]`
+// and `[a-z]` match character classes, and `\` escapes the next byte
+pub fn glob_match(pattern: &[u8], string: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => string.is_empty(),
+        Some((b'*', rest)) => {
+            // Consecutive stars behave like one; collapsing them keeps backtracking in check
+            let rest = &rest[rest.iter().take_while(|byte| **byte == b'*').count()..];
+            (0..=string.len()).any(|skip| glob_match(rest, &string[skip..]))
+        }
+        Some((b'?', rest)) => !string.is_empty() && glob_match(rest, &string[1..]),
+        Some((b'[', rest)) => match (string.first(), match_class(rest, string.first().copied())) {
+            (Some(_), Some((true, after_class))) => glob_match(after_class, &string[1..]),
+            _ => false,
+        },
+        Some((b'\\', rest)) if !rest.is_empty() => {
+            string.first() == Some(&rest[0]) && glob_match(&rest[1..], &string[1..])
+        }
+        Some((literal, rest)) => string.first() == Some(literal) && glob_match(rest, &string[1..]),
+    }
+}
+
+// Match `byte` against the class body following a `[`, returning whether it matched and the
+// pattern after the closing `]`. An unterminated class runs to the end of the pattern.
+fn match_class(class: &[u8], byte: Option<u8>) -> Option<(bool, &[u8])> {
+    let byte = byte?;
+    let (negated, mut class) = match class.split_first() {
+        Some((b'^', rest)) => (true, rest),
+        _ => (false, class),
+    };
+
+    let mut matched = false;
+    loop {
+        match class {
+            [] => break,
+            [b']', rest @ ..] => {
+                class = rest;
+                break;
+            }
+            [b'\\', escaped, rest @ ..] => {
+                matched |= *escaped == byte;
+                class = rest;
+            }
+            [start, b'-', end, rest @ ..] if *end != b']' => {
+                let (low, high) = if start <= end { (*start, *end) } else { (*end, *start) };
+                matched |= (low..=high).contains(&byte);
+                class = rest;
+            }
+            [literal, rest @ ..] => {
+                matched |= *literal == byte;
+                class = rest;
             }
         }
     }
-    None
+    Some((matched != negated, class))
+}
+
+// CRC-64/Jones as used by Redis for the RDB checksum (reflected, zero initial value)
+pub fn crc64(bytes: &[u8]) -> u64 {
+    const POLY: u64 = 0x95AC_9329_AC4B_C9B5;
+    let mut crc = 0u64;
+    for byte in bytes {
+        crc ^= u64::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ POLY } else { crc >> 1 };
+        }
+    }
+    crc
 }
 
-// Determines whether a command should be forwarded to slaves
-pub fn should_forward_to_slaves(command: &str) -> bool {
-    match command {
-        "SET" | "GET" | "DEL" | "INCR" | "DECR" | "MSET" | "MGET" => true,
-        _ => false, // Do not forward protocol-related commands like PING, REPLCONF, PSYNC, etc.
+// SHA-256 as lowercase hex, which is how ACL LIST shows passwords
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    const K: [u32; 64] = [
+        0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+        0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+        0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+        0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+        0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+        0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+        0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+        0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+    ];
+    let mut state: [u32; 8] = [0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19];
+
+    // Pad with a 1 bit, zeros, and the message length in bits so it fills whole 64-byte blocks
+    let mut message = bytes.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((bytes.len() as u64) * 8).to_be_bytes());
+
+    for block in message.chunks_exact(64) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let choice = (e & f) ^ (!e & g);
+            let temp1 = h.wrapping_add(s1).wrapping_add(choice).wrapping_add(K[i]).wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let majority = (a & b) ^ (a & c) ^ (b & c);
+            let temp2 = s0.wrapping_add(majority);
+            (h, g, f, e, d, c, b, a) = (g, f, e, d.wrapping_add(temp1), c, b, a, temp1.wrapping_add(temp2));
+        }
+        for (word, added) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *word = word.wrapping_add(added);
+        }
+    }
+    state.iter().map(|word| format!("{:08x}", word)).collect()
+}
+
+// Parse a memory size like Redis config does: a byte count with an optional unit, where
+// k/m/g are powers of 1000 and kb/mb/gb powers of 1024 (case-insensitive)
+pub fn parse_memory_size(value: &str) -> Option<usize> {
+    let value = value.to_lowercase();
+    let digits_end = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (number, unit) = value.split_at(digits_end);
+    let multiplier: usize = match unit {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    number.parse::<usize>().ok()?.checked_mul(multiplier)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_end_of_redis_message_counts_payload_bytes() {
+        // The payload's CRLFs are data, not line ends
+        let message = b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$8\r\none\r\ntwo\r\n";
+        assert_eq!(get_end_of_redis_message(message), Some(message.len()));
+
+        let mut two_messages = message.to_vec();
+        two_messages.extend_from_slice(b"*1\r\n$4\r\nPING\r\n");
+        assert_eq!(get_end_of_redis_message(&two_messages), Some(message.len()));
+        // A payload of nothing but CRLFs
+        assert_eq!(get_end_of_redis_message(b"*1\r\n$4\r\n\r\n\r\n\r\n"), Some(14));
+    }
+
+    #[test]
+    fn test_get_end_of_redis_message_waits_for_the_whole_message() {
+        let message = b"*2\r\n$4\r\nECHO\r\n$4\r\na\r\nb\r\n";
+        // A prefix that happens to end in a CRLF inside the payload is still incomplete
+        for end in 0..message.len() {
+            assert_eq!(get_end_of_redis_message(&message[..end]), None, "prefix of {} bytes", end);
+        }
+        assert_eq!(get_end_of_redis_message(message), Some(message.len()));
+    }
+
+    #[test]
+    fn test_get_end_of_redis_message_hands_over_malformed_messages() {
+        // A payload longer than its header says
+        let message = b"*1\r\n$2\r\nabc\r\n";
+        assert_eq!(get_end_of_redis_message(message), Some(message.len()));
+        let message = b"*x\r\n";
+        assert_eq!(get_end_of_redis_message(message), Some(message.len()));
+        // Inline commands end at the newline
+        assert_eq!(get_end_of_redis_message(b"PING\r\nPING\r\n"), Some(6));
+        assert_eq!(get_end_of_redis_message(b"PIN"), None);
+    }
+
+    fn matches(pattern: &str, string: &str) -> bool {
+        glob_match(pattern.as_bytes(), string.as_bytes())
+    }
+
+    #[test]
+    fn test_glob_match_wildcards() {
+        assert!(matches("*", ""));
+        assert!(matches("*", "anything"));
+        assert!(matches("user:*", "user:42"));
+        assert!(!matches("user:*", "session:42"));
+        assert!(matches("h?llo", "hello") && matches("h?llo", "hallo"));
+        assert!(!matches("h?llo", "hllo"));
+        assert!(matches("a**b", "axyzb"));
+        assert!(matches("*a*b*", "xxaxxbxx"));
+        assert!(!matches("*a*b*", "xxbxxaxx"));
+    }
+
+    #[test]
+    fn test_glob_match_character_classes() {
+        assert!(matches("h[ae]llo", "hello") && matches("h[ae]llo", "hallo"));
+        assert!(!matches("h[ae]llo", "hillo"));
+        assert!(matches("h[^e]llo", "hallo"));
+        assert!(!matches("h[^e]llo", "hello"));
+        assert!(matches("h[a-c]llo", "hbllo"));
+        assert!(!matches("h[a-c]llo", "hdllo"));
+        // A reversed range matches the same as the forward one
+        assert!(matches("h[c-a]llo", "hbllo"));
+        // A class needs a byte to match, even a negated one
+        assert!(!matches("h[^e]", "h"));
+        // An unterminated class runs to the end of the pattern
+        assert!(matches("h[ae", "ha"));
+    }
+
+    #[test]
+    fn test_glob_match_escapes() {
+        assert!(matches("a\\*b", "a*b"));
+        assert!(!matches("a\\*b", "axb"));
+        assert!(matches("what\\?", "what?"));
+        assert!(!matches("what\\?", "whats"));
+        assert!(matches("[\\]]", "]"));
+        assert!(matches("[a\\-z]", "-"));
+        assert!(!matches("[a\\-z]", "m"));
+    }
+
+    #[test]
+    fn test_crc64_matches_redis() {
+        // The check value Redis's own crc64 test uses
+        assert_eq!(crc64(b"123456789"), 0xe9c6d914c4b8d9ca);
+        assert_eq!(crc64(b""), 0);
+    }
+
+    #[test]
+    fn test_sha256_hex_matches_known_digests() {
+        assert_eq!(sha256_hex(b""), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(sha256_hex(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        // 56 bytes, so the padding spills into a second block
+        assert_eq!(
+            sha256_hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
     }
-}
\ No newline at end of file
+}
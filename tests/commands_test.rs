@@ -0,0 +1,321 @@
+mod common;
+
+use std::time::Duration;
+
+#[tokio::test]
+async fn test_del_counts_the_keys_it_removes() {
+    let server = common::server().await;
+    let mut client = server.client().await;
+
+    assert_eq!(client.command(&["SET", "a", "1"]).await, "+OK\r\n");
+    assert_eq!(client.command(&["SET", "b", "2"]).await, "+OK\r\n");
+    assert_eq!(client.command(&["DEL", "a", "b", "c"]).await, ":2\r\n");
+    assert_eq!(client.command(&["GET", "a"]).await, "$-1\r\n");
+    assert_eq!(client.command(&["GET", "b"]).await, "$-1\r\n");
+}
+
+#[tokio::test]
+async fn test_del_skips_expired_keys() {
+    let server = common::server_with(&[("hz", "1")]).await;
+    let mut client = server.client().await;
+
+    assert_eq!(client.command(&["SET", "short", "1", "PX", "20"]).await, "+OK\r\n");
+    assert_eq!(client.command(&["SET", "kept", "2"]).await, "+OK\r\n");
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(client.command(&["DEL", "short", "kept"]).await, ":1\r\n");
+}
+
+#[tokio::test]
+async fn test_numeric_strings_are_stored_verbatim() {
+    let server = common::server().await;
+    let mut client = server.client().await;
+
+    let big = "123456789012345678901234567890";
+    for value in ["007", "+5", big] {
+        assert_eq!(client.command(&["SET", "key", value]).await, "+OK\r\n");
+        assert_eq!(client.command(&["GET", "key"]).await, format!("${}\r\n{}\r\n", value.len(), value));
+    }
+}
+
+#[tokio::test]
+async fn test_decr_goes_below_zero() {
+    let server = common::server().await;
+    let mut client = server.client().await;
+
+    assert_eq!(client.command(&["DECR", "counter"]).await, ":-1\r\n");
+    assert_eq!(client.command(&["DECRBY", "counter", "5"]).await, ":-6\r\n");
+    assert_eq!(client.command(&["INCR", "counter"]).await, ":-5\r\n");
+}
+
+#[tokio::test]
+async fn test_incr_rejects_overflow() {
+    let server = common::server().await;
+    let mut client = server.client().await;
+
+    let max = i64::MAX.to_string();
+    assert_eq!(client.command(&["SET", "counter", &max]).await, "+OK\r\n");
+    assert_eq!(client.command(&["INCR", "counter"]).await, "-ERR increment or decrement would overflow\r\n");
+    assert_eq!(client.command(&["GET", "counter"]).await, format!("${}\r\n{}\r\n", max.len(), max));
+
+    let min = i64::MIN.to_string();
+    assert_eq!(client.command(&["SET", "counter", &min]).await, "+OK\r\n");
+    assert_eq!(client.command(&["DECR", "counter"]).await, "-ERR increment or decrement would overflow\r\n");
+}
+
+#[tokio::test]
+async fn test_move_takes_a_key_to_another_database() {
+    let server = common::server().await;
+    let mut client = server.client().await;
+
+    assert_eq!(client.command(&["SET", "key", "value"]).await, "+OK\r\n");
+    assert_eq!(client.command(&["MOVE", "key", "1"]).await, ":1\r\n");
+    assert_eq!(client.command(&["EXISTS", "key"]).await, ":0\r\n");
+    assert_eq!(client.command(&["SELECT", "1"]).await, "+OK\r\n");
+    assert_eq!(client.command(&["GET", "key"]).await, "$5\r\nvalue\r\n");
+
+    // Nothing moves onto a key the destination already has
+    assert_eq!(client.command(&["SELECT", "0"]).await, "+OK\r\n");
+    assert_eq!(client.command(&["SET", "key", "other"]).await, "+OK\r\n");
+    assert_eq!(client.command(&["MOVE", "key", "1"]).await, ":0\r\n");
+    assert_eq!(client.command(&["GET", "key"]).await, "$5\r\nother\r\n");
+    assert_eq!(client.command(&["MOVE", "missing", "1"]).await, ":0\r\n");
+    assert_eq!(client.command(&["MOVE", "key", "0"]).await, "-ERR source and destination objects are the same\r\n");
+}
+
+#[tokio::test]
+async fn test_swapdb_swaps_keyspaces_for_every_client() {
+    let server = common::server().await;
+    let mut client = server.client().await;
+    let mut other = server.client().await;
+
+    assert_eq!(client.command(&["SET", "key", "zero"]).await, "+OK\r\n");
+    assert_eq!(client.command(&["SELECT", "1"]).await, "+OK\r\n");
+    assert_eq!(client.command(&["SET", "key", "one"]).await, "+OK\r\n");
+    assert_eq!(client.command(&["SET", "only-in-one", "1"]).await, "+OK\r\n");
+
+    assert_eq!(client.command(&["SWAPDB", "0", "1"]).await, "+OK\r\n");
+    assert_eq!(client.command(&["GET", "key"]).await, "$4\r\nzero\r\n");
+    assert_eq!(client.command(&["EXISTS", "only-in-one"]).await, ":0\r\n");
+    // A client that never selected anything sees database 0, now the old database 1
+    assert_eq!(other.command(&["GET", "key"]).await, "$3\r\none\r\n");
+    assert_eq!(other.command(&["EXISTS", "only-in-one"]).await, ":1\r\n");
+}
+
+#[tokio::test]
+async fn test_values_may_contain_crlf() {
+    let server = common::server().await;
+    let mut client = server.client().await;
+
+    let value = "line one\r\nline two\r\n*1\r\n$4\r\nPING\r\n";
+    assert_eq!(client.command(&["SET", "multiline", value]).await, "+OK\r\n");
+    assert_eq!(client.command(&["GET", "multiline"]).await, format!("${}\r\n{}\r\n", value.len(), value));
+    assert_eq!(client.command(&["APPEND", "multiline", "\r\n"]).await, format!(":{}\r\n", value.len() + 2));
+    assert_eq!(client.command(&["PING"]).await, "+PONG\r\n");
+}
+
+#[tokio::test]
+async fn test_commands_split_across_reads() {
+    let server = common::server().await;
+    let mut client = server.client().await;
+
+    let set = common::encode(&[b"SET", b"key", b"split\r\nvalue"]);
+    for byte in &set {
+        client.send_raw(&[*byte]).await;
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    assert_eq!(client.read_reply().await, "+OK\r\n");
+
+    // The end of one command and the start of the next in the same read
+    let get = common::encode(&[b"GET", b"key"]);
+    let mut pipelined = set.clone();
+    pipelined.extend_from_slice(&get[..5]);
+    client.send_raw(&pipelined).await;
+    assert_eq!(client.read_reply().await, "+OK\r\n");
+    assert!(client.reply_within(Duration::from_millis(50)).await.is_none());
+    client.send_raw(&get[5..]).await;
+    assert_eq!(client.read_reply().await, "$12\r\nsplit\r\nvalue\r\n");
+}
+
+#[tokio::test]
+async fn test_allkeys_lru_evicts_beyond_maxmemory() {
+    let server = common::server_with(&[("maxmemory", "4kb"), ("maxmemory-policy", "allkeys-lru")]).await;
+    let mut client = server.client().await;
+
+    let value = "x".repeat(100);
+    for i in 0..200 {
+        assert_eq!(client.command(&["SET", &format!("key{}", i), &value]).await, "+OK\r\n");
+    }
+    let size: usize = client.command(&["DBSIZE"]).await.trim_start_matches(':').trim_end().parse().unwrap();
+    assert!(size > 0 && size < 100, "{} keys still stored", size);
+    // The newest write is kept and the oldest ones made room for it
+    assert_eq!(client.command(&["EXISTS", "key199"]).await, ":1\r\n");
+    assert_eq!(client.command(&["EXISTS", "key0", "key1", "key2", "key3", "key4"]).await, ":0\r\n");
+}
+
+#[tokio::test]
+async fn test_noeviction_refuses_writes_beyond_maxmemory() {
+    let server = common::server_with(&[("maxmemory", "4kb"), ("maxmemory-policy", "noeviction")]).await;
+    let mut client = server.client().await;
+
+    let value = "x".repeat(100);
+    let mut refused = None;
+    for i in 0..200 {
+        let reply = client.command(&["SET", &format!("key{}", i), &value]).await;
+        if reply != "+OK\r\n" {
+            refused = Some((i, reply));
+            break;
+        }
+    }
+    let (stored, reply) = refused.expect("Every write was accepted");
+    assert_eq!(reply, "-OOM command not allowed when used memory > 'maxmemory'.\r\n");
+    assert_eq!(client.command(&["DBSIZE"]).await, format!(":{}\r\n", stored));
+    // Deleting stays allowed so memory can be freed
+    assert_eq!(client.command(&["DEL", "key0"]).await, ":1\r\n");
+}
+
+#[tokio::test]
+async fn test_wrong_argument_counts_are_refused_before_running() {
+    let server = common::server().await;
+    let mut client = server.client().await;
+
+    assert_eq!(client.command(&["GET"]).await, "-ERR wrong number of arguments for 'get' command\r\n");
+    assert_eq!(client.command(&["SET", "key"]).await, "-ERR wrong number of arguments for 'set' command\r\n");
+    assert_eq!(client.command(&["STRLEN", "a", "b"]).await, "-ERR wrong number of arguments for 'strlen' command\r\n");
+    assert_eq!(client.command(&["NOSUCHCOMMAND"]).await, "-ERR unknown command\r\n");
+    // The connection survives them
+    assert_eq!(client.command(&["PING"]).await, "+PONG\r\n");
+}
+
+#[tokio::test]
+async fn test_xadd_rejects_malformed_ids() {
+    let server = common::server().await;
+    let mut client = server.client().await;
+
+    for id in ["abc-*", "-*", "1-*-*", "abc", "1-x"] {
+        assert_eq!(client.command(&["XADD", "stream", id, "field", "value"]).await, "-ERR invalid stream ID\r\n", "{}", id);
+    }
+    assert_eq!(client.command(&["EXISTS", "stream"]).await, ":0\r\n");
+    assert_eq!(client.command(&["XADD", "stream", "5-*", "field", "value"]).await, "$3\r\n5-0\r\n");
+}
+
+#[tokio::test]
+async fn test_incrbyfloat_formats_like_redis() {
+    let server = common::server().await;
+    let mut client = server.client().await;
+
+    assert_eq!(client.command(&["INCRBYFLOAT", "sum", "0.1"]).await, "$3\r\n0.1\r\n");
+    assert_eq!(client.command(&["INCRBYFLOAT", "sum", "0.2"]).await, "$3\r\n0.3\r\n");
+    assert_eq!(client.command(&["GET", "sum"]).await, "$3\r\n0.3\r\n");
+
+    assert_eq!(client.command(&["SET", "price", "10.50"]).await, "+OK\r\n");
+    assert_eq!(client.command(&["INCRBYFLOAT", "price", "0.1"]).await, "$4\r\n10.6\r\n");
+    assert_eq!(client.command(&["SET", "big", "5.0e3"]).await, "+OK\r\n");
+    assert_eq!(client.command(&["INCRBYFLOAT", "big", "2.0e2"]).await, "$4\r\n5200\r\n");
+    assert_eq!(client.command(&["INCRBYFLOAT", "big", "-5200.5"]).await, "$4\r\n-0.5\r\n");
+}
+
+#[tokio::test]
+async fn test_zadd_announces_only_changes() {
+    let server = common::server_with(&[("notify-keyspace-events", "Kz")]).await;
+    let mut subscriber = server.client().await;
+    let mut client = server.client().await;
+
+    subscriber.command(&["SUBSCRIBE", "__keyspace@0__:zset"]).await;
+    let event = "*3\r\n$7\r\nmessage\r\n$19\r\n__keyspace@0__:zset\r\n$4\r\nzadd\r\n";
+    assert_eq!(client.command(&["ZADD", "zset", "1", "a"]).await, ":1\r\n");
+    assert_eq!(subscriber.read_reply().await, event);
+    // The same score again changes nothing
+    assert_eq!(client.command(&["ZADD", "zset", "1", "a"]).await, ":0\r\n");
+    assert!(subscriber.reply_within(Duration::from_millis(50)).await.is_none());
+    // A new score for an existing member is an update, though nothing was added
+    assert_eq!(client.command(&["ZADD", "zset", "2", "a"]).await, ":0\r\n");
+    assert_eq!(subscriber.read_reply().await, event);
+    assert_eq!(client.command(&["ZSCORE", "zset", "a"]).await, "$1\r\n2\r\n");
+}
+
+#[tokio::test]
+async fn test_set_encoding_grows_from_intset_to_hashtable() {
+    let server = common::server().await;
+    let mut client = server.client().await;
+
+    assert_eq!(client.command(&["SADD", "set", "1", "2", "3"]).await, ":3\r\n");
+    assert_eq!(client.command(&["OBJECT", "ENCODING", "set"]).await, "$6\r\nintset\r\n");
+    assert_eq!(client.command(&["SADD", "set", "text"]).await, ":1\r\n");
+    assert_eq!(client.command(&["OBJECT", "ENCODING", "set"]).await, "$8\r\nlistpack\r\n");
+    for i in 0..125 {
+        client.command(&["SADD", "set", &format!("member{}", i)]).await;
+    }
+    assert_eq!(client.command(&["SCARD", "set"]).await, ":129\r\n");
+    assert_eq!(client.command(&["OBJECT", "ENCODING", "set"]).await, "$9\r\nhashtable\r\n");
+    // Shrinking again doesn't convert back
+    for i in 0..125 {
+        client.command(&["SREM", "set", &format!("member{}", i)]).await;
+    }
+    assert_eq!(client.command(&["OBJECT", "ENCODING", "set"]).await, "$9\r\nhashtable\r\n");
+}
+
+#[tokio::test]
+async fn test_zset_encoding_grows_to_skiplist() {
+    let server = common::server().await;
+    let mut client = server.client().await;
+
+    assert_eq!(client.command(&["ZADD", "zset", "1", "a", "2", "b"]).await, ":2\r\n");
+    assert_eq!(client.command(&["OBJECT", "ENCODING", "zset"]).await, "$8\r\nlistpack\r\n");
+    // A member longer than zset-max-listpack-value
+    assert_eq!(client.command(&["ZADD", "zset", "3", &"m".repeat(65)]).await, ":1\r\n");
+    assert_eq!(client.command(&["OBJECT", "ENCODING", "zset"]).await, "$8\r\nskiplist\r\n");
+}
+
+#[tokio::test]
+async fn test_encoding_thresholds_follow_config_set() {
+    let server = common::server().await;
+    let mut client = server.client().await;
+
+    assert_eq!(client.command(&["CONFIG", "SET", "zset-max-listpack-entries", "2"]).await, "+OK\r\n");
+    assert_eq!(client.command(&["CONFIG", "SET", "hash-max-listpack-entries", "1"]).await, "+OK\r\n");
+    assert_eq!(client.command(&["ZADD", "zset", "1", "a", "2", "b"]).await, ":2\r\n");
+    assert_eq!(client.command(&["OBJECT", "ENCODING", "zset"]).await, "$8\r\nlistpack\r\n");
+    assert_eq!(client.command(&["ZADD", "zset", "3", "c"]).await, ":1\r\n");
+    assert_eq!(client.command(&["OBJECT", "ENCODING", "zset"]).await, "$8\r\nskiplist\r\n");
+
+    assert_eq!(client.command(&["HSET", "hash", "one", "1"]).await, ":1\r\n");
+    assert_eq!(client.command(&["OBJECT", "ENCODING", "hash"]).await, "$8\r\nlistpack\r\n");
+    assert_eq!(client.command(&["HSET", "hash", "two", "2"]).await, ":1\r\n");
+    assert_eq!(client.command(&["OBJECT", "ENCODING", "hash"]).await, "$9\r\nhashtable\r\n");
+}
+
+// The cursor and keys of a SCAN reply
+fn parse_scan_reply(reply: &str) -> (String, Vec<String>) {
+    let lines: Vec<&str> = reply.split("\r\n").collect();
+    let keys = lines[4..].iter().skip(1).step_by(2).filter(|line| !line.is_empty()).map(|key| key.to_string()).collect();
+    (lines[2].to_string(), keys)
+}
+
+#[tokio::test]
+async fn test_scan_returns_every_key_kept_while_others_change() {
+    let server = common::server().await;
+    let mut client = server.client().await;
+
+    for i in 0..100 {
+        assert_eq!(client.command(&["SET", &format!("key{}", i), "1"]).await, "+OK\r\n");
+    }
+    let mut seen = std::collections::HashSet::new();
+    let mut deleted = std::collections::HashSet::new();
+    let mut cursor = "0".to_string();
+    for round in 0.. {
+        let (next, keys) = parse_scan_reply(&client.command(&["SCAN", &cursor, "COUNT", "10"]).await);
+        seen.extend(keys);
+        if next == "0" {
+            break;
+        }
+        cursor = next;
+        // Between calls delete a key from each end of the keyspace and add new ones
+        for key in [format!("key{}", round), format!("key{}", 99 - round)] {
+            client.command(&["DEL", &key]).await;
+            deleted.insert(key);
+        }
+        client.command(&["SET", &format!("new{}", round), "1"]).await;
+    }
+    let missing: Vec<String> = (0..100).map(|i| format!("key{}", i)).filter(|key| !deleted.contains(key) && !seen.contains(key)).collect();
+    assert!(missing.is_empty(), "SCAN never returned {:?}", missing);
+}
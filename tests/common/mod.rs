@@ -0,0 +1,142 @@
+// Helpers shared by the integration tests: servers on free ports and a minimal RESP client.
+// Each test binary uses some of them.
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+
+use redis_starter_rust::start_test_server_with_config;
+
+// How long a test waits for a reply before failing rather than hanging
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// A port nothing is listening on, picked by the OS
+pub fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+// A running server that is stopped when dropped
+pub struct TestServer {
+    pub port: u16,
+    task: JoinHandle<()>,
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl TestServer {
+    pub async fn client(&self) -> Client {
+        Client::connect(self.port).await
+    }
+}
+
+// A server with the default config
+pub async fn server() -> TestServer {
+    server_with(&[]).await
+}
+
+// A server started as if with `--name value` for each pair, on a free port
+pub async fn server_with(config: &[(&str, &str)]) -> TestServer {
+    let port = free_port();
+    let mut config_map: HashMap<String, String> = config.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+    config_map.insert("port".to_string(), port.to_string());
+    TestServer { port, task: start_test_server_with_config(config_map).await }
+}
+
+// Encode a command as a RESP array of bulk strings
+pub fn encode(args: &[&[u8]]) -> Vec<u8> {
+    let mut message = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        message.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        message.extend_from_slice(arg);
+        message.extend_from_slice(b"\r\n");
+    }
+    message
+}
+
+pub struct Client {
+    stream: TcpStream,
+    buffer: Vec<u8>, // Bytes read past the last reply
+}
+
+impl Client {
+    pub async fn connect(port: u16) -> Self {
+        let stream = TcpStream::connect(("127.0.0.1", port)).await.expect("Could not connect to the test server");
+        Client { stream, buffer: Vec::new() }
+    }
+
+    // Send a command and return its reply, as text
+    pub async fn command(&mut self, args: &[&str]) -> String {
+        let args: Vec<&[u8]> = args.iter().map(|arg| arg.as_bytes()).collect();
+        self.command_bytes(&args).await
+    }
+
+    // Send a command with binary arguments and return its reply
+    pub async fn command_bytes(&mut self, args: &[&[u8]]) -> String {
+        self.send_raw(&encode(args)).await;
+        self.read_reply().await
+    }
+
+    pub async fn send_raw(&mut self, bytes: &[u8]) {
+        self.stream.write_all(bytes).await.expect("Could not write to the test server");
+    }
+
+    // Wait for the next complete reply
+    pub async fn read_reply(&mut self) -> String {
+        loop {
+            if let Some(length) = reply_length(&self.buffer) {
+                let reply: Vec<u8> = self.buffer.drain(..length).collect();
+                return String::from_utf8_lossy(&reply).into_owned();
+            }
+            let mut chunk = [0; 4096];
+            let read = timeout(REPLY_TIMEOUT, self.stream.read(&mut chunk))
+                .await
+                .expect("Timed out waiting for a reply")
+                .expect("Could not read from the test server");
+            assert!(read > 0, "The server closed the connection");
+            self.buffer.extend_from_slice(&chunk[..read]);
+        }
+    }
+
+    // Whether a reply arrives within `wait`, for checking that a command blocks
+    pub async fn reply_within(&mut self, wait: Duration) -> Option<String> {
+        timeout(wait, self.read_reply()).await.ok()
+    }
+
+    // Whether the server closes the connection within `wait` without replying
+    pub async fn closed_within(&mut self, wait: Duration) -> bool {
+        let mut chunk = [0; 4096];
+        self.buffer.is_empty() && matches!(timeout(wait, self.stream.read(&mut chunk)).await, Ok(Ok(0) | Err(_)))
+    }
+}
+
+// The length of the RESP2/RESP3 reply at the start of `buffer`, or None if it isn't complete
+fn reply_length(buffer: &[u8]) -> Option<usize> {
+    let line_end = buffer.windows(2).position(|window| window == b"\r\n")?;
+    let header = std::str::from_utf8(&buffer[1..line_end]).ok()?;
+    let mut length = line_end + 2;
+    match buffer[0] {
+        b'$' | b'=' | b'!' => {
+            let size: i64 = header.parse().ok()?;
+            if size >= 0 {
+                length += size as usize + 2;
+            }
+        }
+        kind @ (b'*' | b'%' | b'~' | b'>' | b'|') => {
+            let count: i64 = header.parse().ok()?;
+            let elements = if matches!(kind, b'%' | b'|') { count * 2 } else { count };
+            for _ in 0..elements.max(0) {
+                length += reply_length(&buffer[length..])?;
+            }
+        }
+        _ => {}
+    }
+    (buffer.len() >= length).then_some(length)
+}
@@ -0,0 +1,94 @@
+mod common;
+
+const HELLO_FIELDS: &str = "$6\r\nserver\r\n$5\r\nredis\r\n$7\r\nversion\r\n$5\r\n7.2.0\r\n";
+const HELLO_TAIL: &str = "$4\r\nmode\r\n$10\r\nstandalone\r\n$4\r\nrole\r\n$6\r\nmaster\r\n$7\r\nmodules\r\n*0\r\n";
+
+#[tokio::test]
+async fn test_hello_switches_the_reply_protocol() {
+    let server = common::server().await;
+    let mut client = server.client().await;
+
+    assert_eq!(client.command(&["HSET", "hash", "field", "value"]).await, ":1\r\n");
+    assert_eq!(client.command(&["HGETALL", "hash"]).await, "*2\r\n$5\r\nfield\r\n$5\r\nvalue\r\n");
+
+    // The reply to HELLO 3 is already a map
+    assert_eq!(client.command(&["HELLO", "3"]).await, format!("%6\r\n{}$5\r\nproto\r\n:3\r\n{}", HELLO_FIELDS, HELLO_TAIL));
+    assert_eq!(client.command(&["HGETALL", "hash"]).await, "%1\r\n$5\r\nfield\r\n$5\r\nvalue\r\n");
+
+    assert_eq!(client.command(&["HELLO", "2"]).await, format!("*12\r\n{}$5\r\nproto\r\n:2\r\n{}", HELLO_FIELDS, HELLO_TAIL));
+    assert_eq!(client.command(&["HGETALL", "hash"]).await, "*2\r\n$5\r\nfield\r\n$5\r\nvalue\r\n");
+}
+
+#[tokio::test]
+async fn test_hello_refuses_unknown_protocols() {
+    let server = common::server().await;
+    let mut client = server.client().await;
+
+    assert_eq!(client.command(&["HELLO", "4"]).await, "-NOPROTO unsupported protocol version\r\n");
+    assert_eq!(client.command(&["HELLO", "1"]).await, "-NOPROTO unsupported protocol version\r\n");
+    assert_eq!(client.command(&["HELLO", "three"]).await, "-ERR Protocol version is not an integer or out of range\r\n");
+    // The connection keeps speaking RESP2
+    assert_eq!(client.command(&["HSET", "hash", "field", "value"]).await, ":1\r\n");
+    assert_eq!(client.command(&["HGETALL", "hash"]).await, "*2\r\n$5\r\nfield\r\n$5\r\nvalue\r\n");
+}
+
+#[tokio::test]
+async fn test_requirepass_refuses_commands_until_auth() {
+    let server = common::server_with(&[("requirepass", "secret")]).await;
+    let mut client = server.client().await;
+
+    assert_eq!(client.command(&["GET", "key"]).await, "-NOAUTH Authentication required\r\n");
+    assert_eq!(client.command(&["SET", "key", "value"]).await, "-NOAUTH Authentication required\r\n");
+    // PING, AUTH and HELLO are let through
+    assert_eq!(client.command(&["PING"]).await, "+PONG\r\n");
+    let wrongpass = "-WRONGPASS invalid username-password pair or user is disabled.\r\n";
+    assert_eq!(client.command(&["AUTH", "guess"]).await, wrongpass);
+    assert_eq!(client.command(&["AUTH", "someone", "secret"]).await, wrongpass);
+    assert_eq!(client.command(&["GET", "key"]).await, "-NOAUTH Authentication required\r\n");
+
+    assert_eq!(client.command(&["AUTH", "secret"]).await, "+OK\r\n");
+    assert_eq!(client.command(&["SET", "key", "value"]).await, "+OK\r\n");
+    assert_eq!(client.command(&["ACL", "WHOAMI"]).await, "$7\r\ndefault\r\n");
+
+    // The default user can be named, and HELLO can authenticate on its own
+    let mut named = server.client().await;
+    assert_eq!(named.command(&["AUTH", "default", "secret"]).await, "+OK\r\n");
+    assert_eq!(named.command(&["GET", "key"]).await, "$5\r\nvalue\r\n");
+    let mut hello = server.client().await;
+    assert_eq!(hello.command(&["HELLO", "3", "AUTH", "default", "wrong"]).await, wrongpass);
+    assert_eq!(
+        hello.command(&["HELLO", "3", "AUTH", "default", "secret"]).await,
+        format!("%6\r\n{}$5\r\nproto\r\n:3\r\n{}", HELLO_FIELDS, HELLO_TAIL)
+    );
+    assert_eq!(hello.command(&["GET", "key"]).await, "$5\r\nvalue\r\n");
+}
+
+#[tokio::test]
+async fn test_auth_without_requirepass() {
+    let server = common::server().await;
+    let mut client = server.client().await;
+
+    assert_eq!(
+        client.command(&["AUTH", "secret"]).await,
+        "-ERR AUTH <password> called without any password configured for the default user. Are you sure your configuration is correct?\r\n"
+    );
+    // The default user takes any password, but no other user exists
+    assert_eq!(client.command(&["AUTH", "default", "anything"]).await, "+OK\r\n");
+    assert_eq!(client.command(&["AUTH", "someone", "anything"]).await, "-WRONGPASS invalid username-password pair or user is disabled.\r\n");
+}
+
+#[tokio::test]
+async fn test_acl_list_shows_the_hashed_password() {
+    let server = common::server().await;
+    let mut client = server.client().await;
+
+    assert_eq!(client.command(&["ACL", "LIST"]).await, "*1\r\n$51\r\nuser default on nopass sanitize-payload ~* &* +@all\r\n");
+    assert_eq!(client.command(&["CONFIG", "SET", "requirepass", "secret"]).await, "+OK\r\n");
+    assert_eq!(client.command(&["AUTH", "secret"]).await, "+OK\r\n");
+    // The SHA-256 of "secret", never the password itself
+    assert_eq!(
+        client.command(&["ACL", "LIST"]).await,
+        "*1\r\n$110\r\nuser default on #2bb80d537b1da3e38bd30361aa855686bde0eacd7162fef6a25fe97bf527a25b sanitize-payload ~* &* +@all\r\n"
+    );
+    assert_eq!(client.command(&["ACL", "LIST", "extra"]).await, "-ERR wrong number of arguments for 'acl|list' command\r\n");
+}
@@ -0,0 +1,44 @@
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+use redis_starter_rust::database::{RedisDatabase, RedisValue};
+use redis_starter_rust::run_active_expiration;
+
+#[tokio::test]
+async fn test_active_expiration_removes_keys_nobody_reads() {
+    let db = Arc::new(RwLock::new(RedisDatabase::new()));
+    {
+        let mut db = db.write().await;
+        db.insert("short".to_string(), RedisValue::new("1".to_string(), Some(20)));
+        db.insert("kept".to_string(), RedisValue::new("2".to_string(), None));
+    }
+    let expiration = tokio::spawn(run_active_expiration(Arc::clone(&db), 100));
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    {
+        let db = db.read().await;
+        assert!(!db.data().contains_key("short"));
+        assert!(db.data().contains_key("kept"));
+    }
+    expiration.abort();
+}
+
+#[tokio::test]
+async fn test_server_expires_keys_in_the_background() {
+    let server = common::server_with(&[("hz", "100"), ("notify-keyspace-events", "Ex")]).await;
+    let mut subscriber = server.client().await;
+    let mut client = server.client().await;
+
+    subscriber.command(&["SUBSCRIBE", "__keyevent@0__:expired"]).await;
+    assert_eq!(client.command(&["SET", "short", "1", "PX", "20"]).await, "+OK\r\n");
+    // Only the background cycle can remove the key, as nothing reads it
+    let message = subscriber.reply_within(Duration::from_secs(2)).await;
+    assert_eq!(
+        message.as_deref(),
+        Some("*3\r\n$7\r\nmessage\r\n$22\r\n__keyevent@0__:expired\r\n$5\r\nshort\r\n")
+    );
+    assert_eq!(client.command(&["DBSIZE"]).await, ":0\r\n");
+}
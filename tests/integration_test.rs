@@ -0,0 +1,57 @@
+mod common;
+
+use std::time::Duration;
+use tokio::time::timeout;
+
+use common::{free_port, Client};
+use redis_starter_rust::start_test_server;
+
+#[tokio::test]
+async fn test_server_accepts_connections_once_started() {
+    let port = free_port();
+    let server = start_test_server(port).await;
+
+    let mut client = Client::connect(port).await;
+    assert_eq!(client.command(&["PING"]).await, "+PONG\r\n");
+    assert_eq!(client.command(&["SET", "key", "value"]).await, "+OK\r\n");
+    assert_eq!(client.command(&["GET", "key"]).await, "$5\r\nvalue\r\n");
+
+    server.abort();
+    let _ = server.await;
+    assert!(tokio::net::TcpStream::connect(("127.0.0.1", port)).await.is_err());
+}
+
+#[tokio::test]
+async fn test_servers_run_side_by_side() {
+    let first = common::server().await;
+    let second = common::server().await;
+
+    let mut first_client = first.client().await;
+    let mut second_client = second.client().await;
+    assert_eq!(first_client.command(&["SET", "key", "first"]).await, "+OK\r\n");
+    assert_eq!(second_client.command(&["GET", "key"]).await, "$-1\r\n");
+}
+
+#[tokio::test]
+async fn test_shutdown_stops_the_server_without_a_reply() {
+    let port = free_port();
+    let server = start_test_server(port).await;
+
+    let mut client = Client::connect(port).await;
+    client.send_raw(&common::encode(&[b"SHUTDOWN", b"NOSAVE"])).await;
+    assert!(client.closed_within(Duration::from_secs(5)).await);
+    timeout(Duration::from_secs(5), server).await.expect("The server kept running").unwrap();
+    assert!(tokio::net::TcpStream::connect(("127.0.0.1", port)).await.is_err());
+}
+
+#[tokio::test]
+async fn test_shutdown_queued_in_a_transaction_runs_on_exec() {
+    let port = free_port();
+    let server = start_test_server(port).await;
+
+    let mut client = Client::connect(port).await;
+    assert_eq!(client.command(&["MULTI"]).await, "+OK\r\n");
+    assert_eq!(client.command(&["SHUTDOWN", "NOSAVE"]).await, "+QUEUED\r\n");
+    client.send_raw(&common::encode(&[b"EXEC"])).await;
+    timeout(Duration::from_secs(5), server).await.expect("The server kept running").unwrap();
+}
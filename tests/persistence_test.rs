@@ -0,0 +1,118 @@
+mod common;
+
+use std::path::PathBuf;
+
+use redis_starter_rust::database::RedisDatabase;
+use redis_starter_rust::rdb_parser::parse_rdb_file;
+
+// Not valid UTF-8, so it only survives a round trip kept as bytes
+const BINARY: &[u8] = b"\xff\x00\xfe\r\n\x80";
+
+// An empty directory of its own for each test's RDB file
+fn rdb_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("redis-rust-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[tokio::test]
+async fn test_save_writes_a_file_the_parser_reads_back() {
+    let dir = rdb_dir("save");
+    let dir_arg = dir.to_str().unwrap();
+    let config = [("dir", dir_arg), ("dbfilename", "dump.rdb")];
+
+    let server = common::server_with(&config).await;
+    let mut client = server.client().await;
+    assert_eq!(client.command(&["SET", "plain", "value"]).await, "+OK\r\n");
+    assert_eq!(client.command(&["SET", "expiring", "soon", "PX", "100000"]).await, "+OK\r\n");
+    assert_eq!(client.command_bytes(&[b"SET", b"binary", BINARY]).await, "+OK\r\n");
+    assert_eq!(client.command(&["SAVE"]).await, "+OK\r\n");
+    drop(server);
+
+    let mut db = RedisDatabase::new();
+    parse_rdb_file(dir.join("dump.rdb").to_str().unwrap(), &mut db, true).unwrap();
+    assert_eq!(db.data().len(), 3);
+    assert_eq!(db.get("plain").unwrap().get_value().string_bytes(), Some(b"value".to_vec()));
+    assert_eq!(db.get("expiring").unwrap().get_value().string_bytes(), Some(b"soon".to_vec()));
+    assert_eq!(db.get("binary").unwrap().get_value().string_bytes(), Some(BINARY.to_vec()));
+
+    // A restarted server loads the same file
+    let restarted = common::server_with(&config).await;
+    let mut client = restarted.client().await;
+    assert_eq!(client.command(&["GET", "plain"]).await, "$5\r\nvalue\r\n");
+    assert_eq!(client.command(&["GET", "expiring"]).await, "$4\r\nsoon\r\n");
+    assert_eq!(client.command(&["STRLEN", "binary"]).await, format!(":{}\r\n", BINARY.len()));
+    let ttl = client.command(&["PTTL", "expiring"]).await;
+    let ttl: i64 = ttl.trim_start_matches(':').trim_end().parse().unwrap();
+    assert!(ttl > 0 && ttl <= 100000, "unexpected PTTL {}", ttl);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_save_keeps_every_type() {
+    let dir = rdb_dir("types");
+    let dir_arg = dir.to_str().unwrap();
+    let config = [("dir", dir_arg), ("dbfilename", "dump.rdb")];
+
+    let server = common::server_with(&config).await;
+    let mut client = server.client().await;
+    assert_eq!(client.command(&["INCRBY", "counter", "42"]).await, ":42\r\n");
+    assert_eq!(client.command(&["RPUSH", "list", "a", "b", "c"]).await, ":3\r\n");
+    assert_eq!(client.command(&["SADD", "set", "x", "y"]).await, ":2\r\n");
+    assert_eq!(client.command(&["HSET", "hash", "field", "value"]).await, ":1\r\n");
+    assert_eq!(client.command(&["ZADD", "zset", "1.5", "low", "-inf", "lowest", "2", "high"]).await, ":3\r\n");
+    assert_eq!(client.command(&["EXPIRE", "list", "1000"]).await, ":1\r\n");
+    assert_eq!(client.command(&["SAVE"]).await, "+OK\r\n");
+    drop(server);
+    // The temporary file was renamed into place
+    let files: Vec<_> = std::fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+    assert_eq!(files, ["dump.rdb"]);
+
+    let restarted = common::server_with(&config).await;
+    let mut client = restarted.client().await;
+    assert_eq!(client.command(&["INCR", "counter"]).await, ":43\r\n");
+    assert_eq!(client.command(&["LRANGE", "list", "0", "-1"]).await, "*3\r\n$1\r\na\r\n$1\r\nb\r\n$1\r\nc\r\n");
+    assert_eq!(client.command(&["TYPE", "list"]).await, "+list\r\n");
+    assert_ne!(client.command(&["TTL", "list"]).await, ":-1\r\n");
+    assert_eq!(client.command(&["SCARD", "set"]).await, ":2\r\n");
+    assert_eq!(client.command(&["SISMEMBER", "set", "y"]).await, ":1\r\n");
+    assert_eq!(client.command(&["HGET", "hash", "field"]).await, "$5\r\nvalue\r\n");
+    assert_eq!(
+        client.command(&["ZRANGE", "zset", "0", "-1", "WITHSCORES"]).await,
+        "*6\r\n$6\r\nlowest\r\n$4\r\n-inf\r\n$3\r\nlow\r\n$3\r\n1.5\r\n$4\r\nhigh\r\n$1\r\n2\r\n"
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_save_keeps_streams_and_their_last_id() {
+    let dir = rdb_dir("streams");
+    let dir_arg = dir.to_str().unwrap();
+    let config = [("dir", dir_arg), ("dbfilename", "dump.rdb")];
+
+    let server = common::server_with(&config).await;
+    let mut client = server.client().await;
+    assert_eq!(client.command(&["XADD", "stream", "1-1", "field", "value"]).await, "$3\r\n1-1\r\n");
+    assert_eq!(client.command(&["XADD", "stream", "2-1", "other", "thing", "more", "fields"]).await, "$3\r\n2-1\r\n");
+    assert_eq!(client.command(&["XADD", "stream", "3-1", "field", "gone"]).await, "$3\r\n3-1\r\n");
+    assert_eq!(client.command(&["XDEL", "stream", "3-1"]).await, ":1\r\n");
+    assert_eq!(client.command(&["SAVE"]).await, "+OK\r\n");
+    drop(server);
+
+    let restarted = common::server_with(&config).await;
+    let mut client = restarted.client().await;
+    let range = client.command(&["XRANGE", "stream", "-", "+"]).await;
+    assert!(range.starts_with("*2\r\n*2\r\n$3\r\n1-1\r\n*2\r\n$5\r\nfield\r\n$5\r\nvalue\r\n*2\r\n$3\r\n2-1\r\n*4\r\n"), "{:?}", range);
+    assert!(range.contains("$5\r\nother\r\n$5\r\nthing\r\n") && range.contains("$4\r\nmore\r\n$6\r\nfields\r\n"), "{:?}", range);
+    // The deleted entry's ID is still used up
+    assert_eq!(
+        client.command(&["XADD", "stream", "3-1", "field", "again"]).await,
+        "-ERR The ID specified in XADD is equal or smaller than the target stream top item\r\n"
+    );
+    assert_eq!(client.command(&["XADD", "stream", "3-*", "field", "again"]).await, "$3\r\n3-2\r\n");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
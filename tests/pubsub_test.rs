@@ -0,0 +1,82 @@
+mod common;
+
+// The frame a subscriber gets for `payload` published to `channel`
+fn message(channel: &str, payload: &str) -> String {
+    format!("*3\r\n$7\r\nmessage\r\n${}\r\n{}\r\n${}\r\n{}\r\n", channel.len(), channel, payload.len(), payload)
+}
+
+// The confirmation of a (un)subscription, with the subscription count after it
+fn confirmation(kind: &str, channel: &str, count: usize) -> String {
+    format!("*3\r\n${}\r\n{}\r\n${}\r\n{}\r\n:{}\r\n", kind.len(), kind, channel.len(), channel, count)
+}
+
+#[tokio::test]
+async fn test_publish_reaches_subscribers_until_they_unsubscribe() {
+    let server = common::server().await;
+    let mut subscriber = server.client().await;
+    let mut publisher = server.client().await;
+
+    assert_eq!(subscriber.command(&["SUBSCRIBE", "news", "sport"]).await, confirmation("subscribe", "news", 1));
+    assert_eq!(subscriber.read_reply().await, confirmation("subscribe", "sport", 2));
+
+    assert_eq!(publisher.command(&["PUBLISH", "news", "hello"]).await, ":1\r\n");
+    assert_eq!(subscriber.read_reply().await, message("news", "hello"));
+    assert_eq!(publisher.command(&["PUBLISH", "weather", "rain"]).await, ":0\r\n");
+
+    assert_eq!(subscriber.command(&["UNSUBSCRIBE", "news"]).await, confirmation("unsubscribe", "news", 1));
+    assert_eq!(publisher.command(&["PUBLISH", "news", "missed"]).await, ":0\r\n");
+    assert_eq!(publisher.command(&["PUBLISH", "sport", "goal"]).await, ":1\r\n");
+    assert_eq!(subscriber.read_reply().await, message("sport", "goal"));
+
+    // Without arguments it leaves every channel, and the connection runs commands again
+    assert_eq!(subscriber.command(&["UNSUBSCRIBE"]).await, confirmation("unsubscribe", "sport", 0));
+    assert_eq!(subscriber.command(&["UNSUBSCRIBE"]).await, "*3\r\n$11\r\nunsubscribe\r\n$-1\r\n:0\r\n");
+    assert_eq!(subscriber.command(&["GET", "key"]).await, "$-1\r\n");
+}
+
+#[tokio::test]
+async fn test_psubscribe_matches_channels_by_pattern() {
+    let server = common::server().await;
+    let mut subscriber = server.client().await;
+    let mut publisher = server.client().await;
+
+    assert_eq!(subscriber.command(&["PSUBSCRIBE", "news.*"]).await, confirmation("psubscribe", "news.*", 1));
+    assert_eq!(subscriber.command(&["SUBSCRIBE", "news.tech"]).await, confirmation("subscribe", "news.tech", 2));
+
+    // Delivered once per matching subscription
+    assert_eq!(publisher.command(&["PUBLISH", "news.tech", "chips"]).await, ":2\r\n");
+    let replies = [subscriber.read_reply().await, subscriber.read_reply().await];
+    assert!(replies.contains(&message("news.tech", "chips")), "{:?}", replies);
+    let pmessage = "*4\r\n$8\r\npmessage\r\n$6\r\nnews.*\r\n$9\r\nnews.tech\r\n$5\r\nchips\r\n";
+    assert!(replies.contains(&pmessage.to_string()), "{:?}", replies);
+    assert_eq!(publisher.command(&["PUBLISH", "sport", "goal"]).await, ":0\r\n");
+
+    assert_eq!(subscriber.command(&["PUNSUBSCRIBE", "news.*"]).await, confirmation("punsubscribe", "news.*", 1));
+    assert_eq!(publisher.command(&["PUBLISH", "news.tech", "more"]).await, ":1\r\n");
+}
+
+#[tokio::test]
+async fn test_subscribed_connections_only_manage_subscriptions_under_resp2() {
+    let server = common::server().await;
+    let mut subscriber = server.client().await;
+
+    assert_eq!(subscriber.command(&["SUBSCRIBE", "news"]).await, confirmation("subscribe", "news", 1));
+    assert_eq!(
+        subscriber.command(&["GET", "key"]).await,
+        "-ERR Can't execute 'get': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context\r\n"
+    );
+    assert_eq!(subscriber.command(&["PING"]).await, "*2\r\n$4\r\npong\r\n$0\r\n\r\n");
+}
+
+#[tokio::test]
+async fn test_resp3_subscribers_get_push_frames_and_run_any_command() {
+    let server = common::server().await;
+    let mut subscriber = server.client().await;
+    let mut publisher = server.client().await;
+
+    subscriber.command(&["HELLO", "3"]).await;
+    assert_eq!(subscriber.command(&["SUBSCRIBE", "news"]).await, ">3\r\n$9\r\nsubscribe\r\n$4\r\nnews\r\n:1\r\n");
+    assert_eq!(publisher.command(&["PUBLISH", "news", "hello"]).await, ":1\r\n");
+    assert_eq!(subscriber.read_reply().await, ">3\r\n$7\r\nmessage\r\n$4\r\nnews\r\n$5\r\nhello\r\n");
+    assert_eq!(subscriber.command(&["GET", "key"]).await, "$-1\r\n");
+}
@@ -0,0 +1,196 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{encode, Client, TestServer};
+
+// How long a test waits for the replica to catch up before failing
+const SYNC_TIMEOUT: Duration = Duration::from_secs(5);
+
+// A replica of `master`, returned once the master counts it as connected
+async fn replica_of(master: &TestServer) -> TestServer {
+    let replicaof = format!("127.0.0.1 {}", master.port);
+    let replica = common::server_with(&[("replicaof", &replicaof)]).await;
+    poll_until(&mut master.client().await, &["WAIT", "1", "100"], ":1\r\n").await;
+    replica
+}
+
+// Send `args` until the reply is `expected`, failing the test after SYNC_TIMEOUT
+async fn poll_until(client: &mut Client, args: &[&str], expected: &str) {
+    let deadline = tokio::time::Instant::now() + SYNC_TIMEOUT;
+    loop {
+        let reply = client.command(args).await;
+        if reply == expected {
+            return;
+        }
+        assert!(tokio::time::Instant::now() < deadline, "{:?} still replies {:?}, not {:?}", args, reply, expected);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+// A byte count from INFO replication, which shows them as "<count> bytes"
+async fn replication_field(client: &mut Client, field: &str) -> usize {
+    let info = client.command(&["INFO", "replication"]).await;
+    let prefix = format!("{}:", field);
+    let line = info.split("\r\n").find(|line| line.starts_with(&prefix)).unwrap_or_else(|| panic!("No {} in INFO", field));
+    line[prefix.len()..].trim_end_matches(" bytes").parse().unwrap()
+}
+
+#[tokio::test]
+async fn test_replica_offset_advances_by_the_bytes_it_applies() {
+    let master = common::server().await;
+    let replica = replica_of(&master).await;
+    let mut master_client = master.client().await;
+    let mut replica_client = replica.client().await;
+
+    // The first write after the sync also carries a SELECT, so measure the second
+    assert_eq!(master_client.command(&["SET", "first", "1"]).await, "+OK\r\n");
+    poll_until(&mut replica_client, &["GET", "first"], "$1\r\n1\r\n").await;
+    let before = replication_field(&mut replica_client, "slave_repl_offset").await;
+
+    assert_eq!(master_client.command(&["SET", "second", "22"]).await, "+OK\r\n");
+    poll_until(&mut replica_client, &["GET", "second"], "$2\r\n22\r\n").await;
+    let after = replication_field(&mut replica_client, "slave_repl_offset").await;
+    assert_eq!(after - before, encode(&[b"SET", b"second", b"22"]).len());
+}
+
+#[tokio::test]
+async fn test_master_and_replica_offsets_match() {
+    let master = common::server().await;
+    let replica = replica_of(&master).await;
+    let mut master_client = master.client().await;
+    let mut replica_client = replica.client().await;
+
+    for i in 0..20 {
+        assert_eq!(master_client.command(&["SET", &format!("key{}", i), &"x".repeat(i)]).await, "+OK\r\n");
+    }
+    // Reads and replies to them aren't part of the replication stream
+    assert_eq!(master_client.command(&["GET", "key1"]).await, "$1\r\nx\r\n");
+    assert_eq!(master_client.command(&["WAIT", "1", "1000"]).await, ":1\r\n");
+
+    let master_offset = replication_field(&mut master_client, "master_repl_offset").await;
+    assert!(master_offset > 0);
+    let deadline = tokio::time::Instant::now() + SYNC_TIMEOUT;
+    loop {
+        let replica_offset = replication_field(&mut replica_client, "slave_repl_offset").await;
+        if replica_offset == master_offset {
+            break;
+        }
+        assert!(replica_offset < master_offset, "The replica is ahead: {} > {}", replica_offset, master_offset);
+        assert!(tokio::time::Instant::now() < deadline, "The replica stopped at {} of {}", replica_offset, master_offset);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+#[tokio::test]
+async fn test_master_expirations_reach_the_replica() {
+    let master = common::server_with(&[("hz", "100")]).await;
+    let replica = replica_of(&master).await;
+    let mut master_client = master.client().await;
+    let mut replica_client = replica.client().await;
+
+    assert_eq!(master_client.command(&["SET", "short", "1", "PX", "300"]).await, "+OK\r\n");
+    poll_until(&mut replica_client, &["EXISTS", "short"], ":1\r\n").await;
+    let before = replication_field(&mut replica_client, "slave_repl_offset").await;
+
+    // Nothing reads the key, so the DEL comes from the master's background expiration
+    let expected = before + encode(&[b"DEL", b"short"]).len();
+    let deadline = tokio::time::Instant::now() + SYNC_TIMEOUT;
+    while replication_field(&mut replica_client, "slave_repl_offset").await < expected {
+        assert!(tokio::time::Instant::now() < deadline, "The replica never received a DEL");
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(replication_field(&mut replica_client, "slave_repl_offset").await, expected);
+    assert_eq!(replica_client.command(&["GET", "short"]).await, "$-1\r\n");
+    assert_eq!(master_client.command(&["EXISTS", "short"]).await, ":0\r\n");
+}
+
+#[tokio::test]
+async fn test_xadd_reaches_the_replica() {
+    let master = common::server().await;
+    let replica = replica_of(&master).await;
+    let mut master_client = master.client().await;
+    let mut replica_client = replica.client().await;
+
+    assert_eq!(master_client.command(&["XADD", "stream", "1-1", "field", "value"]).await, "$3\r\n1-1\r\n");
+    assert_eq!(master_client.command(&["XADD", "stream", "2-1", "other", "thing"]).await, "$3\r\n2-1\r\n");
+    poll_until(&mut replica_client, &["XLEN", "stream"], ":2\r\n").await;
+    assert_eq!(
+        replica_client.command(&["XRANGE", "stream", "-", "+"]).await,
+        "*2\r\n*2\r\n$3\r\n1-1\r\n*2\r\n$5\r\nfield\r\n$5\r\nvalue\r\n*2\r\n$3\r\n2-1\r\n*2\r\n$5\r\nother\r\n$5\r\nthing\r\n"
+    );
+
+    // Pings and reads aren't forwarded
+    let offset = replication_field(&mut master_client, "master_repl_offset").await;
+    assert_eq!(master_client.command(&["PING"]).await, "+PONG\r\n");
+    assert_eq!(master_client.command(&["XLEN", "stream"]).await, ":2\r\n");
+    assert_eq!(replication_field(&mut master_client, "master_repl_offset").await, offset);
+}
+
+#[tokio::test]
+async fn test_refused_writes_are_not_forwarded() {
+    let master = common::server().await;
+    let replica = replica_of(&master).await;
+    let mut admin = master.client().await;
+    let mut replica_client = replica.client().await;
+
+    assert_eq!(admin.command(&["SET", "before", "1"]).await, "+OK\r\n");
+    poll_until(&mut replica_client, &["GET", "before"], "$1\r\n1\r\n").await;
+    let offset = replication_field(&mut admin, "master_repl_offset").await;
+
+    let mut subscriber = master.client().await;
+    assert_eq!(subscriber.command(&["SUBSCRIBE", "news"]).await, "*3\r\n$9\r\nsubscribe\r\n$4\r\nnews\r\n:1\r\n");
+    assert!(subscriber.command(&["SET", "refused", "1"]).await.starts_with("-ERR Can't execute 'set'"));
+    assert_eq!(admin.command(&["CONFIG", "SET", "requirepass", "secret"]).await, "+OK\r\n");
+    let mut anonymous = master.client().await;
+    assert_eq!(anonymous.command(&["SET", "refused", "2"]).await, "-NOAUTH Authentication required\r\n");
+    assert_eq!(admin.command(&["AUTH", "secret"]).await, "+OK\r\n");
+    assert_eq!(replication_field(&mut admin, "master_repl_offset").await, offset);
+
+    // Once authenticated the same write goes through and reaches the replica
+    assert_eq!(anonymous.command(&["AUTH", "secret"]).await, "+OK\r\n");
+    assert_eq!(anonymous.command(&["SET", "after", "1"]).await, "+OK\r\n");
+    poll_until(&mut replica_client, &["GET", "after"], "$1\r\n1\r\n").await;
+    assert_eq!(replica_client.command(&["EXISTS", "refused"]).await, ":0\r\n");
+}
+
+#[tokio::test]
+async fn test_transactions_reach_the_replica_as_one_block() {
+    let master = common::server().await;
+    let replica = replica_of(&master).await;
+    let mut master_client = master.client().await;
+    let mut replica_client = replica.client().await;
+
+    assert_eq!(master_client.command(&["SET", "counter", "1"]).await, "+OK\r\n");
+    poll_until(&mut replica_client, &["GET", "counter"], "$1\r\n1\r\n").await;
+    let offset = replication_field(&mut master_client, "master_repl_offset").await;
+
+    // Nothing is sent while commands are queued, nor for a discarded transaction
+    assert_eq!(master_client.command(&["MULTI"]).await, "+OK\r\n");
+    assert_eq!(master_client.command(&["SET", "discarded", "1"]).await, "+QUEUED\r\n");
+    assert_eq!(replication_field(&mut replica_client, "slave_repl_offset").await, offset);
+    assert_eq!(master_client.command(&["DISCARD"]).await, "+OK\r\n");
+    assert_eq!(replication_field(&mut master_client, "master_repl_offset").await, offset);
+
+    assert_eq!(master_client.command(&["MULTI"]).await, "+OK\r\n");
+    for command in [&["INCR", "counter"][..], &["GET", "counter"], &["SELECT", "1"], &["SET", "other", "2"]] {
+        assert_eq!(master_client.command(command).await, "+QUEUED\r\n");
+    }
+    assert_eq!(master_client.command(&["EXEC"]).await, "*4\r\n:2\r\n$1\r\n2\r\n+OK\r\n+OK\r\n");
+
+    // The writes go out wrapped in MULTI and EXEC, with the read left out
+    let block = [
+        encode(&[b"MULTI"]),
+        encode(&[b"INCR", b"counter"]),
+        encode(&[b"SELECT", b"1"]),
+        encode(&[b"SET", b"other", b"2"]),
+        encode(&[b"EXEC"]),
+    ]
+    .concat();
+    assert_eq!(master_client.command(&["SELECT", "0"]).await, "+OK\r\n");
+    assert_eq!(replication_field(&mut master_client, "master_repl_offset").await, offset + block.len());
+    poll_until(&mut replica_client, &["GET", "counter"], "$1\r\n2\r\n").await;
+    assert_eq!(replica_client.command(&["EXISTS", "discarded"]).await, ":0\r\n");
+    assert_eq!(replica_client.command(&["SELECT", "1"]).await, "+OK\r\n");
+    assert_eq!(replica_client.command(&["GET", "other"]).await, "$1\r\n2\r\n");
+}
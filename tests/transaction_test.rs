@@ -0,0 +1,186 @@
+mod common;
+
+#[tokio::test]
+async fn test_exec_replies_with_each_command_reply_unwrapped() {
+    let server = common::server().await;
+    let mut client = server.client().await;
+
+    assert_eq!(client.command(&["MULTI"]).await, "+OK\r\n");
+    for command in [&["SET", "key", "10"][..], &["GET", "key"], &["INCR", "key"], &["GET", "missing"], &["INCR", "text"]] {
+        assert_eq!(client.command(command).await, "+QUEUED\r\n");
+    }
+    assert_eq!(client.command(&["SET", "text", "abc"]).await, "+QUEUED\r\n");
+    assert_eq!(client.command(&["INCR", "text"]).await, "+QUEUED\r\n");
+    // An error at run time fails only its own command
+    assert_eq!(
+        client.command(&["EXEC"]).await,
+        "*7\r\n+OK\r\n$2\r\n10\r\n:11\r\n$-1\r\n:1\r\n+OK\r\n-ERR value is not an integer or out of range\r\n"
+    );
+    assert_eq!(client.command(&["GET", "key"]).await, "$2\r\n11\r\n");
+}
+
+#[tokio::test]
+async fn test_exec_of_an_empty_transaction() {
+    let server = common::server().await;
+    let mut client = server.client().await;
+
+    assert_eq!(client.command(&["MULTI"]).await, "+OK\r\n");
+    assert_eq!(client.command(&["EXEC"]).await, "*0\r\n");
+    assert_eq!(client.command(&["EXEC"]).await, "-ERR EXEC without MULTI\r\n");
+}
+
+#[tokio::test]
+async fn test_queueing_errors_abort_the_whole_transaction() {
+    let server = common::server().await;
+    let mut client = server.client().await;
+
+    assert_eq!(client.command(&["MULTI"]).await, "+OK\r\n");
+    assert_eq!(client.command(&["SET", "key", "1"]).await, "+QUEUED\r\n");
+    assert_eq!(client.command(&["NOSUCHCOMMAND", "arg"]).await, "-ERR unknown command\r\n");
+    assert_eq!(client.command(&["GET"]).await, "-ERR wrong number of arguments for 'get' command\r\n");
+    assert_eq!(client.command(&["EXEC"]).await, "-EXECABORT Transaction discarded because of previous errors.\r\n");
+    // Nothing queued ran
+    assert_eq!(client.command(&["EXISTS", "key"]).await, ":0\r\n");
+}
+
+#[tokio::test]
+async fn test_discard_drops_the_queued_commands() {
+    let server = common::server().await;
+    let mut client = server.client().await;
+
+    assert_eq!(client.command(&["MULTI"]).await, "+OK\r\n");
+    assert_eq!(client.command(&["SET", "key", "discarded"]).await, "+QUEUED\r\n");
+    assert_eq!(client.command(&["DISCARD"]).await, "+OK\r\n");
+    assert_eq!(client.command(&["GET", "key"]).await, "$-1\r\n");
+
+    // A later transaction runs only its own commands
+    assert_eq!(client.command(&["MULTI"]).await, "+OK\r\n");
+    assert_eq!(client.command(&["INCR", "counter"]).await, "+QUEUED\r\n");
+    assert_eq!(client.command(&["EXEC"]).await, "*1\r\n:1\r\n");
+    assert_eq!(client.command(&["GET", "key"]).await, "$-1\r\n");
+    assert_eq!(client.command(&["DISCARD"]).await, "-ERR DISCARD without MULTI\r\n");
+}
+
+#[tokio::test]
+async fn test_nested_multi_keeps_the_queue() {
+    let server = common::server().await;
+    let mut client = server.client().await;
+
+    assert_eq!(client.command(&["MULTI"]).await, "+OK\r\n");
+    assert_eq!(client.command(&["SET", "key", "1"]).await, "+QUEUED\r\n");
+    assert_eq!(client.command(&["MULTI"]).await, "-ERR MULTI already started\r\n");
+    assert_eq!(client.command(&["INCR", "key"]).await, "+QUEUED\r\n");
+    assert_eq!(client.command(&["EXEC"]).await, "*2\r\n+OK\r\n:2\r\n");
+}
+
+#[tokio::test]
+async fn test_subscribe_queued_in_a_transaction_runs_on_exec() {
+    let server = common::server().await;
+    let mut subscriber = server.client().await;
+    let mut publisher = server.client().await;
+
+    assert_eq!(subscriber.command(&["MULTI"]).await, "+OK\r\n");
+    assert_eq!(subscriber.command(&["SUBSCRIBE", "news"]).await, "+QUEUED\r\n");
+    assert_eq!(subscriber.command(&["PSUBSCRIBE", "n*"]).await, "+QUEUED\r\n");
+    assert_eq!(
+        subscriber.command(&["EXEC"]).await,
+        "*2\r\n*3\r\n$9\r\nsubscribe\r\n$4\r\nnews\r\n:1\r\n*3\r\n$10\r\npsubscribe\r\n$2\r\nn*\r\n:2\r\n"
+    );
+    assert_eq!(publisher.command(&["PUBLISH", "news", "hello"]).await, ":2\r\n");
+    assert_eq!(subscriber.read_reply().await, "*3\r\n$7\r\nmessage\r\n$4\r\nnews\r\n$5\r\nhello\r\n");
+}
+
+#[tokio::test]
+async fn test_a_write_to_a_watched_key_aborts_exec() {
+    let server = common::server().await;
+    let mut client = server.client().await;
+    let mut other = server.client().await;
+
+    assert_eq!(client.command(&["WATCH", "watched"]).await, "+OK\r\n");
+    assert_eq!(other.command(&["SET", "watched", "theirs"]).await, "+OK\r\n");
+    assert_eq!(client.command(&["MULTI"]).await, "+OK\r\n");
+    assert_eq!(client.command(&["SET", "watched", "mine"]).await, "+QUEUED\r\n");
+    assert_eq!(client.command(&["EXEC"]).await, "*-1\r\n");
+    assert_eq!(client.command(&["GET", "watched"]).await, "$6\r\ntheirs\r\n");
+
+    // EXEC forgets the watched keys, so the next transaction runs
+    assert_eq!(other.command(&["SET", "watched", "again"]).await, "+OK\r\n");
+    assert_eq!(client.command(&["MULTI"]).await, "+OK\r\n");
+    assert_eq!(client.command(&["SET", "watched", "mine"]).await, "+QUEUED\r\n");
+    assert_eq!(client.command(&["EXEC"]).await, "*1\r\n+OK\r\n");
+}
+
+#[tokio::test]
+async fn test_writes_that_change_nothing_watched_leave_exec_alone() {
+    let server = common::server_with(&[("maxmemory", "1kb"), ("maxmemory-policy", "noeviction")]).await;
+    let mut client = server.client().await;
+    let mut other = server.client().await;
+
+    assert_eq!(other.command(&["SET", "text", "abc"]).await, "+OK\r\n");
+    assert_eq!(client.command(&["WATCH", "text", "missing"]).await, "+OK\r\n");
+    // A write to another key, one that fails and one refused for memory
+    assert_eq!(other.command(&["SET", "unrelated", "1"]).await, "+OK\r\n");
+    assert_eq!(other.command(&["INCR", "text"]).await, "-ERR value is not an integer or out of range\r\n");
+    assert_eq!(other.command(&["SET", "filler", &"x".repeat(2048)]).await, "+OK\r\n");
+    assert_eq!(other.command(&["SET", "missing", "1"]).await, "-OOM command not allowed when used memory > 'maxmemory'.\r\n");
+
+    assert_eq!(client.command(&["MULTI"]).await, "+OK\r\n");
+    assert_eq!(client.command(&["GET", "text"]).await, "+QUEUED\r\n");
+    assert_eq!(client.command(&["EXEC"]).await, "*1\r\n$3\r\nabc\r\n");
+}
+
+#[tokio::test]
+async fn test_unwatch_forgets_the_watched_keys() {
+    let server = common::server().await;
+    let mut client = server.client().await;
+    let mut other = server.client().await;
+
+    assert_eq!(client.command(&["WATCH", "watched"]).await, "+OK\r\n");
+    assert_eq!(client.command(&["UNWATCH"]).await, "+OK\r\n");
+    assert_eq!(other.command(&["SET", "watched", "theirs"]).await, "+OK\r\n");
+    assert_eq!(client.command(&["MULTI"]).await, "+OK\r\n");
+    assert_eq!(client.command(&["SET", "watched", "mine"]).await, "+QUEUED\r\n");
+    assert_eq!(client.command(&["EXEC"]).await, "*1\r\n+OK\r\n");
+    assert_eq!(client.command(&["GET", "watched"]).await, "$4\r\nmine\r\n");
+}
+
+#[tokio::test]
+async fn test_blocking_commands_dont_wait_inside_exec() {
+    let server = common::server().await;
+    let mut client = server.client().await;
+
+    assert_eq!(client.command(&["MULTI"]).await, "+OK\r\n");
+    assert_eq!(client.command(&["XREAD", "BLOCK", "0", "STREAMS", "stream", "$"]).await, "+QUEUED\r\n");
+    assert_eq!(client.command(&["WAIT", "1", "0"]).await, "+QUEUED\r\n");
+    client.send_raw(&common::encode(&[b"EXEC"])).await;
+    let reply = client.reply_within(std::time::Duration::from_secs(2)).await;
+    assert_eq!(reply.as_deref(), Some("*2\r\n$-1\r\n:0\r\n"));
+    // Other clients can still write once it's done
+    assert_eq!(server.client().await.command(&["XADD", "stream", "1-1", "f", "v"]).await, "$3\r\n1-1\r\n");
+}
+
+#[tokio::test]
+async fn test_watch_makes_concurrent_read_modify_writes_safe() {
+    let server = common::server().await;
+    let mut tasks = Vec::new();
+    for _ in 0..4 {
+        let mut client = server.client().await;
+        tasks.push(tokio::spawn(async move {
+            let mut done = 0;
+            while done < 25 {
+                assert_eq!(client.command(&["WATCH", "counter"]).await, "+OK\r\n");
+                let reply = client.command(&["GET", "counter"]).await;
+                let value: u64 = reply.lines().nth(1).map_or(0, |value| value.parse().unwrap());
+                assert_eq!(client.command(&["MULTI"]).await, "+OK\r\n");
+                assert_eq!(client.command(&["SET", "counter", &(value + 1).to_string()]).await, "+QUEUED\r\n");
+                if client.command(&["EXEC"]).await != "*-1\r\n" {
+                    done += 1;
+                }
+            }
+        }));
+    }
+    for task in tasks {
+        task.await.unwrap();
+    }
+    assert_eq!(server.client().await.command(&["GET", "counter"]).await, "$3\r\n100\r\n");
+}